@@ -14,26 +14,31 @@
 
 //! Ballista Rust executor binary.
 
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use arrow_flight::flight_service_server::FlightServiceServer;
-use ballista::serde::protobuf::{
-    self, scheduler_grpc_client::SchedulerGrpcClient, RegisterExecutorParams,
-};
+use ballista::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
 use ballista::{
+    compression::ShuffleCompression,
+    executor::execution_loop::poll_loop,
     executor::flight_service::BallistaFlightService,
+    executor::janitor::janitor_loop,
     executor::{BallistaExecutor, ExecutorConfig},
     print_version,
-    scheduler::{state::StandaloneClient, SchedulerServer},
+    scheduler::{
+        state::{StandaloneClient, DEFAULT_SWEEP_INTERVAL},
+        SchedulerServer, DEFAULT_MAX_TASK_ATTEMPTS, DEFAULT_SPLIT_SIZE,
+    },
     serde::protobuf::scheduler_grpc_server::SchedulerGrpcServer,
     serde::scheduler::ExecutorMeta,
     BALLISTA_VERSION,
 };
 use futures::future::MaybeDone;
-use log::{info, warn};
+use log::info;
 use tempfile::TempDir;
-use tonic::transport::{Channel, Server};
+use tonic::transport::Server;
 use uuid::Uuid;
 
 #[macro_use]
@@ -47,25 +52,6 @@ mod config {
 }
 use config::prelude::*;
 
-async fn registration_loop(
-    mut scheduler: SchedulerGrpcClient<Channel>,
-    executor_meta: ExecutorMeta,
-) {
-    let executor_meta: protobuf::ExecutorMetadata = executor_meta.into();
-    loop {
-        info!("Starting registration with scheduler");
-        let registration_result = scheduler
-            .register_executor(RegisterExecutorParams {
-                metadata: Some(executor_meta.clone()),
-            })
-            .await;
-        if let Err(error) = registration_result {
-            warn!("Executor registration failed. If this continues to happen the executor might be marked as dead by the scheduler. Error: {}", error);
-        }
-        tokio::time::sleep(Duration::from_secs(15)).await;
-    }
-}
-
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
@@ -78,6 +64,7 @@ async fn main() -> Result<()> {
         print_version();
         std::process::exit(0);
     }
+    ballista::udf::register_all();
 
     let namespace = opt.namespace;
     let external_host = opt.external_host;
@@ -104,20 +91,42 @@ async fn main() -> Result<()> {
             .into_string()
             .unwrap(),
     );
-    let config = ExecutorConfig::new(&external_host, port, &work_dir, opt.concurrent_tasks);
+    let shuffle_compression = opt
+        .shuffle_compression
+        .parse::<ShuffleCompression>()
+        .context("Could not parse shuffle compression")?;
+    if opt.concurrent_tasks == 0 {
+        return Err(anyhow::format_err!("concurrent_tasks must be at least 1"));
+    }
+    let mut config = ExecutorConfig::new(&external_host, port, &work_dir, opt.concurrent_tasks)
+        .with_shuffle_compression(shuffle_compression)
+        .with_read_ahead_batches(opt.read_ahead_batches);
+    if opt.job_data_ttl > 0 {
+        config = config.with_job_data_ttl(Duration::from_secs(opt.job_data_ttl));
+    }
+    if opt.work_dir_quota > 0 {
+        config = config.with_work_dir_quota(opt.work_dir_quota);
+    }
     info!("Running with config: {:?}", config);
 
     let executor_meta = ExecutorMeta {
         id: Uuid::new_v4().to_string(), // assign this executor a unique ID
         host: external_host,
         port,
+        task_slots: opt.concurrent_tasks,
     };
 
     if opt.local {
         info!("Running in local mode. Scheduler will be run in-proc");
         let client = StandaloneClient::try_new_temporary()
             .context("Could not create standalone config backend")?;
-        let server = SchedulerGrpcServer::new(SchedulerServer::new(client, namespace));
+        client.spawn_sweeper(DEFAULT_SWEEP_INTERVAL);
+        let server = SchedulerGrpcServer::new(SchedulerServer::new(
+            client,
+            namespace,
+            DEFAULT_MAX_TASK_ATTEMPTS,
+            DEFAULT_SPLIT_SIZE,
+        ));
         let addr = format!("{}:{}", bind_host, scheduler_port);
         let addr = addr
             .parse()
@@ -155,8 +164,8 @@ async fn main() -> Result<()> {
     let scheduler = SchedulerGrpcClient::connect(scheduler_url)
         .await
         .context("Could not connect to scheduler")?;
-    let executor = Arc::new(BallistaExecutor::new(config, scheduler.clone()));
-    let service = BallistaFlightService::new(executor);
+    let executor = Arc::new(BallistaExecutor::new(config, scheduler.clone()).await);
+    let service = BallistaFlightService::new(executor.clone());
 
     let server = FlightServiceServer::new(service);
     info!(
@@ -164,7 +173,11 @@ async fn main() -> Result<()> {
         BALLISTA_VERSION, addr
     );
     let server_future = tokio::spawn(Server::builder().add_service(server).serve(addr));
-    tokio::spawn(registration_loop(scheduler, executor_meta));
+    tokio::spawn(janitor_loop(
+        executor.clone(),
+        Duration::from_secs(opt.janitor_interval),
+    ));
+    tokio::spawn(poll_loop(scheduler, executor, executor_meta));
 
     server_future
         .await
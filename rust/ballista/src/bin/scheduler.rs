@@ -19,7 +19,7 @@ use ballista::BALLISTA_VERSION;
 use ballista::{
     print_version,
     scheduler::{
-        state::{ConfigBackendClient, EtcdClient, StandaloneClient},
+        state::{ConfigBackendClient, EtcdClient, StandaloneClient, DEFAULT_SWEEP_INTERVAL},
         ConfigBackend, SchedulerServer,
     },
     serde::protobuf::scheduler_grpc_server::SchedulerGrpcServer,
@@ -45,13 +45,20 @@ use config::prelude::*;
 async fn start_server<T: ConfigBackendClient + Send + Sync + 'static>(
     config_backend: T,
     namespace: String,
+    max_task_attempts: usize,
+    split_size: u64,
     addr: SocketAddr,
 ) -> Result<()> {
     info!(
         "Ballista v{} Scheduler listening on {:?}",
         BALLISTA_VERSION, addr
     );
-    let server = SchedulerGrpcServer::new(SchedulerServer::new(config_backend, namespace));
+    let server = SchedulerGrpcServer::new(SchedulerServer::new(
+        config_backend,
+        namespace,
+        max_task_attempts,
+        split_size,
+    ));
     Ok(Server::builder()
         .add_service(server)
         .serve(addr)
@@ -71,11 +78,14 @@ async fn main() -> Result<()> {
         print_version();
         std::process::exit(0);
     }
+    ballista::udf::register_all();
     println!("{}", opt.namespace);
 
     let namespace = opt.namespace;
     let bind_host = opt.bind_host;
     let port = opt.port;
+    let max_task_attempts = opt.max_task_attempts;
+    let split_size = opt.split_size;
 
     let addr = format!("{}:{}", bind_host, port);
     let addr = addr.parse()?;
@@ -86,13 +96,14 @@ async fn main() -> Result<()> {
                 .await
                 .context("Could not connect to etcd")?;
             let client = EtcdClient::new(etcd);
-            start_server(client, namespace, addr).await?;
+            start_server(client, namespace, max_task_attempts, split_size, addr).await?;
         }
         ConfigBackend::Standalone => {
             // TODO: Use a real file and make path is configurable
             let client = StandaloneClient::try_new_temporary()
                 .context("Could not create standalone config backend")?;
-            start_server(client, namespace, addr).await?;
+            client.spawn_sweeper(DEFAULT_SWEEP_INTERVAL);
+            start_server(client, namespace, max_task_attempts, split_size, addr).await?;
         }
     };
     Ok(())
@@ -15,26 +15,41 @@
 //! Client API for sending requests to executors.
 
 use std::convert::{TryFrom, TryInto};
+use std::io;
 use std::sync::Arc;
 use std::{collections::HashMap, pin::Pin};
 
+use crate::compression::{
+    decompress_stream, ChunkReader, ShuffleCompression, ACCEPT_COMPRESSION_KEY, COMPRESSION_KEY,
+    NEXT_PARTITION_MARKER,
+};
 use crate::error::{ballista_error, BallistaError, Result};
-use crate::memory_stream::MemoryStream;
+use crate::receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE};
 use crate::serde::protobuf::{self};
 use crate::serde::scheduler::{Action, ExecutePartition, ExecutePartitionResult, PartitionId};
 
-use crate::utils::PartitionStats;
+use crate::utils::{check_upload_schema, PartitionStats};
 use arrow::array::{StringArray, StructArray};
 use arrow::datatypes::Schema;
+use arrow::error::ArrowError;
+use arrow::ipc::writer::IpcWriteOptions;
 use arrow::record_batch::RecordBatch;
+use arrow_flight::flight_descriptor::DescriptorType;
 use arrow_flight::flight_service_client::FlightServiceClient;
-use arrow_flight::utils::flight_data_to_arrow_batch;
-use arrow_flight::Ticket;
+use arrow_flight::utils::{
+    flight_data_from_arrow_batch, flight_data_from_arrow_schema, flight_data_to_arrow_batch,
+};
+use arrow_flight::{FlightData, FlightDescriptor, Ticket};
 use datafusion::physical_plan::common::collect;
 use datafusion::physical_plan::{ExecutionPlan, SendableRecordBatchStream};
 use datafusion::{logical_plan::LogicalPlan, physical_plan::RecordBatchStream};
+use futures::{Stream, StreamExt};
 use log::debug;
 use prost::Message;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+use tonic::metadata::MetadataValue;
+use tonic::Status;
 use uuid::Uuid;
 
 /// Client for interacting with Ballista executors.
@@ -109,26 +124,161 @@ impl BallistaClient {
             .collect::<Result<Vec<_>>>()
     }
 
-    /// Fetch a partition from an executor
+    /// Fetch a partition from an executor. The batches are streamed from the executor as
+    /// they are consumed rather than being loaded into memory up front.
     pub async fn fetch_partition(
         &mut self,
         job_uuid: &Uuid,
         stage_id: usize,
         partition_id: usize,
-    ) -> Result<Vec<RecordBatch>> {
+    ) -> Result<SendableRecordBatchStream> {
         let action = Action::FetchPartition(PartitionId::new(
             job_uuid.to_owned(),
             stage_id,
             partition_id,
         ));
-        let stream = self.execute_action(&action).await?;
-        Ok(collect(stream)
+        self.do_get(&action, true).await
+    }
+
+    /// Fetch one output partition of a hash-partitioned shuffle partition from an executor
+    pub async fn fetch_shuffle_partition(
+        &mut self,
+        job_uuid: &Uuid,
+        stage_id: usize,
+        partition_id: usize,
+        output_partition: usize,
+    ) -> Result<SendableRecordBatchStream> {
+        let action = Action::FetchShufflePartition(
+            PartitionId::new(job_uuid.to_owned(), stage_id, partition_id),
+            output_partition,
+        );
+        self.do_get(&action, true).await
+    }
+
+    /// Upload record batches to the executor, which holds them as a partition that can be
+    /// fetched like the output of a query stage. Returns the statistics of the partition.
+    pub async fn put_partition(
+        &mut self,
+        partition_id: &PartitionId,
+        schema: &Schema,
+        batches: Vec<RecordBatch>,
+    ) -> Result<PartitionStats> {
+        check_upload_schema(schema)?;
+        let options = IpcWriteOptions::default();
+        // the first message describes the partition and carries the schema of the batches
+        let mut schema_flight_data = flight_data_from_arrow_schema(schema, &options);
+        schema_flight_data.flight_descriptor = Some(FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            cmd: vec![],
+            path: vec![
+                partition_id.job_uuid.to_string(),
+                partition_id.stage_id.to_string(),
+                partition_id.partition_id.to_string(),
+            ],
+        });
+        // the batches are encoded as they are sent, and have no dictionaries
+        let flights = futures::stream::once(futures::future::ready(schema_flight_data)).chain(
+            futures::stream::iter(batches)
+                .map(move |batch| flight_data_from_arrow_batch(&batch, &options).1),
+        );
+
+        let mut results = self
+            .flight_client
+            .do_put(flights)
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+            .into_inner();
+        let result = results
+            .message()
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+            .ok_or_else(|| {
+                BallistaError::General("do_put did not return the partition statistics".to_owned())
+            })?;
+        let stats = protobuf::PartitionStats::decode(result.app_metadata.as_slice())
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        Ok(stats.into())
+    }
+
+    /// Fetch several partitions from an executor as one stream. For hash-partitioned partitions,
+    /// the given output partition of each partition is fetched.
+    pub async fn fetch_partitions(
+        &mut self,
+        partition_ids: Vec<PartitionId>,
+        output_partition: Option<usize>,
+    ) -> Result<SendableRecordBatchStream> {
+        let action = Action::FetchPartitions(partition_ids, output_partition);
+        self.do_get(&action, true).await
+    }
+
+    /// Ask the executor to abort the tasks of a cancelled job and remove its work files
+    pub async fn cancel_job(&mut self, job_uuid: &Uuid) -> Result<()> {
+        self.do_action(Action::CancelJob(*job_uuid), "cancel_job")
             .await
-            .map_err(|e| BallistaError::General(format!("{:?}", e)))?)
+    }
+
+    /// Ask the executor to remove the work files of a finished job, except for the output of
+    /// the given stages
+    pub async fn remove_job_data(
+        &mut self,
+        job_uuid: &Uuid,
+        keep_stages: Vec<usize>,
+    ) -> Result<()> {
+        self.do_action(
+            Action::RemoveJobData(*job_uuid, keep_stages),
+            "remove_job_data",
+        )
+        .await
+    }
+
+    /// Ask the executor to remove the partitions of a table that was uploaded with
+    /// [`BallistaClient::put_partition`]
+    pub async fn remove_uploaded_table(&mut self, table_uuid: &Uuid) -> Result<()> {
+        self.do_action(
+            Action::RemoveUploadedTable(*table_uuid),
+            "remove_uploaded_table",
+        )
+        .await
+    }
+
+    /// Send an action that does not return results to the executor
+    async fn do_action(&mut self, action: Action, action_type: &str) -> Result<()> {
+        let action: protobuf::Action = action.try_into()?;
+        let mut body: Vec<u8> = Vec::with_capacity(action.encoded_len());
+        action
+            .encode(&mut body)
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+
+        let mut stream = self
+            .flight_client
+            .do_action(tonic::Request::new(arrow_flight::Action {
+                r#type: action_type.to_owned(),
+                body,
+            }))
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+            .into_inner();
+        while stream
+            .message()
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+            .is_some()
+        {}
+        Ok(())
     }
 
     /// Execute an action and retrieve the results
     pub async fn execute_action(&mut self, action: &Action) -> Result<SendableRecordBatchStream> {
+        self.do_get(action, false).await
+    }
+
+    /// Execute an action and retrieve the results. Compressed shuffle files are sent as is when
+    /// `accept_compression` is set, and decompressed by the client.
+    async fn do_get(
+        &mut self,
+        action: &Action,
+        accept_compression: bool,
+    ) -> Result<SendableRecordBatchStream> {
         let serialized_action: protobuf::Action = action.to_owned().try_into()?;
 
         let mut buf: Vec<u8> = Vec::with_capacity(serialized_action.encoded_len());
@@ -137,14 +287,33 @@ impl BallistaClient {
             .encode(&mut buf)
             .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
 
-        let request = tonic::Request::new(Ticket { ticket: buf });
+        let mut request = tonic::Request::new(Ticket { ticket: buf });
+        if accept_compression {
+            request.metadata_mut().insert(
+                ACCEPT_COMPRESSION_KEY,
+                MetadataValue::from_static(ShuffleCompression::SUPPORTED),
+            );
+        }
 
-        let mut stream = self
+        let response = self
             .flight_client
             .do_get(request)
             .await
-            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-            .into_inner();
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        let compression = response
+            .metadata()
+            .get(COMPRESSION_KEY)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+                    .parse::<ShuffleCompression>()
+            })
+            .transpose()?;
+        let mut stream = response.into_inner();
+        if let Some(compression) = compression {
+            return decompress_partition(stream, compression).await;
+        }
 
         // the schema should be the first message returned, else client should error
         match stream
@@ -156,26 +325,185 @@ impl BallistaClient {
                 // convert FlightData to a stream
                 let schema = Arc::new(Schema::try_from(&flight_data)?);
 
-                // all the remaining stream messages should be dictionary and record batches
+                // all the remaining stream messages should be dictionary and record batches,
+                // which are decoded as they are read by the consumer of the returned stream
+                let (sender, result) =
+                    RecordBatchReceiverStream::create(schema.clone(), DEFAULT_BUFFER_SIZE);
+                tokio::spawn(async move {
+                    loop {
+                        let batch = match stream.message().await {
+                            Ok(Some(flight_data)) => {
+                                flight_data_to_arrow_batch(&flight_data, schema.clone(), &[])
+                            }
+                            Ok(None) => break,
+                            Err(e) => Err(ArrowError::ExternalError(Box::new(e))),
+                        };
+                        let failed = batch.is_err();
+                        // sending fails when the consumer dropped the stream
+                        if sender.send(batch).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                });
 
-                //TODO we should stream the data rather than load into memory first
-                let mut batches = vec![];
+                Ok(Box::pin(result))
+            }
+            None => Err(ballista_error(
+                "Did not receive schema batch from flight server",
+            )),
+        }
+    }
+}
 
-                while let Some(flight_data) = stream
-                    .message()
-                    .await
-                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-                {
-                    let batch = flight_data_to_arrow_batch(&flight_data, schema.clone(), &[])?;
+/// Decode the chunks of compressed shuffle files as they are read by the consumer of the
+/// returned stream. The files of several partitions are separated by a message whose
+/// application metadata is [`NEXT_PARTITION_MARKER`]. The decoder blocks on the chunks as they
+/// arrive, so the returned stream holds a blocking thread until it is read to the end or dropped.
+async fn decompress_partition<S>(
+    mut stream: S,
+    compression: ShuffleCompression,
+) -> Result<SendableRecordBatchStream>
+where
+    S: Stream<Item = std::result::Result<FlightData, Status>> + Send + Unpin + 'static,
+{
+    // the chunks of each file are sent through their own channel, and the channels are sent to
+    // the decoder in the order of the files
+    let (file_sender, mut files) = mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut chunk_sender = None;
+        loop {
+            let chunk = match stream.next().await {
+                Some(Ok(flight_data)) if flight_data.app_metadata == NEXT_PARTITION_MARKER => {
+                    chunk_sender = None;
+                    continue;
+                }
+                Some(Ok(flight_data)) => Ok(flight_data.data_body),
+                None => break,
+                Some(Err(e)) => Err(io::Error::new(io::ErrorKind::Other, e)),
+            };
+            let sender = match chunk_sender.take() {
+                Some(sender) => sender,
+                None => {
+                    let (sender, chunks) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+                    // sending fails when the decoder stopped reading
+                    if file_sender.send(chunks).await.is_err() {
+                        break;
+                    }
+                    sender
+                }
+            };
+            let failed = chunk.is_err();
+            if sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+            chunk_sender = Some(sender);
+        }
+    });
 
-                    batches.push(batch);
+    // the decoders are blocking readers, and the schema is only known once the decoder has
+    // read the start of the first IPC stream
+    let (result_sender, result_receiver) = oneshot::channel();
+    task::spawn_blocking(move || {
+        let mut result_sender = Some(result_sender);
+        let mut sender: Option<mpsc::Sender<arrow::error::Result<RecordBatch>>> = None;
+        while let Some(chunks) = files.blocking_recv() {
+            let reader = match decompress_stream(compression, ChunkReader::new(chunks)) {
+                Ok(reader) => reader,
+                Err(e) => {
+                    if let Some(result_sender) = result_sender.take() {
+                        let _ = result_sender.send(Err(e));
+                    } else if let Some(sender) = &sender {
+                        let _ = sender.blocking_send(Err(ArrowError::ExternalError(Box::new(e))));
+                    }
+                    return;
+                }
+            };
+            if let Some(result_sender) = result_sender.take() {
+                let (batch_sender, result) =
+                    RecordBatchReceiverStream::create(reader.schema(), DEFAULT_BUFFER_SIZE);
+                if result_sender.send(Ok(result)).is_err() {
+                    return;
                 }
+                sender = Some(batch_sender);
+            }
+            let sender = match &sender {
+                Some(sender) => sender,
+                None => return,
+            };
+            for batch in reader {
+                let failed = batch.is_err();
+                // sending fails when the consumer dropped the stream
+                if sender.blocking_send(batch).is_err() || failed {
+                    return;
+                }
+            }
+        }
+    });
+
+    let result = result_receiver
+        .await
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))??;
+    Ok(Box::pin(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::{open_shuffle_file, ShuffleWriter};
+    use arrow::array::{Array, UInt32Array};
+    use arrow::datatypes::{DataType, Field};
+    use std::fs::File;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn decompress_two_partitions() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::UInt32, false)]));
+        let dir = tempfile::TempDir::new()?;
+        let mut messages = vec![];
+        for i in 0..2u32 {
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(UInt32Array::from(vec![i; 100]))],
+            )?;
+            let path = dir.path().join(format!("{}.arrow", i));
+            let mut writer =
+                ShuffleWriter::try_new(File::create(&path)?, &schema, ShuffleCompression::Lz4)?;
+            writer.write(&batch)?;
+            writer.finish()?;
 
-                Ok(Box::pin(MemoryStream::try_new(batches, schema, None)?))
+            let (_, mut file) = open_shuffle_file(path.to_str().unwrap())?;
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            if i > 0 {
+                messages.push(Ok(FlightData {
+                    app_metadata: NEXT_PARTITION_MARKER.to_vec(),
+                    ..Default::default()
+                }));
             }
-            None => Err(ballista_error(
-                "Did not receive schema batch from flight server",
-            )),
+            // split the file into several chunks like the executor does
+            let (first, second) = data.split_at(data.len() / 2);
+            for chunk in &[first, second] {
+                messages.push(Ok(FlightData {
+                    data_body: chunk.to_vec(),
+                    ..Default::default()
+                }));
+            }
+        }
+
+        let stream =
+            decompress_partition(futures::stream::iter(messages), ShuffleCompression::Lz4).await?;
+        assert_eq!(schema, stream.schema());
+        let batches = collect(stream).await?;
+        assert_eq!(2, batches.len());
+        for (i, batch) in batches.iter().enumerate() {
+            let ids = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .unwrap();
+            assert_eq!(100, ids.len());
+            assert_eq!(i as u32, ids.value(0));
         }
+        Ok(())
     }
 }
@@ -0,0 +1,367 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compression of shuffle files. Uncompressed shuffle files are Arrow IPC files, and compressed
+//! shuffle files start with a header naming the codec, followed by an Arrow IPC stream that is
+//! compressed with that codec.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use arrow::datatypes::Schema;
+use arrow::ipc::reader::{FileReader, StreamReader};
+use arrow::ipc::writer::{FileWriter, StreamWriter};
+use arrow::record_batch::{RecordBatch, RecordBatchReader};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+
+use crate::error::{BallistaError, Result};
+
+/// Header that compressed shuffle files start with, followed by one byte for the codec
+const COMPRESSED_MAGIC: &[u8] = b"BALLISTA_SHUFFLE";
+
+/// Name of the gRPC metadata key in which clients list the codecs that they can decode when
+/// fetching a partition
+pub const ACCEPT_COMPRESSION_KEY: &str = "ballista-accept-compression";
+
+/// Name of the gRPC metadata key in which executors name the codec of a partition that is sent
+/// compressed
+pub const COMPRESSION_KEY: &str = "ballista-compression";
+
+/// Application metadata of the message that separates the compressed partitions when several
+/// partitions are fetched in one request
+pub const NEXT_PARTITION_MARKER: &[u8] = b"ballista-next-partition";
+
+/// Codec used to compress shuffle files
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShuffleCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl ShuffleCompression {
+    /// Codecs that can be decoded by this version of Ballista
+    pub const SUPPORTED: &'static str = "lz4,zstd";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShuffleCompression::None => "none",
+            ShuffleCompression::Lz4 => "lz4",
+            ShuffleCompression::Zstd => "zstd",
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            ShuffleCompression::None => 0,
+            ShuffleCompression::Lz4 => 1,
+            ShuffleCompression::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(ShuffleCompression::None),
+            1 => Ok(ShuffleCompression::Lz4),
+            2 => Ok(ShuffleCompression::Zstd),
+            _ => Err(BallistaError::General(format!(
+                "Unknown shuffle compression codec {}",
+                id
+            ))),
+        }
+    }
+}
+
+impl Default for ShuffleCompression {
+    fn default() -> Self {
+        ShuffleCompression::None
+    }
+}
+
+impl fmt::Display for ShuffleCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ShuffleCompression {
+    type Err = BallistaError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(ShuffleCompression::None),
+            "lz4" => Ok(ShuffleCompression::Lz4),
+            "zstd" => Ok(ShuffleCompression::Zstd),
+            _ => Err(BallistaError::General(format!(
+                "Invalid shuffle compression {}, expected none, lz4 or zstd",
+                s
+            ))),
+        }
+    }
+}
+
+/// Counts the bytes written to a writer, to report the size of the uncompressed IPC stream
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum Writer {
+    Uncompressed(FileWriter<File>),
+    Lz4(StreamWriter<CountingWriter<lz4_flex::frame::FrameEncoder<File>>>),
+    Zstd(StreamWriter<CountingWriter<zstd::stream::write::Encoder<File>>>),
+}
+
+/// Writer of a shuffle file with the given compression
+pub struct ShuffleWriter {
+    writer: Writer,
+}
+
+impl ShuffleWriter {
+    pub fn try_new(
+        mut file: File,
+        schema: &Schema,
+        compression: ShuffleCompression,
+    ) -> Result<Self> {
+        if compression != ShuffleCompression::None {
+            file.write_all(COMPRESSED_MAGIC)?;
+            file.write_all(&[compression.id()])?;
+        }
+        let writer = match compression {
+            ShuffleCompression::None => Writer::Uncompressed(FileWriter::try_new(file, schema)?),
+            ShuffleCompression::Lz4 => {
+                let writer = CountingWriter {
+                    inner: lz4_flex::frame::FrameEncoder::new(file),
+                    count: 0,
+                };
+                Writer::Lz4(StreamWriter::try_new(writer, schema)?)
+            }
+            ShuffleCompression::Zstd => {
+                let writer = CountingWriter {
+                    inner: zstd::stream::write::Encoder::new(file, 0)?,
+                    count: 0,
+                };
+                Writer::Zstd(StreamWriter::try_new(writer, schema)?)
+            }
+        };
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match &mut self.writer {
+            Writer::Uncompressed(writer) => writer.write(batch)?,
+            Writer::Lz4(writer) => writer.write(batch)?,
+            Writer::Zstd(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    /// Finish writing the file. Returns the size of the uncompressed IPC data and the size of
+    /// the file.
+    pub fn finish(self) -> Result<(u64, u64)> {
+        match self.writer {
+            Writer::Uncompressed(mut writer) => {
+                writer.finish()?;
+                let file = writer.into_inner()?;
+                let len = file.metadata()?.len();
+                Ok((len, len))
+            }
+            Writer::Lz4(mut writer) => {
+                writer.finish()?;
+                let writer = writer.into_inner()?;
+                let file = writer.inner.finish().map_err(|e| {
+                    BallistaError::General(format!("Failed to finish LZ4 frame: {:?}", e))
+                })?;
+                Ok((writer.count, file.metadata()?.len()))
+            }
+            Writer::Zstd(mut writer) => {
+                writer.finish()?;
+                let writer = writer.into_inner()?;
+                let file = writer.inner.finish()?;
+                Ok((writer.count, file.metadata()?.len()))
+            }
+        }
+    }
+}
+
+/// Open a shuffle file and read the codec from its header. The returned file is positioned at
+/// the start of the Arrow IPC data.
+pub fn open_shuffle_file(path: &str) -> Result<(ShuffleCompression, File)> {
+    let mut file = File::open(path).map_err(|e| open_error(path, e))?;
+    let mut header = [0u8; 17];
+    let compression = header_compression(file.read_exact(&mut header), &header)?;
+    if compression == ShuffleCompression::None {
+        file.seek(SeekFrom::Start(0))?;
+    }
+    Ok((compression, file))
+}
+
+/// Open a shuffle file with Tokio, see [`open_shuffle_file`]
+pub async fn open_shuffle_file_async(path: &str) -> Result<(ShuffleCompression, fs::File)> {
+    let mut file = fs::File::open(path)
+        .await
+        .map_err(|e| open_error(path, e))?;
+    let mut header = [0u8; 17];
+    let read = file.read_exact(&mut header).await.map(|_| ());
+    let compression = header_compression(read, &header)?;
+    if compression == ShuffleCompression::None {
+        file.seek(SeekFrom::Start(0)).await?;
+    }
+    Ok((compression, file))
+}
+
+fn open_error(path: &str, e: io::Error) -> BallistaError {
+    BallistaError::General(format!(
+        "Failed to open partition file at {}: {:?}",
+        path, e
+    ))
+}
+
+/// Codec named by the header of a shuffle file, given the result of reading the header
+fn header_compression(read: io::Result<()>, header: &[u8; 17]) -> Result<ShuffleCompression> {
+    match read {
+        Ok(()) if header.starts_with(COMPRESSED_MAGIC) => ShuffleCompression::from_id(header[16]),
+        Ok(()) => Ok(ShuffleCompression::None),
+        // IPC files can be shorter than the header when they are not valid
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(ShuffleCompression::None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read the record batches of a shuffle file that was opened with [`open_shuffle_file`]
+pub fn read_shuffle_file(
+    compression: ShuffleCompression,
+    file: File,
+) -> Result<Box<dyn RecordBatchReader>> {
+    match compression {
+        ShuffleCompression::None => Ok(Box::new(FileReader::try_new(file)?)),
+        _ => decompress_stream(compression, file),
+    }
+}
+
+/// Read the record batches of a compressed Arrow IPC stream
+pub fn decompress_stream<R: Read + 'static>(
+    compression: ShuffleCompression,
+    reader: R,
+) -> Result<Box<dyn RecordBatchReader>> {
+    Ok(match compression {
+        ShuffleCompression::None => Box::new(StreamReader::try_new(reader)?),
+        ShuffleCompression::Lz4 => Box::new(StreamReader::try_new(
+            lz4_flex::frame::FrameDecoder::new(reader),
+        )?),
+        ShuffleCompression::Zstd => Box::new(StreamReader::try_new(
+            zstd::stream::read::Decoder::new(reader)?,
+        )?),
+    })
+}
+
+/// Blocking reader of the chunks of a compressed partition that are received by an async task.
+/// Reading waits for the next chunk with `blocking_recv`, so the reader must be used from a
+/// blocking thread, which it occupies while the partition is fetched.
+pub(crate) struct ChunkReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChunkReader {
+    pub(crate) fn new(chunks: mpsc::Receiver<io::Result<Vec<u8>>>) -> Self {
+        Self {
+            chunks,
+            chunk: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{StringArray, UInt32Array};
+    use arrow::datatypes::{DataType, Field};
+    use std::sync::Arc;
+
+    #[test]
+    fn roundtrip_compressed_files() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::UInt32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(UInt32Array::from((0..1000).collect::<Vec<u32>>())),
+                Arc::new(StringArray::from(vec!["repeated string value"; 1000])),
+            ],
+        )?;
+        let dir = tempfile::TempDir::new()?;
+        for compression in &[
+            ShuffleCompression::None,
+            ShuffleCompression::Lz4,
+            ShuffleCompression::Zstd,
+        ] {
+            let path = dir.path().join(format!("{}.arrow", compression));
+            let mut writer = ShuffleWriter::try_new(File::create(&path)?, &schema, *compression)?;
+            writer.write(&batch)?;
+            writer.write(&batch)?;
+            let (uncompressed_bytes, compressed_bytes) = writer.finish()?;
+            if *compression != ShuffleCompression::None {
+                assert!(compressed_bytes < uncompressed_bytes);
+            }
+
+            let (codec, file) = open_shuffle_file(path.to_str().unwrap())?;
+            assert_eq!(*compression, codec);
+            let reader = read_shuffle_file(codec, file)?;
+            assert_eq!(schema, reader.schema());
+            let batches = reader.collect::<arrow::error::Result<Vec<_>>>()?;
+            assert_eq!(2, batches.len());
+            assert_eq!(format!("{:?}", batch), format!("{:?}", batches[1]));
+        }
+        Ok(())
+    }
+}
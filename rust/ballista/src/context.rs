@@ -18,33 +18,53 @@ use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::{any::Any, pin::Pin};
 use std::{collections::HashMap, convert::TryInto};
-use std::{fs, time::Duration};
+use std::{fs, future::Future, time::Duration};
 
 use crate::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
 use crate::serde::protobuf::{
-    execute_query_params::Query, job_status, ExecuteQueryParams, ExecuteQueryResult,
-    GetJobStatusParams, GetJobStatusResult,
+    execute_query_params::Query, job_status, CancelJobParams, ExecuteQueryParams,
+    ExecuteQueryResult, FileType, GetExecutorMetadataParams, GetFileMetadataParams,
+    GetJobStatusParams, GetJobStatusResult, JobStatus, PartitionLocation,
 };
 use crate::serde::scheduler::{Action, ExecutorMeta};
 use crate::{client::BallistaClient, serde::scheduler};
 use crate::{
     error::{BallistaError, Result},
-    memory_stream::MemoryStream,
+    receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE},
 };
 
-use crate::scheduler::planner::DistributedPlanner;
+use crate::scheduler::execution_plans::{
+    JsonReadOptions, JsonTable, PartitionedTable, UploadedTable, DEFAULT_PARQUET_CONCURRENCY,
+};
+use crate::scheduler::file_metadata::merge_statistics;
+use crate::scheduler::planner::{self, DistributedPlanner};
+use crate::udf;
+use crate::utils;
 use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
 use datafusion::datasource::datasource::Statistics;
 use datafusion::datasource::TableProvider;
 use datafusion::error::Result as DFResult;
 use datafusion::execution::context::ExecutionContext;
 use datafusion::logical_plan::{DFSchema, Expr, LogicalPlan, Partitioning};
 use datafusion::physical_plan::csv::CsvReadOptions;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::udaf::AggregateUDF;
+use datafusion::physical_plan::udf::ScalarUDF;
 use datafusion::physical_plan::ExecutionPlan;
 use datafusion::{dataframe::DataFrame, physical_plan::RecordBatchStream};
-use log::{debug, error, info};
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use tokio::runtime::Runtime;
 use uuid::Uuid;
 
+/// Setting that controls where the paths of tables are resolved. With the default, `client`,
+/// the files must be accessible from the client machine, and with `scheduler` the scheduler
+/// resolves the paths and provides the schema of the tables.
+pub const TABLE_RESOLUTION: &str = "table.resolution";
+
 #[allow(dead_code)]
 
 pub struct BallistaContextState {
@@ -92,6 +112,11 @@ impl BallistaContext {
     /// Create a DataFrame representing a Parquet table scan
 
     pub fn read_parquet(&self, path: &str) -> Result<BallistaDataFrame> {
+        if self.resolve_on_scheduler()? {
+            let table = self.scheduler_parquet_table(path, vec![])?;
+            return self.read_table(Arc::new(table));
+        }
+
         // convert to absolute path because the executor likely has a different working directory
         let path = PathBuf::from(path);
         let path = fs::canonicalize(&path)?;
@@ -102,9 +127,40 @@ impl BallistaContext {
         Ok(BallistaDataFrame::from(self.state.clone(), df))
     }
 
+    /// Create a DataFrame representing a scan of Parquet files stored in Hive-style
+    /// `column=value` directories, such as `year=2021/month=03/`. The partition columns are
+    /// added to the schema as strings, and filters on them skip the directories that cannot
+    /// match.
+    pub fn read_parquet_partitioned(
+        &self,
+        path: &str,
+        partition_columns: &[&str],
+    ) -> Result<BallistaDataFrame> {
+        let partition_columns: Vec<String> =
+            partition_columns.iter().map(|c| c.to_string()).collect();
+        if self.resolve_on_scheduler()? {
+            let table = self.scheduler_parquet_table(path, partition_columns)?;
+            return self.read_table(Arc::new(table));
+        }
+
+        // convert to absolute path because the executor likely has a different working directory
+        let path = fs::canonicalize(&PathBuf::from(path))?;
+        let table = PartitionedTable::try_new_parquet(
+            path.to_str().unwrap(),
+            &partition_columns,
+            DEFAULT_PARQUET_CONCURRENCY,
+        )?;
+        self.read_table(Arc::new(table))
+    }
+
     /// Create a DataFrame representing a CSV table scan
 
     pub fn read_csv(&self, path: &str, options: CsvReadOptions) -> Result<BallistaDataFrame> {
+        if self.resolve_on_scheduler()? {
+            let table = self.scheduler_csv_table(path, &options, vec![])?;
+            return self.read_table(Arc::new(table));
+        }
+
         // convert to absolute path because the executor likely has a different working directory
         let path = PathBuf::from(path);
         let path = fs::canonicalize(&path)?;
@@ -115,6 +171,180 @@ impl BallistaContext {
         Ok(BallistaDataFrame::from(self.state.clone(), df))
     }
 
+    /// Create a DataFrame representing a scan of CSV files stored in Hive-style `column=value`
+    /// directories, see [`BallistaContext::read_parquet_partitioned`]
+    pub fn read_csv_partitioned(
+        &self,
+        path: &str,
+        options: CsvReadOptions,
+        partition_columns: &[&str],
+    ) -> Result<BallistaDataFrame> {
+        let partition_columns: Vec<String> =
+            partition_columns.iter().map(|c| c.to_string()).collect();
+        if self.resolve_on_scheduler()? {
+            let table = self.scheduler_csv_table(path, &options, partition_columns)?;
+            return self.read_table(Arc::new(table));
+        }
+
+        // convert to absolute path because the executor likely has a different working directory
+        let path = fs::canonicalize(&PathBuf::from(path))?;
+        let table =
+            PartitionedTable::try_new_csv(path.to_str().unwrap(), &partition_columns, options)?;
+        self.read_table(Arc::new(table))
+    }
+
+    /// Create a DataFrame representing a scan of newline-delimited JSON files. Unless the
+    /// options contain a schema, the schema is inferred from the first records of the first
+    /// file.
+    pub fn read_json(&self, path: &str, options: JsonReadOptions) -> Result<BallistaDataFrame> {
+        if self.resolve_on_scheduler()? {
+            let mut table = self.scheduler_table(GetFileMetadataParams {
+                path: path.to_owned(),
+                file_type: FileType::NdJson.into(),
+                has_header: false,
+                delimiter: String::new(),
+                file_extension: options.file_extension.to_owned(),
+                partition_columns: vec![],
+            })?;
+            // an explicit schema takes precedence over the one inferred by the scheduler
+            if let Some(schema) = options.schema {
+                table.schema = Arc::new(schema.clone());
+            }
+            return self.read_table(Arc::new(table));
+        }
+
+        // convert to absolute path because the executor likely has a different working directory
+        let path = fs::canonicalize(&PathBuf::from(path))?;
+        let table = JsonTable::try_new(path.to_str().unwrap(), options)?;
+        self.read_table(Arc::new(table))
+    }
+
+    /// Create a DataFrame representing a scan of a table provider. In-memory tables are sent
+    /// to the executors as part of the query plan, and other providers must have an extension
+    /// codec registered, see [`crate::serde::extension`].
+    pub fn read_table(
+        &self,
+        provider: Arc<dyn TableProvider + Send + Sync>,
+    ) -> Result<BallistaDataFrame> {
+        let mut ctx = ExecutionContext::new();
+        let df = ctx.read_table(provider)?;
+        Ok(BallistaDataFrame::from(self.state.clone(), df))
+    }
+
+    /// Upload record batches to the executors and create a DataFrame representing a scan of
+    /// them. The batches are spread across the executors, which hold them as partitions that
+    /// queries read in place, so the data does not need to be accessible from the cluster.
+    pub fn read_record_batches(
+        &self,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<BallistaDataFrame> {
+        let scheduler_url = {
+            let state = self.state.lock().unwrap();
+            format!("http://{}:{}", state.scheduler_host, state.scheduler_port)
+        };
+        let table_schema = schema.clone();
+
+        utils::check_upload_schema(schema.as_ref())?;
+        let partitions = block_on_client_runtime(async move {
+            info!("Connecting to Ballista scheduler at {}", scheduler_url);
+            let mut scheduler = SchedulerGrpcClient::connect(scheduler_url).await?;
+            let executors: Vec<ExecutorMeta> = scheduler
+                .get_executors_metadata(GetExecutorMetadataParams {})
+                .await?
+                .into_inner()
+                .metadata
+                .into_iter()
+                .map(|meta| meta.into())
+                .collect();
+            if executors.is_empty() {
+                return Err(BallistaError::General(
+                    "There are no executors to upload the record batches to".to_owned(),
+                ));
+            }
+
+            // the batches are assigned round-robin to at most one partition per executor
+            let num_partitions = executors.len().min(batches.len()).max(1);
+            let mut partition_batches = vec![vec![]; num_partitions];
+            for (i, batch) in batches.into_iter().enumerate() {
+                partition_batches[i % num_partitions].push(batch);
+            }
+            let table_uuid = Uuid::new_v4();
+            let mut uploads = vec![];
+            for (partition, batches) in partition_batches.into_iter().enumerate() {
+                let partition_id =
+                    scheduler::PartitionId::new(table_uuid, scheduler::UPLOAD_STAGE_ID, partition);
+                let executor_meta = executors[partition].clone();
+                uploads.push(upload_partition(
+                    executor_meta,
+                    partition_id,
+                    schema.clone(),
+                    batches,
+                ));
+            }
+            futures::future::try_join_all(uploads).await
+        })?;
+
+        self.read_table(Arc::new(UploadedTable::new(table_schema, partitions)))
+    }
+
+    /// Upload record batches to the executors and register them as a table, see
+    /// [`BallistaContext::read_record_batches`]
+    pub fn register_record_batches(
+        &self,
+        name: &str,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<()> {
+        let df = self.read_record_batches(schema, batches)?;
+        self.register_table(name, &df)
+    }
+
+    /// Deregister a table registered with [`BallistaContext::register_record_batches`] and
+    /// remove its partitions from the executors. The executors keep uploaded tables until they
+    /// are removed. The table stays registered if its partitions could not be removed, so that
+    /// the removal can be retried.
+    pub fn remove_record_batches(&self, name: &str) -> Result<()> {
+        let source = match self.state.lock().unwrap().tables.get(name) {
+            Some(LogicalPlan::TableScan { source, .. })
+                if source.as_any().is::<UploadedTable>() =>
+            {
+                source.clone()
+            }
+            Some(_) => {
+                return Err(BallistaError::General(format!(
+                    "Table {} is not an uploaded table",
+                    name
+                )))
+            }
+            None => {
+                return Err(BallistaError::General(format!(
+                    "Table {} is not registered",
+                    name
+                )))
+            }
+        };
+
+        let removed = source.clone();
+        block_on_client_runtime(async move {
+            let table = removed.as_any().downcast_ref::<UploadedTable>().unwrap();
+            table.remove().await
+        })?;
+
+        // the name may have been registered again while the partitions were being removed
+        let mut state = self.state.lock().unwrap();
+        let unchanged = match state.tables.get(name) {
+            Some(LogicalPlan::TableScan {
+                source: current, ..
+            }) => Arc::as_ptr(current) as *const u8 == Arc::as_ptr(&source) as *const u8,
+            _ => false,
+        };
+        if unchanged {
+            state.tables.remove(name);
+        }
+        Ok(())
+    }
+
     /// Register a DataFrame as a table that can be referenced from a SQL query
     pub fn register_table(&self, name: &str, table: &BallistaDataFrame) -> Result<()> {
         let mut state = self.state.lock().unwrap();
@@ -134,10 +364,173 @@ impl BallistaContext {
         self.register_table(name, &df)
     }
 
+    /// Register newline-delimited JSON files as a table, see [`BallistaContext::read_json`]
+    pub fn register_json(&self, name: &str, path: &str, options: JsonReadOptions) -> Result<()> {
+        let df = self.read_json(path, options)?;
+        self.register_table(name, &df)
+    }
+
+    /// Register Parquet files stored in Hive-style `column=value` directories as a table, see
+    /// [`BallistaContext::read_parquet_partitioned`]
+    pub fn register_parquet_partitioned(
+        &self,
+        name: &str,
+        path: &str,
+        partition_columns: &[&str],
+    ) -> Result<()> {
+        let df = self.read_parquet_partitioned(path, partition_columns)?;
+        self.register_table(name, &df)
+    }
+
+    /// Register CSV files stored in Hive-style `column=value` directories as a table, see
+    /// [`BallistaContext::read_parquet_partitioned`]
+    pub fn register_csv_partitioned(
+        &self,
+        name: &str,
+        path: &str,
+        options: CsvReadOptions,
+        partition_columns: &[&str],
+    ) -> Result<()> {
+        let df = self.read_csv_partitioned(path, options, partition_columns)?;
+        self.register_table(name, &df)
+    }
+
+    /// Whether table paths are resolved by the scheduler rather than on this machine, which is
+    /// the case when the `table.resolution` setting is `scheduler`
+    fn resolve_on_scheduler(&self) -> Result<bool> {
+        let state = self.state.lock().unwrap();
+        match state.settings.get(TABLE_RESOLUTION).map(|s| s.as_str()) {
+            None | Some("client") => Ok(false),
+            Some("scheduler") => Ok(true),
+            Some(other) => Err(BallistaError::General(format!(
+                "Invalid {} {}, expected client or scheduler",
+                TABLE_RESOLUTION, other
+            ))),
+        }
+    }
+
+    /// Resolve a table of Parquet files with the scheduler
+    fn scheduler_parquet_table(
+        &self,
+        path: &str,
+        partition_columns: Vec<String>,
+    ) -> Result<SchedulerTable> {
+        self.scheduler_table(GetFileMetadataParams {
+            path: path.to_owned(),
+            file_type: FileType::Parquet.into(),
+            has_header: false,
+            delimiter: String::new(),
+            file_extension: String::new(),
+            partition_columns,
+        })
+    }
+
+    /// Resolve a table of CSV files with the scheduler
+    fn scheduler_csv_table(
+        &self,
+        path: &str,
+        options: &CsvReadOptions,
+        partition_columns: Vec<String>,
+    ) -> Result<SchedulerTable> {
+        let delimiter = [options.delimiter];
+        let delimiter = std::str::from_utf8(&delimiter)
+            .map_err(|_| BallistaError::General("Invalid CSV delimiter".to_owned()))?;
+        let mut table = self.scheduler_table(GetFileMetadataParams {
+            path: path.to_owned(),
+            file_type: FileType::Csv.into(),
+            has_header: options.has_header,
+            delimiter: delimiter.to_owned(),
+            file_extension: options.file_extension.to_owned(),
+            partition_columns,
+        })?;
+        // an explicit schema takes precedence over the one inferred by the scheduler, and the
+        // partition columns are still appended to it
+        if let Some(schema) = options.schema {
+            let mut fields = schema.fields().clone();
+            let num_file_columns = table.schema.fields().len() - table.partition_columns.len();
+            fields.extend(table.schema.fields()[num_file_columns..].iter().cloned());
+            table.schema = Arc::new(Schema::new(fields));
+        }
+        Ok(table)
+    }
+
+    /// Ask the scheduler for the schema and statistics of a table, so that the files of the
+    /// table only need to be accessible from the cluster
+    fn scheduler_table(&self, params: GetFileMetadataParams) -> Result<SchedulerTable> {
+        let scheduler_url = {
+            let state = self.state.lock().unwrap();
+            format!("http://{}:{}", state.scheduler_host, state.scheduler_port)
+        };
+        let path = params.path.clone();
+        let file_type: FileType = params.file_type.try_into()?;
+        let has_header = params.has_header;
+        let delimiter = params.delimiter.as_bytes().first().cloned().unwrap_or(b',');
+        let file_extension = params.file_extension.clone();
+        let partition_columns = params.partition_columns.clone();
+
+        let metadata = block_on_client_runtime(async move {
+            info!("Connecting to Ballista scheduler at {}", scheduler_url);
+            let mut scheduler = SchedulerGrpcClient::connect(scheduler_url).await?;
+            Ok(scheduler.get_file_metadata(params).await?.into_inner())
+        })?;
+
+        let schema: Schema = metadata
+            .schema
+            .as_ref()
+            .ok_or_else(|| {
+                BallistaError::Internal("Received file metadata without schema".to_owned())
+            })?
+            .try_into()?;
+        // the statistics are only known when the scheduler returned them for every file
+        let statistics = if metadata
+            .partitions
+            .iter()
+            .all(|part| part.statistics.len() == part.filename.len())
+        {
+            let files = metadata
+                .partitions
+                .iter()
+                .flat_map(|part| part.statistics.iter())
+                .map(|statistics| statistics.try_into())
+                .collect::<Result<Vec<_>>>()?;
+            merge_statistics(&files)
+        } else {
+            Statistics {
+                num_rows: None,
+                total_byte_size: None,
+                column_statistics: None,
+            }
+        };
+
+        Ok(SchedulerTable {
+            path,
+            file_type,
+            has_header,
+            delimiter,
+            file_extension,
+            partition_columns,
+            schema: Arc::new(schema),
+            statistics,
+        })
+    }
+
+    /// Register a scalar UDF so that it can be used in queries. The same function must also be
+    /// registered with the scheduler and executors, see [`crate::udf`].
+    pub fn register_udf(&self, f: ScalarUDF) {
+        udf::register_udf(f)
+    }
+
+    /// Register an aggregate UDF so that it can be used in queries. The same function must also
+    /// be registered with the scheduler and executors, see [`crate::udf`].
+    pub fn register_udaf(&self, f: AggregateUDF) {
+        udf::register_udaf(f)
+    }
+
     /// Create a DataFrame from a SQL statement
     pub fn sql(&self, sql: &str) -> Result<BallistaDataFrame> {
         // use local DataFusion context for now but later this might call the scheduler
         let mut ctx = ExecutionContext::new();
+        register_udfs(&mut ctx);
         // register tables
         let state = self.state.lock().unwrap();
         for (name, plan) in &state.tables {
@@ -150,6 +543,17 @@ impl BallistaContext {
     }
 }
 
+/// Register the functions from the UDF registry with a DataFusion context, so that they can be
+/// resolved when planning SQL
+pub(crate) fn register_udfs(ctx: &mut ExecutionContext) {
+    for f in udf::udfs() {
+        ctx.register_udf(f.as_ref().clone());
+    }
+    for f in udf::udafs() {
+        ctx.register_udaf(f.as_ref().clone());
+    }
+}
+
 /// This ugly adapter is needed because we use DataFusion's logical plan when building queries
 /// and when we register tables with DataFusion's `ExecutionContext` we need to provide a
 /// TableProvider which is effectively a wrapper around a physical plan. We need to be able to
@@ -163,7 +567,7 @@ pub(crate) struct DFTableAdapter {
 }
 
 impl DFTableAdapter {
-    fn new(logical_plan: LogicalPlan, plan: Arc<dyn ExecutionPlan>) -> Self {
+    pub(crate) fn new(logical_plan: LogicalPlan, plan: Arc<dyn ExecutionPlan>) -> Self {
         Self { logical_plan, plan }
     }
 }
@@ -195,6 +599,134 @@ impl TableProvider for DFTableAdapter {
     }
 }
 
+/// A table whose path is resolved by the scheduler. The schema and statistics of the table are
+/// provided by the scheduler, and the table is sent back to the scheduler as a scan of its
+/// unresolved path, so it cannot be scanned on this machine.
+pub(crate) struct SchedulerTable {
+    /// Path of the table, relative to the working directory of the scheduler and executors
+    pub path: String,
+    pub file_type: FileType,
+    /// CSV options
+    pub has_header: bool,
+    pub delimiter: u8,
+    pub file_extension: String,
+    /// Partition columns of a table stored in Hive-style `column=value` directories
+    pub partition_columns: Vec<String>,
+    pub schema: SchemaRef,
+    pub statistics: Statistics,
+}
+
+impl TableProvider for SchedulerTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+        _filters: &[Expr],
+    ) -> DFResult<Arc<dyn ExecutionPlan>> {
+        // the files may not exist on this machine, so the scan only describes the schema that
+        // is needed to plan SQL queries against the table
+        let schema = match projection {
+            Some(columns) => Arc::new(Schema::new(
+                columns
+                    .iter()
+                    .map(|i| self.schema.field(*i).clone())
+                    .collect(),
+            )),
+            None => self.schema.clone(),
+        };
+        Ok(Arc::new(EmptyExec::new(false, schema)))
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.statistics.clone()
+    }
+}
+
+lazy_static! {
+    /// Runtime on which the synchronous context API makes its requests to the scheduler and
+    /// the executors
+    static ref CLIENT_RUNTIME: std::result::Result<Runtime, String> =
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("ballista-client")
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string());
+}
+
+/// Run a future on the client runtime and wait for its result. The context API is synchronous
+/// and may be called from within a Tokio runtime, which cannot block on another future, so the
+/// future is run by the runtime shared by all contexts.
+fn block_on_client_runtime<T: Send + 'static>(
+    future: impl Future<Output = Result<T>> + Send + 'static,
+) -> Result<T> {
+    let runtime = CLIENT_RUNTIME.as_ref().map_err(|e| {
+        BallistaError::General(format!("Could not create the client runtime: {}", e))
+    })?;
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    runtime.spawn(async move {
+        // the caller only stops waiting when it panicked
+        let _ = sender.send(future.await);
+    });
+    receiver
+        .recv()
+        .map_err(|_| BallistaError::Internal("The client runtime dropped a request".to_owned()))?
+}
+
+/// Upload the batches of one partition of an uploaded table to an executor
+async fn upload_partition(
+    executor_meta: ExecutorMeta,
+    partition_id: scheduler::PartitionId,
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+) -> Result<planner::PartitionLocation> {
+    let mut client =
+        BallistaClient::try_new(executor_meta.host.as_str(), executor_meta.port).await?;
+    let stats = client
+        .put_partition(&partition_id, schema.as_ref(), batches)
+        .await?;
+    debug!(
+        "Uploaded partition {:?} to executor {}: {:?}",
+        partition_id, executor_meta.id, stats
+    );
+    Ok(planner::PartitionLocation {
+        partition_id,
+        executor_meta,
+    })
+}
+
+/// Connect to the executor holding a result partition and stream the partition from it
+async fn fetch_result_partition(
+    location: PartitionLocation,
+) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
+    let metadata = location
+        .executor_meta
+        .ok_or_else(|| BallistaError::Internal("Received empty executor metadata".to_owned()))?;
+    let partition_id = location
+        .partition_id
+        .ok_or_else(|| BallistaError::Internal("Received empty partition id".to_owned()))?;
+    let job_uuid = Uuid::parse_str(&partition_id.job_uuid).map_err(|_| {
+        BallistaError::Internal(format!("Invalid job uuid {}", partition_id.job_uuid))
+    })?;
+    let mut ballista_client =
+        BallistaClient::try_new(metadata.host.as_str(), metadata.port as u16).await?;
+    ballista_client
+        .fetch_partition(
+            &job_uuid,
+            partition_id.stage_id as usize,
+            partition_id.partition_id as usize,
+        )
+        .await
+}
+
 /// The Ballista DataFrame is a wrapper around the DataFusion DataFrame and overrides the
 /// `collect` method so that the query is executed against Ballista and not DataFusion.
 
@@ -203,23 +735,89 @@ pub struct BallistaDataFrame {
     state: Arc<Mutex<BallistaContextState>>,
     /// DataFusion DataFrame representing logical query plan
     df: Arc<dyn DataFrame>,
+    /// ID of the job that was last submitted by `collect`
+    job_id: Arc<Mutex<Option<String>>>,
+}
+
+/// Cancels a job when it is dropped before being disarmed, so that a job is not left running
+/// when the future returned by `collect` is dropped before the job has finished
+struct CancelJobOnDrop {
+    scheduler_url: String,
+    job_id: String,
+    armed: bool,
+}
+
+impl CancelJobOnDrop {
+    fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancelJobOnDrop {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let scheduler_url = self.scheduler_url.clone();
+        let job_id = self.job_id.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    info!("Cancelling job {} because its results were dropped", job_id);
+                    if let Err(e) = cancel_job(scheduler_url, job_id.clone()).await {
+                        warn!("Failed to cancel job {}: {:?}", job_id, e);
+                    }
+                });
+            }
+            Err(_) => warn!(
+                "Cannot cancel job {} because there is no Tokio runtime",
+                job_id
+            ),
+        }
+    }
+}
+
+async fn cancel_job(scheduler_url: String, job_id: String) -> Result<bool> {
+    let mut scheduler = SchedulerGrpcClient::connect(scheduler_url).await?;
+    Ok(scheduler
+        .cancel_job(CancelJobParams { job_id })
+        .await?
+        .into_inner()
+        .cancelled)
 }
 
 impl BallistaDataFrame {
     pub fn from(state: Arc<Mutex<BallistaContextState>>, df: Arc<dyn DataFrame>) -> Self {
-        Self { state, df }
+        Self {
+            state,
+            df,
+            job_id: Arc::new(Mutex::new(None)),
+        }
     }
 
-    pub async fn collect(&self) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
-        let scheduler_url = {
-            let state = self.state.lock().unwrap();
+    fn scheduler_url(&self) -> String {
+        let state = self.state.lock().unwrap();
+        format!("http://{}:{}", state.scheduler_host, state.scheduler_port)
+    }
 
-            format!("http://{}:{}", state.scheduler_host, state.scheduler_port)
-        };
+    /// Cancel the job that was last submitted by `collect`. Returns false if the job had
+    /// already finished.
+    pub async fn cancel(&self) -> Result<bool> {
+        let job_id = self.job_id.lock().unwrap().clone().ok_or_else(|| {
+            BallistaError::General("No job has been submitted for this DataFrame".to_owned())
+        })?;
+        info!("Cancelling job {}", job_id);
+        cancel_job(self.scheduler_url(), job_id).await
+    }
+
+    /// Submit the query to the scheduler and stream the results once the job has completed.
+    /// If the returned future is dropped before the job has finished, the job is cancelled.
+    pub async fn collect(&self) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
+        let scheduler_url = self.scheduler_url();
 
         info!("Connecting to Ballista scheduler at {}", scheduler_url);
 
-        let mut scheduler = SchedulerGrpcClient::connect(scheduler_url).await?;
+        let mut scheduler = SchedulerGrpcClient::connect(scheduler_url.clone()).await?;
 
         let plan = self.df.to_logical_plan();
         let schema: Schema = plan.schema().as_ref().clone().into();
@@ -231,6 +829,12 @@ impl BallistaDataFrame {
             .await?
             .into_inner()
             .job_id;
+        *self.job_id.lock().unwrap() = Some(job_id.clone());
+        let mut cancel_on_drop = CancelJobOnDrop {
+            scheduler_url,
+            job_id: job_id.clone(),
+            armed: true,
+        };
 
         loop {
             let GetJobStatusResult { status } = scheduler
@@ -239,7 +843,13 @@ impl BallistaDataFrame {
                 })
                 .await?
                 .into_inner();
-            let status = status.and_then(|s| s.status).ok_or_else(|| {
+            let JobStatus {
+                status,
+                task_attempts,
+            } = status.ok_or_else(|| {
+                BallistaError::Internal("Received empty status message".to_owned())
+            })?;
+            let status = status.ok_or_else(|| {
                 BallistaError::Internal("Received empty status message".to_owned())
             })?;
             let wait_future = tokio::time::sleep(Duration::from_millis(100));
@@ -249,42 +859,52 @@ impl BallistaDataFrame {
                     wait_future.await;
                 }
                 job_status::Status::Running(_) => {
-                    info!("Job {} is running...", job_id);
+                    info!(
+                        "Job {} is running ({} task attempts so far)...",
+                        job_id, task_attempts
+                    );
                     wait_future.await;
                 }
                 job_status::Status::Failed(err) => {
-                    let msg = format!("Job {} failed: {}", job_id, err.error);
+                    let msg = format!(
+                        "Job {} failed after {} task attempts: {}",
+                        job_id, task_attempts, err.error
+                    );
                     error!("{}", msg);
+                    cancel_on_drop.disarm();
+                    break Err(BallistaError::General(msg));
+                }
+                job_status::Status::Cancelled(_) => {
+                    let msg = format!("Job {} was cancelled", job_id);
+                    error!("{}", msg);
+                    cancel_on_drop.disarm();
                     break Err(BallistaError::General(msg));
                 }
                 job_status::Status::Completed(completed) => {
-                    // TODO: use streaming. Probably need to change the signature of fetch_partition to achieve that
-                    let mut result = vec![];
-                    for location in completed.partition_location {
-                        let metadata = location.executor_meta.ok_or_else(|| {
-                            BallistaError::Internal("Received empty executor metadata".to_owned())
-                        })?;
-                        let partition_id = location.partition_id.ok_or_else(|| {
-                            BallistaError::Internal("Received empty partition id".to_owned())
-                        })?;
-                        let mut ballista_client =
-                            BallistaClient::try_new(metadata.host.as_str(), metadata.port as u16)
-                                .await?;
-                        result.append(
-                            &mut ballista_client
-                                .fetch_partition(
-                                    &Uuid::parse_str(&partition_id.job_uuid).unwrap(),
-                                    partition_id.stage_id as usize,
-                                    partition_id.partition_id as usize,
-                                )
-                                .await?,
-                        );
-                    }
-                    break Ok(Box::pin(MemoryStream::try_new(
-                        result,
-                        Arc::new(schema),
-                        None,
-                    )?));
+                    cancel_on_drop.disarm();
+                    // stream the result partitions one after the other so that only a few
+                    // batches are held in memory at any time
+                    let (sender, result) =
+                        RecordBatchReceiverStream::create(Arc::new(schema), DEFAULT_BUFFER_SIZE);
+                    tokio::spawn(async move {
+                        for location in completed.partition_location {
+                            let mut stream = match fetch_result_partition(location).await {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    let e = ArrowError::ExternalError(Box::new(e));
+                                    // the consumer might already have dropped the stream
+                                    let _ = sender.send(Err(e)).await;
+                                    return;
+                                }
+                            };
+                            while let Some(batch) = stream.next().await {
+                                if sender.send(batch).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    });
+                    break Ok(Box::pin(result));
                 }
             };
         }
@@ -0,0 +1,183 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The execution loop polls the scheduler for tasks, executes them and reports their status
+//! back to the scheduler on the next poll.
+
+use std::convert::TryInto;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::BallistaError;
+use crate::executor::BallistaExecutor;
+use crate::serde::protobuf::{
+    self, scheduler_grpc_client::SchedulerGrpcClient, task_status, CompletedTask, FailedTask,
+    PartitionId, PollWorkParams, PollWorkResult, TaskDefinition, TaskStatus,
+};
+use crate::serde::scheduler::ExecutorMeta;
+use crate::utils::PartitionStats;
+
+use datafusion::physical_plan::ExecutionPlan;
+use log::{debug, error, info, warn};
+use tonic::transport::Channel;
+use uuid::Uuid;
+
+pub async fn poll_loop(
+    mut scheduler: SchedulerGrpcClient<Channel>,
+    executor: Arc<BallistaExecutor>,
+    executor_meta: ExecutorMeta,
+) {
+    let executor_meta: protobuf::ExecutorMetadata = executor_meta.into();
+    let (task_status_sender, mut task_status_receiver) = std::sync::mpsc::channel::<TaskStatus>();
+
+    loop {
+        debug!("Polling scheduler for work");
+
+        let task_status: Vec<TaskStatus> = sample_tasks_status(&mut task_status_receiver);
+
+        let poll_work_result: Result<tonic::Response<PollWorkResult>, tonic::Status> = scheduler
+            .poll_work(PollWorkParams {
+                metadata: Some(executor_meta.clone()),
+                can_accept_task: executor.available_task_slots() > 0
+                    && !executor.work_dir_quota_exceeded(),
+                task_status,
+            })
+            .await;
+
+        let task_status_sender = task_status_sender.clone();
+
+        match poll_work_result {
+            Ok(result) => {
+                if let Some(task) = result.into_inner().task {
+                    run_received_task(
+                        executor.clone(),
+                        &executor_meta.id,
+                        task_status_sender,
+                        task,
+                    );
+                    // ask for more work straight away
+                    continue;
+                }
+            }
+            Err(error) => {
+                warn!("Executor poll work loop failed. If this continues to happen the executor might be marked as dead by the scheduler. Error: {}", error);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn run_received_task(
+    executor: Arc<BallistaExecutor>,
+    executor_id: &str,
+    task_status_sender: Sender<TaskStatus>,
+    task: TaskDefinition,
+) {
+    let executor_id = executor_id.to_owned();
+    tokio::spawn(async move {
+        let task_id = match task.task_id {
+            Some(task_id) => task_id,
+            None => {
+                error!("Received a task without a task id");
+                return;
+            }
+        };
+        info!(
+            "Received task {}/{}/{}",
+            task_id.job_uuid, task_id.stage_id, task_id.partition_id
+        );
+        let result = execute_task(&executor, &task_id, task.plan.as_ref()).await;
+        let status = as_task_status(result, executor_id, task_id);
+        if let Err(e) = task_status_sender.send(status) {
+            warn!("Could not report task status: {}", e);
+        }
+    });
+}
+
+async fn execute_task(
+    executor: &BallistaExecutor,
+    task_id: &PartitionId,
+    plan: Option<&protobuf::PhysicalPlanNode>,
+) -> Result<PartitionStats, BallistaError> {
+    let job_uuid = Uuid::parse_str(&task_id.job_uuid)
+        .map_err(|_| BallistaError::General(format!("Invalid job uuid {}", task_id.job_uuid)))?;
+    let plan: Arc<dyn ExecutionPlan> = plan
+        .ok_or_else(|| BallistaError::General("Received a task without a plan".to_owned()))?
+        .try_into()?;
+    let (_, stats) = executor
+        .execute_partition(
+            job_uuid,
+            task_id.stage_id as usize,
+            task_id.partition_id as usize,
+            plan,
+        )
+        .await?;
+    Ok(stats)
+}
+
+fn as_task_status(
+    execution_result: Result<PartitionStats, BallistaError>,
+    executor_id: String,
+    task_id: PartitionId,
+) -> TaskStatus {
+    match execution_result {
+        Ok(stats) => {
+            info!("Task {:?} finished", task_id);
+
+            TaskStatus {
+                partition_id: Some(task_id),
+                status: Some(task_status::Status::Completed(CompletedTask {
+                    executor_id,
+                    stats: Some(stats.into()),
+                })),
+                // attempts are tracked by the scheduler
+                attempts: 0,
+                failed_executor_ids: vec![],
+            }
+        }
+        Err(e) => {
+            let error_msg = e.to_string();
+            info!("Task {:?} failed: {}", task_id, error_msg);
+
+            TaskStatus {
+                partition_id: Some(task_id),
+                status: Some(task_status::Status::Failed(FailedTask { error: error_msg })),
+                attempts: 0,
+                failed_executor_ids: vec![],
+            }
+        }
+    }
+}
+
+fn sample_tasks_status(task_status_receiver: &mut Receiver<TaskStatus>) -> Vec<TaskStatus> {
+    let mut task_status: Vec<TaskStatus> = vec![];
+
+    loop {
+        match task_status_receiver.try_recv() {
+            Result::Ok(status) => {
+                task_status.push(status);
+            }
+            Err(TryRecvError::Empty) => {
+                break;
+            }
+            Err(TryRecvError::Disconnected) => {
+                error!("Task statuses channel disconnected");
+                break;
+            }
+        }
+    }
+
+    task_status
+}
@@ -14,44 +14,75 @@
 
 //! Implementation of the Apache Arrow Flight protocol that wraps an executor.
 
-use std::fs::File;
-use std::path::PathBuf;
+use std::convert::TryFrom;
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::task::{Context, Poll};
 use std::time::Instant;
 
+use crate::compression::{
+    open_shuffle_file, read_shuffle_file, ShuffleCompression, ACCEPT_COMPRESSION_KEY,
+};
 use crate::error::BallistaError;
-use crate::executor::BallistaExecutor;
+use crate::executor::{shuffle_stream, BallistaExecutor};
 use crate::memory_stream::MemoryStream;
-use crate::serde::decode_protobuf;
-use crate::serde::scheduler::Action as BallistaAction;
-use crate::utils::{self, format_plan, PartitionStats};
+use crate::receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE};
+use crate::serde::scheduler::{Action as BallistaAction, PartitionId};
+use crate::serde::{decode_protobuf, encode_protobuf, protobuf};
+use crate::utils::{check_upload_schema, format_plan, PartitionStats};
 
 use arrow::array::{ArrayRef, StringBuilder};
 use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
 use arrow::error::{ArrowError, Result as ArrowResult};
-use arrow::ipc::reader::FileReader;
 use arrow::ipc::writer::IpcWriteOptions;
-use arrow::record_batch::RecordBatch;
+use arrow::record_batch::{RecordBatch, RecordBatchReader};
+use arrow_flight::utils::flight_data_to_arrow_batch;
 use arrow_flight::{
-    flight_service_server::FlightService, Action, ActionType, Criteria, Empty, FlightData,
-    FlightDescriptor, FlightInfo, HandshakeRequest, HandshakeResponse, PutResult, SchemaResult,
-    Ticket,
+    flight_descriptor::DescriptorType, flight_service_server::FlightService, Action, ActionType,
+    Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest,
+    HandshakeResponse, Location, PutResult, SchemaResult, Ticket,
 };
-use crossbeam::channel::{bounded, Receiver, RecvError, Sender};
 use datafusion::error::DataFusionError;
-use datafusion::physical_plan::RecordBatchStream;
-use futures::{Stream, StreamExt};
+use datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream};
+use futures::Stream;
 use log::{debug, info, warn};
+use prost::Message;
 use std::collections::HashMap;
-use std::io::{Read, Seek};
 use tokio::task;
 use tokio::task::JoinHandle;
 use tonic::{Request, Response, Status, Streaming};
-
-type FlightDataSender = Sender<Option<Result<FlightData, Status>>>;
-type FlightDataReceiver = Receiver<Option<Result<FlightData, Status>>>;
+use uuid::Uuid;
+
+const REMOVE_EXPIRED_JOBS_ACTION: &str = "remove_expired_jobs";
+const WORK_DIR_USAGE_ACTION: &str = "work_dir_usage";
+
+/// Administrative actions of the executor, with their descriptions
+const ADMIN_ACTIONS: &[(&str, &str)] = &[
+    (
+        "cancel_job",
+        "Abort the tasks of a cancelled job and remove its work files. The body is a \
+         serialized CancelJob action.",
+    ),
+    (
+        "remove_job_data",
+        "Remove the work files of a finished job, except for the output of the given stages. \
+         The body is a serialized RemoveJobData action.",
+    ),
+    (
+        "remove_uploaded_table",
+        "Remove the partitions of a table that was uploaded by a client. The body is a \
+         serialized RemoveUploadedTable action.",
+    ),
+    (
+        REMOVE_EXPIRED_JOBS_ACTION,
+        "Remove the work files of the jobs that have not been written for longer than the \
+         time to live of job data. Returns the ids of the jobs whose files were removed.",
+    ),
+    (
+        WORK_DIR_USAGE_ACTION,
+        "Returns the number of bytes in the work directory.",
+    ),
+];
 
 /// Service implementing the Apache Arrow Flight Protocol
 #[derive(Clone)]
@@ -63,9 +94,26 @@ impl BallistaFlightService {
     pub fn new(executor: Arc<BallistaExecutor>) -> Self {
         Self { executor }
     }
+
+    /// Stream partition files held by the executor, reading ahead of the client by the
+    /// configured number of messages
+    async fn stream_partition_files(
+        &self,
+        paths: Vec<String>,
+        accepted_compression: &[ShuffleCompression],
+    ) -> Result<Response<BoxedFlightStream<FlightData>>, Status> {
+        shuffle_stream::stream_partition_files(
+            paths,
+            accepted_compression,
+            self.executor.config.read_ahead_batches,
+        )
+        .await
+        .map_err(|e| from_ballista_err(&e))
+    }
 }
 
-type BoxedFlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+pub(crate) type BoxedFlightStream<T> =
+    Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
 
 #[tonic::async_trait]
 
@@ -82,6 +130,7 @@ impl FlightService for BallistaFlightService {
         &self,
         request: Request<Ticket>,
     ) -> Result<Response<Self::DoGetStream>, Status> {
+        let accepted_compression = accepted_compression(&request);
         let ticket = request.into_inner();
         info!("Received do_get request");
 
@@ -99,39 +148,17 @@ impl FlightService for BallistaFlightService {
 
                 let mut tasks: Vec<JoinHandle<Result<_, BallistaError>>> = vec![];
                 for part in partition.partition_id.clone() {
-                    let work_dir = self.executor.config.work_dir.clone();
+                    let executor = self.executor.clone();
                     let partition = partition.clone();
                     tasks.push(tokio::spawn(async move {
-                        let mut path = PathBuf::from(&work_dir);
-                        path.push(&format!("{}", partition.job_uuid));
-                        path.push(&format!("{}", partition.stage_id));
-                        path.push(&format!("{}", part));
-                        std::fs::create_dir_all(&path)?;
-
-                        path.push("data.arrow");
-                        let path = path.to_str().unwrap();
-                        info!("Writing results to {}", path);
-
-                        let now = Instant::now();
-
-                        // execute the query partition
-                        let mut stream = partition
-                            .plan
-                            .execute(part)
-                            .await
-                            .map_err(|e| from_datafusion_err(&e))?;
-
-                        // stream results to disk
-                        let stats = utils::write_stream_to_disk(&mut stream, &path)
-                            .await
-                            .map_err(|e| from_ballista_err(&e))?;
-
-                        info!(
-                            "Executed partition {} in {} seconds. Statistics: {:?}",
-                            part,
-                            now.elapsed().as_secs(),
-                            stats
-                        );
+                        let (path, stats) = executor
+                            .execute_partition(
+                                partition.job_uuid,
+                                partition.stage_id,
+                                part,
+                                partition.plan,
+                            )
+                            .await?;
 
                         let mut flights: Vec<Result<FlightData, Status>> = vec![];
                         let options = arrow::ipc::writer::IpcWriteOptions::default();
@@ -195,53 +222,89 @@ impl FlightService for BallistaFlightService {
                 // fetch a partition that was previously executed by this executor
                 info!("FetchPartition {:?}", partition_id);
 
-                let mut path = PathBuf::from(&self.executor.config.work_dir);
-                path.push(&format!("{}", partition_id.job_uuid));
-                path.push(&format!("{}", partition_id.stage_id));
-                path.push(&format!("{}", partition_id.partition_id));
-                path.push("data.arrow");
-                let path = path.to_str().unwrap();
+                let path = self.executor.partition_file(partition_id, None);
+                let path = path.to_str().unwrap().to_owned();
 
                 info!("FetchPartition {:?} reading {}", partition_id, path);
-                let file = File::open(&path)
-                    .map_err(|e| {
-                        BallistaError::General(format!(
-                            "Failed to open partition file at {}: {:?}",
-                            path, e
-                        ))
-                    })
-                    .map_err(|e| from_ballista_err(&e))?;
-                let reader = FileReader::try_new(file).map_err(|e| from_arrow_err(&e))?;
+                self.stream_partition_files(vec![path], &accepted_compression)
+                    .await
+            }
+            BallistaAction::FetchShufflePartition(partition_id, output_partition) => {
+                // fetch one output partition of a hash-partitioned partition that was
+                // previously executed by this executor
+                info!(
+                    "FetchShufflePartition {:?} output_partition={}",
+                    partition_id, output_partition
+                );
 
-                let (tx, rx): (FlightDataSender, FlightDataReceiver) = bounded(2);
+                let path = self
+                    .executor
+                    .partition_file(partition_id, Some(*output_partition));
+                let path = path.to_str().unwrap().to_owned();
 
-                // Arrow IPC reader does not implement Sync + Send so we need to use a channel
-                // to communicate
-                task::spawn_blocking(move || {
-                    if let Err(e) = stream_flight_data(reader, tx) {
-                        warn!("Error streaming results: {:?}", e);
-                    }
-                });
+                info!("FetchShufflePartition {:?} reading {}", partition_id, path);
+                self.stream_partition_files(vec![path], &accepted_compression)
+                    .await
+            }
+            BallistaAction::FetchPartitions(partition_ids, output_partition) => {
+                // fetch several partitions, or the same output partition of several
+                // hash-partitioned partitions, as one stream
+                info!(
+                    "FetchPartitions {:?} output_partition={:?}",
+                    partition_ids, output_partition
+                );
 
-                Ok(Response::new(
-                    Box::pin(FlightDataStream::new(rx)) as Self::DoGetStream
-                ))
+                let paths = partition_ids
+                    .iter()
+                    .map(|partition_id| {
+                        let path = self
+                            .executor
+                            .partition_file(partition_id, *output_partition);
+                        path.to_str().unwrap().to_owned()
+                    })
+                    .collect();
+                self.stream_partition_files(paths, &accepted_compression)
+                    .await
             }
+            BallistaAction::CancelJob(_)
+            | BallistaAction::RemoveJobData(..)
+            | BallistaAction::RemoveUploadedTable(_) => Err(Status::invalid_argument(
+                "CancelJob, RemoveJobData and RemoveUploadedTable must be sent with do_action",
+            )),
         }
     }
 
     async fn get_schema(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<SchemaResult>, Status> {
-        Err(Status::unimplemented("get_schema"))
+        let (partition_id, output_partition) = parse_descriptor(&request.into_inner())?;
+        let path = self
+            .executor
+            .partition_file(&partition_id, output_partition);
+        let schema = task::spawn_blocking(move || partition_schema(&path))
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?
+            .map_err(|e| from_ballista_err(&e))?;
+        let options = IpcWriteOptions::default();
+        Ok(Response::new(
+            arrow_flight::utils::flight_schema_from_arrow_schema(schema.as_ref(), &options),
+        ))
     }
 
     async fn get_flight_info(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<FlightInfo>, Status> {
-        Err(Status::unimplemented("get_flight_info"))
+        let (partition_id, output_partition) = parse_descriptor(&request.into_inner())?;
+        let executor = self.executor.clone();
+        let info = task::spawn_blocking(move || {
+            flight_info(&executor, &partition_id, output_partition, true)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("{:?}", e)))?
+        .map_err(|e| from_ballista_err(&e))?;
+        Ok(Response::new(info))
     }
 
     async fn handshake(
@@ -253,22 +316,109 @@ impl FlightService for BallistaFlightService {
 
     async fn list_flights(
         &self,
-        _request: Request<Criteria>,
+        request: Request<Criteria>,
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
-        Err(Status::unimplemented("list_flights"))
+        // the criteria can restrict the listing to the partitions of one job
+        let criteria = request.into_inner();
+        let job_uuid = if criteria.expression.is_empty() {
+            None
+        } else {
+            let job_id = String::from_utf8_lossy(&criteria.expression).to_string();
+            Some(Uuid::parse_str(&job_id).map_err(|e| {
+                Status::invalid_argument(format!("Invalid job id {}: {}", job_id, e))
+            })?)
+        };
+        let executor = self.executor.clone();
+        let infos = task::spawn_blocking(move || {
+            let infos = executor
+                .shuffle_partitions(job_uuid.as_ref())?
+                .iter()
+                .filter_map(|(partition_id, output_partition)| {
+                    // counting the rows means reading the partitions, which is left to
+                    // get_flight_info
+                    match flight_info(&executor, partition_id, *output_partition, false) {
+                        Ok(info) => Some(info),
+                        Err(e) => {
+                            // partitions that are still being written cannot be read yet, and
+                            // must not fail the listing of the other partitions
+                            warn!(
+                                "Skipping partition {:?} in list_flights: {}",
+                                partition_id, e
+                            );
+                            None
+                        }
+                    }
+                })
+                .collect();
+            Ok::<Vec<FlightInfo>, BallistaError>(infos)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("{:?}", e)))?
+        .map_err(|e| from_ballista_err(&e))?;
+        let output = futures::stream::iter(infos.into_iter().map(Ok));
+        Ok(Response::new(Box::pin(output) as Self::ListFlightsStream))
     }
 
     async fn do_put(
         &self,
         request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        let mut request = request.into_inner();
-
-        while let Some(data) = request.next().await {
-            let _data = data?;
+        let mut stream = request.into_inner();
+
+        // the first message describes the partition and carries the schema of the batches
+        let flight_data = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("do_put received an empty stream"))?;
+        let descriptor = flight_data.flight_descriptor.as_ref().ok_or_else(|| {
+            Status::invalid_argument("do_put requires the descriptor of the partition")
+        })?;
+        let (partition_id, output_partition) = parse_descriptor(descriptor)?;
+        if output_partition.is_some() {
+            return Err(Status::invalid_argument(
+                "Uploaded partitions cannot have output partitions",
+            ));
         }
-
-        Err(Status::unimplemented("do_put"))
+        let schema = Arc::new(Schema::try_from(&flight_data).map_err(|e| from_arrow_err(&e))?);
+        check_upload_schema(schema.as_ref())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        info!("DoPut {:?}", partition_id);
+
+        // the remaining messages are record batches, which are decoded as they are written to
+        // disk
+        let (sender, batches) =
+            RecordBatchReceiverStream::create(schema.clone(), DEFAULT_BUFFER_SIZE);
+        tokio::spawn(async move {
+            loop {
+                let batch = match stream.message().await {
+                    Ok(Some(flight_data)) => {
+                        flight_data_to_arrow_batch(&flight_data, schema.clone(), &[])
+                    }
+                    Ok(None) => break,
+                    Err(e) => Err(ArrowError::ExternalError(Box::new(e))),
+                };
+                let failed = batch.is_err();
+                // sending fails when the writer stopped reading
+                if sender.send(batch).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut batches: SendableRecordBatchStream = Box::pin(batches);
+        let stats = self
+            .executor
+            .write_partition(&partition_id, &mut batches)
+            .await
+            .map_err(|e| from_ballista_err(&e))?;
+
+        // the statistics of the partition are returned in the metadata of the only result
+        let stats: protobuf::PartitionStats = stats.into();
+        let mut app_metadata = Vec::with_capacity(stats.encoded_len());
+        stats
+            .encode(&mut app_metadata)
+            .map_err(|e| Status::internal(format!("{:?}", e)))?;
+        let output = futures::stream::iter(vec![Ok(PutResult { app_metadata })]);
+        Ok(Response::new(Box::pin(output) as Self::DoPutStream))
     }
 
     async fn do_action(
@@ -277,16 +427,89 @@ impl FlightService for BallistaFlightService {
     ) -> Result<Response<Self::DoActionStream>, Status> {
         let action = request.into_inner();
 
-        let _action = decode_protobuf(&action.body.to_vec()).map_err(|e| from_ballista_err(&e))?;
+        match action.r#type.as_str() {
+            REMOVE_EXPIRED_JOBS_ACTION => {
+                let executor = self.executor.clone();
+                let removed = task::spawn_blocking(move || executor.remove_expired_jobs())
+                    .await
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?
+                    .map_err(|e| from_ballista_err(&e))?;
+                let results = removed.into_iter().map(|job_uuid| {
+                    Ok(arrow_flight::Result {
+                        body: job_uuid.to_string().into_bytes(),
+                    })
+                });
+                return Ok(Response::new(
+                    Box::pin(futures::stream::iter(results)) as Self::DoActionStream
+                ));
+            }
+            WORK_DIR_USAGE_ACTION => {
+                let result = arrow_flight::Result {
+                    body: self.executor.work_dir_usage().to_string().into_bytes(),
+                };
+                return Ok(Response::new(
+                    Box::pin(futures::stream::iter(vec![Ok(result)])) as Self::DoActionStream,
+                ));
+            }
+            _ => {}
+        }
 
-        Err(Status::unimplemented("do_action"))
+        // the other actions are serialized Ballista actions, which remove files on a blocking
+        // thread
+        let action = decode_protobuf(&action.body.to_vec()).map_err(|e| from_ballista_err(&e))?;
+        let executor = self.executor.clone();
+        let remove_files = match action {
+            BallistaAction::CancelJob(job_uuid) => {
+                info!("CancelJob: job={}", job_uuid);
+                task::spawn_blocking(move || executor.cancel_job(&job_uuid))
+            }
+            BallistaAction::RemoveJobData(job_uuid, keep_stages) => {
+                info!(
+                    "RemoveJobData: job={}, keep_stages={:?}",
+                    job_uuid, keep_stages
+                );
+                task::spawn_blocking(move || executor.remove_job_data(&job_uuid, &keep_stages))
+            }
+            BallistaAction::RemoveUploadedTable(table_uuid) => {
+                info!("RemoveUploadedTable: table={}", table_uuid);
+                task::spawn_blocking(move || executor.remove_uploaded_table(&table_uuid))
+            }
+            _ => {
+                return Err(Status::unimplemented(format!(
+                    "do_action only supports the actions {}",
+                    ADMIN_ACTIONS
+                        .iter()
+                        .map(|(action_type, _)| *action_type)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )))
+            }
+        };
+        remove_files
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))?
+            .map_err(|e| from_ballista_err(&e))?;
+        Ok(Response::new(
+            Box::pin(futures::stream::empty()) as Self::DoActionStream
+        ))
     }
 
     async fn list_actions(
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<Self::ListActionsStream>, Status> {
-        Err(Status::unimplemented("list_actions"))
+        let actions = ADMIN_ACTIONS
+            .iter()
+            .map(|(action_type, description)| {
+                Ok(ActionType {
+                    r#type: (*action_type).to_owned(),
+                    description: (*description).to_owned(),
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Response::new(
+            Box::pin(futures::stream::iter(actions)) as Self::ListActionsStream
+        ))
     }
 
     async fn do_exchange(
@@ -313,65 +536,247 @@ fn create_flight_iter(
     )
 }
 
-fn stream_flight_data<T>(reader: FileReader<T>, tx: FlightDataSender) -> Result<(), Status>
-where
-    T: Read + Seek,
-{
-    let options = arrow::ipc::writer::IpcWriteOptions::default();
-    let schema_flight_data =
-        arrow_flight::utils::flight_data_from_arrow_schema(reader.schema().as_ref(), &options);
-    send_response(&tx, Some(Ok(schema_flight_data)))?;
-
-    for batch in reader {
-        let batch_flight_data: Vec<_> = batch
-            .map(|b| create_flight_iter(&b, &options).collect())
-            .map_err(|e| from_arrow_err(&e))?;
-        for batch in &batch_flight_data {
-            send_response(&tx, Some(batch.clone()))?;
-        }
+/// Parse a descriptor of a partition held by the executor. The path of the descriptor is the
+/// job id, stage id and partition id, followed by the output partition for the files of
+/// hash-partitioned partitions.
+fn parse_descriptor(descriptor: &FlightDescriptor) -> Result<(PartitionId, Option<usize>), Status> {
+    let invalid = || {
+        Status::invalid_argument(format!(
+            "Expected a path descriptor of the form \
+             job_id/stage_id/partition_id[/output_partition], got {:?}",
+            descriptor.path
+        ))
+    };
+    if descriptor.r#type != DescriptorType::Path as i32
+        || descriptor.path.len() < 3
+        || descriptor.path.len() > 4
+    {
+        return Err(invalid());
     }
-    send_response(&tx, None)?;
-    Ok(())
+    let job_uuid = Uuid::parse_str(&descriptor.path[0]).map_err(|_| invalid())?;
+    let ids = descriptor.path[1..]
+        .iter()
+        .map(|id| id.parse::<usize>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, Status>>()?;
+    Ok((
+        PartitionId::new(job_uuid, ids[0], ids[1]),
+        ids.get(2).copied(),
+    ))
 }
 
-fn send_response(
-    tx: &FlightDataSender,
-    data: Option<Result<FlightData, Status>>,
-) -> Result<(), Status> {
-    tx.send(data)
-        .map_err(|e| Status::internal(format!("{:?}", e)))
+/// Schema of a partition file
+fn partition_schema(path: &Path) -> Result<SchemaRef, BallistaError> {
+    let (compression, file) = open_shuffle_file(path.to_str().unwrap())?;
+    Ok(read_shuffle_file(compression, file)?.schema())
 }
 
-struct FlightDataStream {
-    response_rx: FlightDataReceiver,
-}
-
-impl FlightDataStream {
-    fn new(response_rx: FlightDataReceiver) -> Self {
-        Self { response_rx }
-    }
+/// Describe a partition held by the executor, with a ticket to fetch it from the executor.
+/// The rows of the partition are only counted when `count_rows` is set, since that reads the
+/// whole partition.
+fn flight_info(
+    executor: &BallistaExecutor,
+    partition_id: &PartitionId,
+    output_partition: Option<usize>,
+    count_rows: bool,
+) -> Result<FlightInfo, BallistaError> {
+    let path = executor.partition_file(partition_id, output_partition);
+    let (compression, file) = open_shuffle_file(path.to_str().unwrap())?;
+    let total_bytes = file.metadata()?.len() as i64;
+    let reader = read_shuffle_file(compression, file)?;
+    let options = IpcWriteOptions::default();
+    let schema =
+        arrow_flight::utils::flight_schema_from_arrow_schema(reader.schema().as_ref(), &options)
+            .schema;
+    let total_records = if count_rows {
+        let mut rows = 0;
+        for batch in reader {
+            rows += batch?.num_rows() as i64;
+        }
+        rows
+    } else {
+        -1
+    };
+
+    let mut path = vec![
+        partition_id.job_uuid.to_string(),
+        partition_id.stage_id.to_string(),
+        partition_id.partition_id.to_string(),
+    ];
+    let action = match output_partition {
+        Some(output_partition) => {
+            path.push(output_partition.to_string());
+            BallistaAction::FetchShufflePartition(*partition_id, output_partition)
+        }
+        None => BallistaAction::FetchPartition(*partition_id),
+    };
+    let endpoint = FlightEndpoint {
+        ticket: Some(Ticket {
+            ticket: encode_protobuf(&action)?,
+        }),
+        location: vec![Location {
+            uri: format!(
+                "grpc+tcp://{}:{}",
+                executor.config.host, executor.config.port
+            ),
+        }],
+    };
+    Ok(FlightInfo {
+        schema,
+        flight_descriptor: Some(FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            cmd: vec![],
+            path,
+        }),
+        endpoint: vec![endpoint],
+        total_records,
+        total_bytes,
+    })
 }
 
-impl Stream for FlightDataStream {
-    type Item = Result<FlightData, Status>;
-
-    fn poll_next(self: std::pin::Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.response_rx.recv() {
-            Ok(maybe_batch) => Poll::Ready(maybe_batch),
-            // RecvError means receiver has exited and closed the channel
-            Err(RecvError) => Poll::Ready(None),
-        }
-    }
+/// Codecs that the client of a request can decode, from the request metadata
+fn accepted_compression<T>(request: &Request<T>) -> Vec<ShuffleCompression> {
+    request
+        .metadata()
+        .get(ACCEPT_COMPRESSION_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|codec| codec.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 fn from_arrow_err(e: &ArrowError) -> Status {
     Status::internal(format!("ArrowError: {:?}", e))
 }
 
-fn from_ballista_err(e: &crate::error::BallistaError) -> Status {
+pub(crate) fn from_ballista_err(e: &crate::error::BallistaError) -> Status {
     Status::internal(format!("Ballista Error: {:?}", e))
 }
 
 fn from_datafusion_err(e: &DataFusionError) -> Status {
     Status::internal(format!("DataFusion Error: {:?}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ExecutorConfig;
+    use crate::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
+    use crate::serde::scheduler::UPLOAD_STAGE_ID;
+    use arrow::array::UInt32Array;
+    use futures::StreamExt;
+    use tonic::transport::Endpoint;
+
+    async fn flight_service(work_dir: &str) -> Result<BallistaFlightService, BallistaError> {
+        let channel = Endpoint::from_static("http://localhost:50050").connect_lazy()?;
+        let config = ExecutorConfig::new("localhost", 50051, work_dir, 1);
+        let executor = BallistaExecutor::new(config, SchedulerGrpcClient::new(channel)).await;
+        Ok(BallistaFlightService::new(Arc::new(executor)))
+    }
+
+    fn path_descriptor(path: &[&str]) -> FlightDescriptor {
+        FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            cmd: vec![],
+            path: path.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parse_path_descriptor() -> Result<(), BallistaError> {
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+        assert_eq!(
+            (PartitionId::new(job_uuid, 1, 2), None),
+            parse_descriptor(&path_descriptor(&[&job_id, "1", "2"]))?
+        );
+        assert_eq!(
+            (PartitionId::new(job_uuid, 1, 2), Some(3)),
+            parse_descriptor(&path_descriptor(&[&job_id, "1", "2", "3"]))?
+        );
+
+        for path in &[
+            vec![job_id.as_str(), "1"],
+            vec![job_id.as_str(), "1", "2", "3", "4"],
+            vec![job_id.as_str(), "1", "x"],
+            vec!["not-a-job", "1", "2"],
+        ] {
+            assert!(parse_descriptor(&path_descriptor(path)).is_err());
+        }
+        let mut cmd = path_descriptor(&[&job_id, "1", "2"]);
+        cmd.r#type = DescriptorType::Cmd as i32;
+        assert!(parse_descriptor(&cmd).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn describe_partitions() -> Result<(), BallistaError> {
+        let work_dir = tempfile::TempDir::new()?;
+        let service = flight_service(work_dir.path().to_str().unwrap()).await?;
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::UInt32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(UInt32Array::from(vec![1, 2, 3]))],
+        )?;
+        let partition_id = PartitionId::new(Uuid::new_v4(), UPLOAD_STAGE_ID, 0);
+        let mut stream: SendableRecordBatchStream =
+            Box::pin(MemoryStream::try_new(vec![batch], schema, None)?);
+        service
+            .executor
+            .write_partition(&partition_id, &mut stream)
+            .await?;
+        let descriptor = path_descriptor(&[&partition_id.job_uuid.to_string(), "0", "0"]);
+
+        let info = service
+            .get_flight_info(Request::new(descriptor.clone()))
+            .await?
+            .into_inner();
+        assert_eq!(3, info.total_records);
+        assert_eq!(Some(descriptor.clone()), info.flight_descriptor);
+        assert_eq!(1, info.endpoint.len());
+
+        // a partition that is still being written is left out of the listing
+        let partial = PartitionId::new(Uuid::new_v4(), 1, 0);
+        let path = service.executor.partition_file(&partial, None);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, b"")?;
+        let infos: Vec<FlightInfo> = service
+            .list_flights(Request::new(Criteria { expression: vec![] }))
+            .await?
+            .into_inner()
+            .map(|info| info.unwrap())
+            .collect()
+            .await;
+        assert_eq!(1, infos.len());
+        assert_eq!(-1, infos[0].total_records);
+        assert_eq!(Some(descriptor), infos[0].flight_descriptor);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_admin_actions() -> Result<(), BallistaError> {
+        let work_dir = tempfile::TempDir::new()?;
+        let service = flight_service(work_dir.path().to_str().unwrap()).await?;
+        let actions: Vec<String> = service
+            .list_actions(Request::new(Empty {}))
+            .await?
+            .into_inner()
+            .map(|action| action.unwrap().r#type)
+            .collect()
+            .await;
+        assert_eq!(
+            vec![
+                "cancel_job",
+                "remove_job_data",
+                "remove_uploaded_table",
+                REMOVE_EXPIRED_JOBS_ACTION,
+                WORK_DIR_USAGE_ACTION,
+            ],
+            actions
+        );
+        Ok(())
+    }
+}
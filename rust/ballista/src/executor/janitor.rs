@@ -0,0 +1,70 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The janitor removes the work files of jobs that have not been written for longer than the
+//! configured time to live, such as the files of jobs whose scheduler was lost before it could
+//! tell the executor to remove them.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::executor::BallistaExecutor;
+
+use log::{info, warn};
+
+/// Remove the work files of expired jobs at every interval
+pub async fn janitor_loop(executor: Arc<BallistaExecutor>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let executor = executor.clone();
+        let result = tokio::task::spawn_blocking(move || executor.remove_expired_jobs()).await;
+        match result {
+            Ok(Ok(removed)) if !removed.is_empty() => {
+                info!("Removed the work files of {} expired jobs", removed.len())
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Could not remove the work files of expired jobs: {}", e),
+            Err(e) => warn!("Janitor failed: {}", e),
+        }
+    }
+}
+
+/// Most recent modification time of a file, or of a directory and any file below it
+pub(crate) fn last_modified(path: &Path) -> io::Result<SystemTime> {
+    let metadata = fs::metadata(path)?;
+    let mut modified = metadata.modified()?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            modified = modified.max(last_modified(&entry?.path())?);
+        }
+    }
+    Ok(modified)
+}
+
+/// Number of bytes of a file, or of the files below a directory
+pub(crate) fn disk_usage(path: &Path) -> io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        let mut bytes = 0;
+        for entry in fs::read_dir(path)? {
+            bytes += disk_usage(&entry?.path())?;
+        }
+        Ok(bytes)
+    } else {
+        Ok(metadata.len())
+    }
+}
@@ -14,25 +14,53 @@
 
 //! Core executor logic for executing queries and storing results in memory.
 
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::compression::ShuffleCompression;
+use crate::error::{BallistaError, Result};
+use crate::receiver_stream::DEFAULT_BUFFER_SIZE;
+use crate::scheduler::execution_plans::QueryStageExec;
 use crate::scheduler::planner::DistributedPlanner;
-use crate::serde::scheduler::ExecutorMeta;
-use crate::utils;
-use crate::{error::Result, serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient};
+use crate::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
+use crate::serde::scheduler::{ExecutorMeta, PartitionId, UPLOAD_STAGE_ID};
+use crate::utils::{self, PartitionStats};
 
 use arrow::record_batch::RecordBatch;
 use datafusion::execution::context::ExecutionContext;
 use datafusion::logical_plan::LogicalPlan;
-use datafusion::physical_plan::collect;
-use log::{debug, info};
+use datafusion::physical_plan::{collect, ExecutionPlan, Partitioning, SendableRecordBatchStream};
+use futures::future::{AbortHandle, Abortable};
+use log::{debug, info, warn};
+use tokio::sync::Semaphore;
+use tokio::task;
 use tonic::transport::Channel;
+use uuid::Uuid;
 
 pub mod collect;
+pub mod execution_loop;
 pub mod flight_service;
+pub mod janitor;
+pub mod shuffle_stream;
 
 #[cfg(feature = "snmalloc")]
 #[global_allocator]
 static ALLOC: snmalloc_rs::SnMalloc = snmalloc_rs::SnMalloc;
 
+/// Number of messages that are read ahead of the client when a partition is fetched, unless
+/// configured otherwise
+pub const DEFAULT_READ_AHEAD_BATCHES: usize = DEFAULT_BUFFER_SIZE;
+
+/// Time for which tasks of a cancelled job are refused. A cancelled job is normally forgotten
+/// when the scheduler removes its data, so this only applies to jobs whose data is never
+/// removed, for example because the scheduler was lost. Such a scheduler may still hand out
+/// tasks of the job long after the cancellation, so they are refused for an hour rather than a
+/// few polls, while the cancelled jobs that are remembered stay bounded.
+const CANCELLED_JOB_RETENTION: Duration = Duration::from_secs(3600);
+
 #[derive(Debug, Clone)]
 
 pub struct ExecutorConfig {
@@ -40,7 +68,17 @@ pub struct ExecutorConfig {
     pub(crate) port: u16,
     /// Directory for temporary files, such as IPC files
     pub(crate) work_dir: String,
+    /// Maximum number of partitions that can be executed concurrently
     pub(crate) concurrent_tasks: usize,
+    /// Codec that shuffle files are compressed with
+    pub(crate) shuffle_compression: ShuffleCompression,
+    /// Time after which the work files of a job that are no longer written are removed
+    pub(crate) job_data_ttl: Option<Duration>,
+    /// Maximum number of bytes in the work directory. No new tasks are accepted once it is
+    /// reached.
+    pub(crate) work_dir_quota: Option<u64>,
+    /// Number of messages that are read ahead of the client when a partition is fetched
+    pub(crate) read_ahead_batches: usize,
 }
 
 impl ExecutorConfig {
@@ -50,18 +88,719 @@ impl ExecutorConfig {
             port,
             work_dir: work_dir.to_owned(),
             concurrent_tasks,
+            shuffle_compression: ShuffleCompression::None,
+            job_data_ttl: None,
+            work_dir_quota: None,
+            read_ahead_batches: DEFAULT_READ_AHEAD_BATCHES,
         }
     }
+
+    pub fn with_shuffle_compression(mut self, shuffle_compression: ShuffleCompression) -> Self {
+        self.shuffle_compression = shuffle_compression;
+        self
+    }
+
+    pub fn with_job_data_ttl(mut self, job_data_ttl: Duration) -> Self {
+        self.job_data_ttl = Some(job_data_ttl);
+        self
+    }
+
+    pub fn with_work_dir_quota(mut self, work_dir_quota: u64) -> Self {
+        self.work_dir_quota = Some(work_dir_quota);
+        self
+    }
+
+    pub fn with_read_ahead_batches(mut self, read_ahead_batches: usize) -> Self {
+        self.read_ahead_batches = read_ahead_batches;
+        self
+    }
 }
 
+/// Directory of the work directory holding the tables uploaded by clients. Uploaded tables are
+/// kept until they are removed by the client, so they are not removed with job files.
+const UPLOADS_DIR: &str = "uploads";
+
 #[allow(dead_code)]
 pub struct BallistaExecutor {
     pub(crate) config: ExecutorConfig,
     scheduler: SchedulerGrpcClient<Channel>,
+    /// One permit per task slot. Partitions wait for a free slot before they are executed.
+    task_slots: Semaphore,
+    /// Tasks that are being executed, by job, so that they can be aborted when their job is
+    /// cancelled
+    running_tasks: Mutex<RunningTasks>,
+    /// Bytes of the files in the work directory
+    work_dir_usage: AtomicU64,
+}
+
+#[derive(Default)]
+struct RunningTasks {
+    /// Abort handles of the running tasks of each job, by stage and partition
+    tasks: HashMap<Uuid, HashMap<(usize, usize), AbortHandle>>,
+    /// Jobs that have been cancelled, with the time of their cancellation. Tasks of these jobs
+    /// are not started.
+    cancelled_jobs: HashMap<Uuid, Instant>,
+    /// Jobs whose expired work files are being removed. Tasks of these jobs are not started
+    /// until the removal has finished.
+    removing_jobs: HashSet<Uuid>,
+}
+
+impl RunningTasks {
+    /// Forget the jobs that were cancelled longer ago than the retention of cancelled jobs
+    fn prune_cancelled_jobs(&mut self) {
+        self.cancelled_jobs
+            .retain(|_, cancelled_at| cancelled_at.elapsed() < CANCELLED_JOB_RETENTION);
+    }
 }
 
 impl BallistaExecutor {
-    pub fn new(config: ExecutorConfig, scheduler: SchedulerGrpcClient<Channel>) -> Self {
-        Self { config, scheduler }
+    /// Create an executor. The work directory can hold the files of a previous run, which are
+    /// counted on a blocking thread.
+    pub async fn new(config: ExecutorConfig, scheduler: SchedulerGrpcClient<Channel>) -> Self {
+        let work_dir = PathBuf::from(&config.work_dir);
+        let work_dir_usage = task::spawn_blocking(move || janitor::disk_usage(&work_dir))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| bytes.map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                warn!("Could not compute the size of the work directory: {}", e);
+                0
+            });
+        let task_slots = Semaphore::new(config.concurrent_tasks);
+        Self {
+            config,
+            scheduler,
+            task_slots,
+            running_tasks: Mutex::new(RunningTasks::default()),
+            work_dir_usage: AtomicU64::new(work_dir_usage),
+        }
+    }
+
+    /// The number of task slots that are not currently executing a partition
+    pub fn available_task_slots(&self) -> usize {
+        self.task_slots.available_permits()
+    }
+
+    /// Bytes of the files in the work directory
+    pub fn work_dir_usage(&self) -> u64 {
+        self.work_dir_usage.load(Ordering::SeqCst)
+    }
+
+    /// Whether the work directory has reached its quota, in which case no new tasks are
+    /// accepted
+    pub fn work_dir_quota_exceeded(&self) -> bool {
+        match self.config.work_dir_quota {
+            Some(quota) => self.work_dir_usage() >= quota,
+            None => false,
+        }
+    }
+
+    /// Remove a directory of the work directory. Only the removed files are counted to keep
+    /// track of the usage of the work directory, rather than the whole work directory.
+    fn remove_work_files(&self, path: &Path) -> Result<()> {
+        let bytes = janitor::disk_usage(path)?;
+        std::fs::remove_dir_all(path)?;
+        // files of aborted tasks were never counted, so the usage cannot go below zero
+        let _ = self
+            .work_dir_usage
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |usage| {
+                Some(usage.saturating_sub(bytes))
+            });
+        Ok(())
+    }
+
+    /// Execute one partition of a query stage and write the results to the work directory.
+    /// Returns the path of the results file together with statistics about the partition. For
+    /// stages that hash-partition their output, the path is the directory of the output files.
+    /// The execution is aborted if the job is cancelled, and refused if the work directory has
+    /// reached its quota.
+    pub async fn execute_partition(
+        &self,
+        job_uuid: Uuid,
+        stage_id: usize,
+        part: usize,
+        plan: Arc<dyn ExecutionPlan>,
+    ) -> Result<(String, PartitionStats)> {
+        if self.work_dir_quota_exceeded() {
+            return Err(BallistaError::General(format!(
+                "Work directory {} has reached its quota of {} bytes",
+                self.config.work_dir,
+                self.config.work_dir_quota.unwrap_or_default()
+            )));
+        }
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        {
+            let mut running_tasks = self.running_tasks.lock().unwrap();
+            if running_tasks.cancelled_jobs.contains_key(&job_uuid) {
+                return Err(BallistaError::General(format!(
+                    "Job {} has been cancelled",
+                    job_uuid
+                )));
+            }
+            if running_tasks.removing_jobs.contains(&job_uuid) {
+                return Err(BallistaError::General(format!(
+                    "The expired work files of job {} are being removed",
+                    job_uuid
+                )));
+            }
+            running_tasks
+                .tasks
+                .entry(job_uuid)
+                .or_default()
+                .insert((stage_id, part), abort_handle);
+        }
+
+        let result = Abortable::new(
+            self.execute_partition_internal(job_uuid, stage_id, part, plan),
+            abort_registration,
+        )
+        .await;
+
+        {
+            let mut running_tasks = self.running_tasks.lock().unwrap();
+            if let Some(tasks) = running_tasks.tasks.get_mut(&job_uuid) {
+                tasks.remove(&(stage_id, part));
+                if tasks.is_empty() {
+                    running_tasks.tasks.remove(&job_uuid);
+                }
+            }
+        }
+
+        result.unwrap_or_else(|_| {
+            Err(BallistaError::General(format!(
+                "Task {}/{}/{} was cancelled",
+                job_uuid, stage_id, part
+            )))
+        })
+    }
+
+    /// Write record batches that were uploaded by a client as a partition held by this
+    /// executor, so that the partition can be fetched like the output of a query stage. The
+    /// upload is refused if the work directory has reached its quota.
+    pub async fn write_partition(
+        &self,
+        partition_id: &PartitionId,
+        stream: &mut SendableRecordBatchStream,
+    ) -> Result<PartitionStats> {
+        if partition_id.stage_id != UPLOAD_STAGE_ID {
+            return Err(BallistaError::General(format!(
+                "Uploaded partitions must have stage id {}, not {}",
+                UPLOAD_STAGE_ID, partition_id.stage_id
+            )));
+        }
+        if self.work_dir_quota_exceeded() {
+            return Err(BallistaError::General(format!(
+                "Work directory {} has reached its quota of {} bytes",
+                self.config.work_dir,
+                self.config.work_dir_quota.unwrap_or_default()
+            )));
+        }
+        let path = self.partition_file(partition_id, None);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let path = path.to_str().unwrap();
+        info!("Writing uploaded partition {:?} to {}", partition_id, path);
+        let stats =
+            utils::write_stream_to_disk(stream, path, self.config.shuffle_compression).await?;
+        self.work_dir_usage
+            .fetch_add(stats.compressed_bytes(), Ordering::SeqCst);
+        Ok(stats)
+    }
+
+    /// Abort the running tasks of a cancelled job and remove the files that the job wrote to
+    /// the work directory. Tasks of the job that are received later are not executed. The files
+    /// are removed synchronously, so this should be called from a blocking thread.
+    pub fn cancel_job(&self, job_uuid: &Uuid) -> Result<()> {
+        {
+            let mut running_tasks = self.running_tasks.lock().unwrap();
+            running_tasks.prune_cancelled_jobs();
+            running_tasks
+                .cancelled_jobs
+                .insert(*job_uuid, Instant::now());
+            if let Some(tasks) = running_tasks.tasks.remove(job_uuid) {
+                info!("Aborting {} running tasks of job {}", tasks.len(), job_uuid);
+                for abort_handle in tasks.values() {
+                    abort_handle.abort();
+                }
+            }
+        }
+
+        let mut path = PathBuf::from(&self.config.work_dir);
+        path.push(&format!("{}", job_uuid));
+        if path.exists() {
+            info!("Removing work files of job {} in {:?}", job_uuid, path);
+            self.remove_work_files(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the partitions of a table that was uploaded by a client. Like the other removals,
+    /// this should be called from a blocking thread.
+    pub fn remove_uploaded_table(&self, table_uuid: &Uuid) -> Result<()> {
+        let mut path = PathBuf::from(&self.config.work_dir);
+        path.push(UPLOADS_DIR);
+        path.push(&format!("{}", table_uuid));
+        if path.exists() {
+            info!("Removing uploaded table {} in {:?}", table_uuid, path);
+            self.remove_work_files(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the work files of a finished job, except for the output of the given stages,
+    /// which is still needed when the stage produced the results of the job. This should be
+    /// called from a blocking thread.
+    pub fn remove_job_data(&self, job_uuid: &Uuid, keep_stages: &[usize]) -> Result<()> {
+        // no more tasks of a finished job are assigned, so its cancellation can be forgotten
+        self.running_tasks
+            .lock()
+            .unwrap()
+            .cancelled_jobs
+            .remove(job_uuid);
+        let mut path = PathBuf::from(&self.config.work_dir);
+        path.push(&format!("{}", job_uuid));
+        if !path.exists() {
+            return Ok(());
+        }
+        if keep_stages.is_empty() {
+            info!("Removing work files of job {} in {:?}", job_uuid, path);
+            self.remove_work_files(&path)?;
+        } else {
+            for entry in std::fs::read_dir(&path)? {
+                let entry = entry?;
+                let keep = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse::<usize>().ok())
+                    .map(|stage_id| keep_stages.contains(&stage_id))
+                    .unwrap_or(false);
+                if !keep {
+                    info!(
+                        "Removing work files of job {} in {:?}",
+                        job_uuid,
+                        entry.path()
+                    );
+                    self.remove_work_files(&entry.path())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of the file that holds a partition that was executed by this executor, or one output
+    /// partition of a hash-partitioned partition. Partitions of uploaded tables are held in the
+    /// uploads directory rather than with the files of the jobs.
+    pub fn partition_file(
+        &self,
+        partition_id: &PartitionId,
+        output_partition: Option<usize>,
+    ) -> PathBuf {
+        let mut path = PathBuf::from(&self.config.work_dir);
+        if partition_id.stage_id == UPLOAD_STAGE_ID {
+            path.push(UPLOADS_DIR);
+            path.push(&format!("{}", partition_id.job_uuid));
+        } else {
+            path.push(&format!("{}", partition_id.job_uuid));
+            path.push(&format!("{}", partition_id.stage_id));
+        }
+        path.push(&format!("{}", partition_id.partition_id));
+        match output_partition {
+            Some(output_partition) => {
+                path.push(utils::shuffle_partition_file_name(output_partition))
+            }
+            None => path.push("data.arrow"),
+        }
+        path
+    }
+
+    /// The partitions held in the work directory, optionally of one job or uploaded table only,
+    /// together with the output partition for the files of hash-partitioned partitions
+    pub fn shuffle_partitions(
+        &self,
+        job_uuid: Option<&Uuid>,
+    ) -> Result<Vec<(PartitionId, Option<usize>)>> {
+        let mut partitions = vec![];
+        for job_dir in std::fs::read_dir(&self.config.work_dir)? {
+            let job_dir = job_dir?;
+            let job = match parse_file_name(&job_dir.path(), |name| Uuid::parse_str(name).ok()) {
+                Some(job) if job_uuid.map(|j| *j == job).unwrap_or(true) => job,
+                _ => continue,
+            };
+            for stage_dir in std::fs::read_dir(job_dir.path())? {
+                let stage_dir = stage_dir?;
+                let stage_id = match parse_file_name(&stage_dir.path(), |name| name.parse().ok()) {
+                    Some(stage_id) => stage_id,
+                    None => continue,
+                };
+                stage_partitions(job, stage_id, &stage_dir.path(), &mut partitions)?;
+            }
+        }
+
+        let uploads_dir = Path::new(&self.config.work_dir).join(UPLOADS_DIR);
+        if uploads_dir.exists() {
+            for table_dir in std::fs::read_dir(uploads_dir)? {
+                let table_dir = table_dir?;
+                let table =
+                    match parse_file_name(&table_dir.path(), |name| Uuid::parse_str(name).ok()) {
+                        Some(table) if job_uuid.map(|j| *j == table).unwrap_or(true) => table,
+                        _ => continue,
+                    };
+                stage_partitions(table, UPLOAD_STAGE_ID, &table_dir.path(), &mut partitions)?;
+            }
+        }
+        partitions.sort();
+        Ok(partitions)
+    }
+
+    /// Remove the work files of the jobs that have no running tasks and have not been written
+    /// for longer than the time to live of job data. Returns the jobs whose files were removed.
+    ///
+    /// Only the tasks running on this executor are known here, so the files of a job that is
+    /// still running on other executors are removed once they are older than the time to live.
+    /// The time to live must therefore be longer than the longest job.
+    pub fn remove_expired_jobs(&self) -> Result<Vec<Uuid>> {
+        self.running_tasks.lock().unwrap().prune_cancelled_jobs();
+        let ttl = match self.config.job_data_ttl {
+            Some(ttl) => ttl,
+            None => return Ok(vec![]),
+        };
+        let now = SystemTime::now();
+        let mut removed = vec![];
+        for entry in std::fs::read_dir(&self.config.work_dir)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Could not read the work directory: {}", e);
+                    continue;
+                }
+            };
+            // the work directory holds job directories and the uploads directory, whose tables
+            // are only removed by their clients. Other files are left alone.
+            let job_uuid = match entry
+                .file_name()
+                .to_str()
+                .and_then(|name| Uuid::parse_str(name).ok())
+            {
+                Some(job_uuid) => job_uuid,
+                None => continue,
+            };
+            // tasks are registered before they create their directories, so marking the job
+            // keeps new tasks from writing to a directory that is being removed. The lock is
+            // not held while the files are walked, since starting and finishing tasks need it.
+            {
+                let mut running_tasks = self.running_tasks.lock().unwrap();
+                if running_tasks.tasks.contains_key(&job_uuid)
+                    || !running_tasks.removing_jobs.insert(job_uuid)
+                {
+                    continue;
+                }
+            }
+            let result = self.remove_job_if_expired(&job_uuid, &entry.path(), now, ttl);
+            self.running_tasks
+                .lock()
+                .unwrap()
+                .removing_jobs
+                .remove(&job_uuid);
+            match result {
+                Ok(true) => removed.push(job_uuid),
+                Ok(false) => {}
+                // the files may have been removed concurrently, for example by RemoveJobData
+                Err(e) => warn!(
+                    "Could not remove the expired work files of job {}: {}",
+                    job_uuid, e
+                ),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove the work files of a job if they have not been written for longer than the time
+    /// to live. Returns true if they were removed.
+    fn remove_job_if_expired(
+        &self,
+        job_uuid: &Uuid,
+        path: &Path,
+        now: SystemTime,
+        ttl: Duration,
+    ) -> Result<bool> {
+        let age = now
+            .duration_since(janitor::last_modified(path)?)
+            .unwrap_or_default();
+        if age <= ttl {
+            return Ok(false);
+        }
+        info!(
+            "Removing work files of job {}, which were last written {} seconds ago",
+            job_uuid,
+            age.as_secs()
+        );
+        self.remove_work_files(path)?;
+        Ok(true)
+    }
+
+    async fn execute_partition_internal(
+        &self,
+        job_uuid: Uuid,
+        stage_id: usize,
+        part: usize,
+        plan: Arc<dyn ExecutionPlan>,
+    ) -> Result<(String, PartitionStats)> {
+        let _permit =
+            self.task_slots.acquire().await.map_err(|e| {
+                BallistaError::General(format!("Could not acquire task slot: {}", e))
+            })?;
+
+        let mut path = PathBuf::from(&self.config.work_dir);
+        path.push(&format!("{}", job_uuid));
+        path.push(&format!("{}", stage_id));
+        path.push(&format!("{}", part));
+        std::fs::create_dir_all(&path)?;
+
+        let now = Instant::now();
+
+        // execute the query partition
+        let mut stream = plan.execute(part).await?;
+
+        // stream results to disk, with one file per output partition when the stage
+        // hash-partitions its output
+        let shuffle_output_partitioning = plan
+            .as_any()
+            .downcast_ref::<QueryStageExec>()
+            .and_then(|stage| stage.shuffle_output_partitioning.clone());
+        let (path, stats) = match shuffle_output_partitioning {
+            Some(Partitioning::Hash(exprs, num_partitions)) => {
+                let path = path.to_str().unwrap().to_owned();
+                info!("Writing hash-partitioned results to {}", path);
+                let stats = utils::write_hash_partitioned_stream_to_disk(
+                    &mut stream,
+                    &exprs,
+                    num_partitions,
+                    &path,
+                    self.config.shuffle_compression,
+                )
+                .await?;
+                (path, stats)
+            }
+            _ => {
+                path.push("data.arrow");
+                let path = path.to_str().unwrap().to_owned();
+                info!("Writing results to {}", path);
+                let stats = utils::write_stream_to_disk(
+                    &mut stream,
+                    &path,
+                    self.config.shuffle_compression,
+                )
+                .await?;
+                (path, stats)
+            }
+        };
+
+        // report the Parquet row groups that were skipped by the pushed-down predicates
+        let (skipped_rows, skipped_bytes) = utils::skipped_row_groups(plan.as_ref(), part);
+        let stats = stats.with_skipped(skipped_rows, skipped_bytes);
+        self.work_dir_usage
+            .fetch_add(stats.compressed_bytes(), Ordering::SeqCst);
+
+        info!(
+            "Executed partition {} in {} seconds. Statistics: {:?}",
+            part,
+            now.elapsed().as_secs(),
+            stats
+        );
+
+        Ok((path, stats))
+    }
+}
+
+/// Add the partitions held in the directory of a query stage, or of an uploaded table
+fn stage_partitions(
+    job_uuid: Uuid,
+    stage_id: usize,
+    stage_dir: &Path,
+    partitions: &mut Vec<(PartitionId, Option<usize>)>,
+) -> Result<()> {
+    for partition_dir in std::fs::read_dir(stage_dir)? {
+        let partition_dir = partition_dir?;
+        let partition_id = match parse_file_name(&partition_dir.path(), |name| name.parse().ok()) {
+            Some(partition_id) => PartitionId::new(job_uuid, stage_id, partition_id),
+            None => continue,
+        };
+        for file in std::fs::read_dir(partition_dir.path())? {
+            let output_partition = parse_file_name(&file?.path(), |name| {
+                if name == "data.arrow" {
+                    Some(None)
+                } else {
+                    name.strip_prefix("data-")
+                        .and_then(|name| name.strip_suffix(".arrow"))
+                        .and_then(|n| n.parse().ok())
+                        .map(Some)
+                }
+            });
+            if let Some(output_partition) = output_partition {
+                partitions.push((partition_id, output_partition));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse the name of a file in the work directory
+fn parse_file_name<T>(path: &Path, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::transport::Endpoint;
+
+    async fn executor(work_dir: &str) -> Result<BallistaExecutor> {
+        let channel = Endpoint::from_static("http://localhost:50050").connect_lazy()?;
+        let config = ExecutorConfig::new("localhost", 50051, work_dir, 1)
+            .with_job_data_ttl(Duration::from_secs(0))
+            .with_work_dir_quota(10);
+        Ok(BallistaExecutor::new(config, SchedulerGrpcClient::new(channel)).await)
+    }
+
+    #[tokio::test]
+    async fn remove_job_data() -> Result<()> {
+        let work_dir = tempfile::TempDir::new()?;
+        let job_uuid = Uuid::new_v4();
+        for stage_id in 1..=2 {
+            let mut path = work_dir.path().join(job_uuid.to_string());
+            path.push(stage_id.to_string());
+            path.push("0");
+            std::fs::create_dir_all(&path)?;
+            std::fs::write(path.join("data.arrow"), b"0123456789")?;
+        }
+        let executor = executor(work_dir.path().to_str().unwrap()).await?;
+        assert_eq!(20, executor.work_dir_usage());
+        assert!(executor.work_dir_quota_exceeded());
+
+        // the output of the final stage is kept
+        executor.remove_job_data(&job_uuid, &[2])?;
+        let job_dir = work_dir.path().join(job_uuid.to_string());
+        assert!(!job_dir.join("1").exists());
+        assert!(job_dir.join("2").exists());
+        assert_eq!(10, executor.work_dir_usage());
+
+        // files that are not job directories are left alone
+        std::fs::write(work_dir.path().join("other"), b"0")?;
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(vec![job_uuid], executor.remove_expired_jobs()?);
+        assert!(!job_dir.exists());
+        assert!(work_dir.path().join("other").exists());
+        assert!(!executor.work_dir_quota_exceeded());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forget_cancelled_jobs() -> Result<()> {
+        let work_dir = tempfile::TempDir::new()?;
+        let executor = executor(work_dir.path().to_str().unwrap()).await?;
+        let job_uuid = Uuid::new_v4();
+        executor.cancel_job(&job_uuid)?;
+        let cancelled = |executor: &BallistaExecutor, job_uuid: &Uuid| {
+            let running_tasks = executor.running_tasks.lock().unwrap();
+            running_tasks.cancelled_jobs.contains_key(job_uuid)
+        };
+        assert!(cancelled(&executor, &job_uuid));
+        executor.remove_job_data(&job_uuid, &[])?;
+        assert!(!cancelled(&executor, &job_uuid));
+
+        // cancellations are forgotten after the retention even without removing the job data
+        if let Some(cancelled_at) = Instant::now().checked_sub(CANCELLED_JOB_RETENTION) {
+            executor
+                .running_tasks
+                .lock()
+                .unwrap()
+                .cancelled_jobs
+                .insert(job_uuid, cancelled_at);
+            executor.remove_expired_jobs()?;
+            assert!(!cancelled(&executor, &job_uuid));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_shuffle_partitions() -> Result<()> {
+        let work_dir = tempfile::TempDir::new()?;
+        let executor = executor(work_dir.path().to_str().unwrap()).await?;
+        let job_uuid = Uuid::new_v4();
+        let files = vec![
+            (PartitionId::new(job_uuid, 1, 0), Some(1)),
+            (PartitionId::new(job_uuid, 1, 0), Some(0)),
+            (PartitionId::new(job_uuid, 2, 0), None),
+        ];
+        for (partition_id, output_partition) in &files {
+            let path = executor.partition_file(partition_id, *output_partition);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, b"")?;
+        }
+        let other_job = PartitionId::new(Uuid::new_v4(), 1, 0);
+        let path = executor.partition_file(&other_job, None);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, b"")?;
+
+        assert_eq!(
+            vec![files[1], files[0], files[2]],
+            executor.shuffle_partitions(Some(&job_uuid))?
+        );
+        assert_eq!(4, executor.shuffle_partitions(None)?.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_uploaded_partition() -> Result<()> {
+        use crate::compression::{open_shuffle_file, read_shuffle_file};
+        use crate::memory_stream::MemoryStream;
+        use arrow::array::UInt32Array;
+        use arrow::datatypes::{DataType, Field, Schema};
+
+        let work_dir = tempfile::TempDir::new()?;
+        let executor = executor(work_dir.path().to_str().unwrap()).await?;
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::UInt32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(UInt32Array::from(vec![1, 2, 3]))],
+        )?;
+        let partition_id = PartitionId::new(Uuid::new_v4(), UPLOAD_STAGE_ID, 1);
+        let mut stream: SendableRecordBatchStream =
+            Box::pin(MemoryStream::try_new(vec![batch], schema.clone(), None)?);
+        let stats = executor.write_partition(&partition_id, &mut stream).await?;
+        assert_eq!(3, stats.num_rows());
+        assert_eq!(stats.compressed_bytes(), executor.work_dir_usage());
+        assert_eq!(
+            vec![(partition_id, None)],
+            executor.shuffle_partitions(None)?
+        );
+
+        let path = executor.partition_file(&partition_id, None);
+        let (compression, file) = open_shuffle_file(path.to_str().unwrap())?;
+        let reader = read_shuffle_file(compression, file)?;
+        assert_eq!(schema, reader.schema());
+        assert_eq!(1, reader.count());
+
+        // uploads are refused once the quota is reached
+        let mut stream: SendableRecordBatchStream =
+            Box::pin(MemoryStream::try_new(vec![], schema, None)?);
+        assert!(executor
+            .write_partition(&partition_id, &mut stream)
+            .await
+            .is_err());
+
+        // uploaded tables are kept by the janitor until they are removed
+        let table_dir = work_dir.path().join(UPLOADS_DIR);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(executor.remove_expired_jobs()?.is_empty());
+        assert!(path.exists());
+        assert!(path.starts_with(&table_dir));
+        executor.remove_uploaded_table(&partition_id.job_uuid)?;
+        assert!(!path.exists());
+        assert_eq!(0, executor.work_dir_usage());
+        Ok(())
     }
 }
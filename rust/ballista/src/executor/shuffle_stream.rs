@@ -0,0 +1,395 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streaming of shuffle files as Flight data. Uncompressed shuffle files are read with Tokio and
+//! their Arrow IPC messages are sent as they are, without decoding the record batches.
+//! Compressed files are sent as is to clients that accept their codec, and are only decoded on
+//! a blocking thread for clients that do not.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::compression::{
+    open_shuffle_file_async, read_shuffle_file, ShuffleCompression, COMPRESSION_KEY,
+    NEXT_PARTITION_MARKER,
+};
+use crate::error::{BallistaError, Result};
+use crate::executor::flight_service::{from_ballista_err, BoxedFlightStream};
+
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow_flight::utils::{flight_data_from_arrow_batch, flight_data_from_arrow_schema};
+use arrow_flight::FlightData;
+use futures::Stream;
+use log::warn;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task;
+use tonic::metadata::MetadataValue;
+use tonic::{Response, Status};
+
+/// Size of the chunks that compressed shuffle files are sent in
+const COMPRESSED_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Size of the buffer that uncompressed shuffle files are read through
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Magic bytes that Arrow IPC files start with, followed by two bytes of padding
+const ARROW_MAGIC: &[u8] = b"ARROW1";
+
+/// Marker that precedes the length of an IPC message
+const CONTINUATION_MARKER: [u8; 4] = [0xff; 4];
+
+type FlightDataResult = std::result::Result<FlightData, Status>;
+
+/// Stream the shuffle files of one or more partitions as Flight data, reading at most
+/// `read_ahead` messages ahead of the client. The schema is sent once, followed by the
+/// dictionaries and record batches of every file in turn. When every file is compressed with
+/// the same codec and the client accepts it, the compressed files are sent in chunks instead,
+/// separated by a message whose application metadata is [`NEXT_PARTITION_MARKER`], and the
+/// codec is named in the response metadata.
+pub(crate) async fn stream_partition_files(
+    paths: Vec<String>,
+    accepted_compression: &[ShuffleCompression],
+    read_ahead: usize,
+) -> Result<Response<BoxedFlightStream<FlightData>>> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in &paths {
+        files.push(open_shuffle_file_async(path).await?);
+    }
+    let send_compressed =
+        files
+            .first()
+            .map(|(compression, _)| *compression)
+            .filter(|compression| {
+                *compression != ShuffleCompression::None
+                    && accepted_compression.contains(compression)
+                    && files.iter().all(|(other, _)| other == compression)
+            });
+
+    let (sender, receiver) = mpsc::channel(read_ahead.max(1));
+    tokio::spawn(async move {
+        let result = match send_compressed {
+            Some(_) => send_compressed_files(files, &sender).await,
+            None => send_files(files, &sender).await,
+        };
+        if let Err(e) = result {
+            warn!("Error streaming partitions {:?}: {:?}", paths, e);
+            // the client might already have dropped the stream
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+
+    let mut response =
+        Response::new(Box::pin(FlightDataStream { receiver }) as BoxedFlightStream<_>);
+    if let Some(compression) = send_compressed {
+        response.metadata_mut().insert(
+            COMPRESSION_KEY,
+            MetadataValue::from_static(compression.name()),
+        );
+    }
+    Ok(response)
+}
+
+/// Send the IPC messages of the files, decoding the files that are compressed
+async fn send_files(
+    files: Vec<(ShuffleCompression, File)>,
+    sender: &mpsc::Sender<FlightDataResult>,
+) -> std::result::Result<(), Status> {
+    for (i, (compression, file)) in files.into_iter().enumerate() {
+        // the schema is only sent for the first file
+        let send_schema = i == 0;
+        if compression == ShuffleCompression::None {
+            let mut reader = IpcMessageReader::try_new(file)
+                .await
+                .map_err(|e| from_ballista_err(&e))?;
+            let mut is_schema = true;
+            while let Some(message) = reader
+                .next_message()
+                .await
+                .map_err(|e| from_ballista_err(&e))?
+            {
+                if send_schema || !is_schema {
+                    send(sender, message).await?;
+                }
+                is_schema = false;
+            }
+        } else {
+            let file = file.into_std().await;
+            let sender = sender.clone();
+            task::spawn_blocking(move || {
+                send_decoded_batches(compression, file, send_schema, &sender)
+            })
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))??;
+        }
+    }
+    Ok(())
+}
+
+/// Decode a compressed file and send its record batches
+fn send_decoded_batches(
+    compression: ShuffleCompression,
+    file: std::fs::File,
+    send_schema: bool,
+    sender: &mpsc::Sender<FlightDataResult>,
+) -> std::result::Result<(), Status> {
+    let reader = read_shuffle_file(compression, file).map_err(|e| from_ballista_err(&e))?;
+    let options = IpcWriteOptions::default();
+    if send_schema {
+        let schema = flight_data_from_arrow_schema(reader.schema().as_ref(), &options);
+        blocking_send(sender, schema)?;
+    }
+    for batch in reader {
+        let batch = batch.map_err(|e| Status::internal(format!("ArrowError: {:?}", e)))?;
+        let (dictionaries, batch) = flight_data_from_arrow_batch(&batch, &options);
+        for data in dictionaries.into_iter().chain(std::iter::once(batch)) {
+            blocking_send(sender, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Send the compressed data of the files in chunks
+async fn send_compressed_files(
+    files: Vec<(ShuffleCompression, File)>,
+    sender: &mpsc::Sender<FlightDataResult>,
+) -> std::result::Result<(), Status> {
+    for (i, (_, mut file)) in files.into_iter().enumerate() {
+        if i > 0 {
+            let marker = FlightData {
+                app_metadata: NEXT_PARTITION_MARKER.to_vec(),
+                ..Default::default()
+            };
+            send(sender, marker).await?;
+        }
+        loop {
+            let mut chunk = Vec::with_capacity(COMPRESSED_CHUNK_SIZE);
+            let n = (&mut file)
+                .take(COMPRESSED_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to read partition file: {:?}", e)))?;
+            if n == 0 {
+                break;
+            }
+            let data = FlightData {
+                data_body: chunk,
+                ..Default::default()
+            };
+            send(sender, data).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn send(
+    sender: &mpsc::Sender<FlightDataResult>,
+    data: FlightData,
+) -> std::result::Result<(), Status> {
+    sender
+        .send(Ok(data))
+        .await
+        .map_err(|_| Status::cancelled("The client dropped the stream"))
+}
+
+fn blocking_send(
+    sender: &mpsc::Sender<FlightDataResult>,
+    data: FlightData,
+) -> std::result::Result<(), Status> {
+    sender
+        .blocking_send(Ok(data))
+        .map_err(|_| Status::cancelled("The client dropped the stream"))
+}
+
+/// Reader of the messages of an Arrow IPC file that does not decode them. Each message is
+/// returned as Flight data, which holds the same metadata and body as the IPC message.
+pub(crate) struct IpcMessageReader<R> {
+    reader: BufReader<R>,
+}
+
+impl<R: AsyncRead + Unpin> IpcMessageReader<R> {
+    /// Create a reader of an IPC file, checking the magic bytes at its start
+    pub(crate) async fn try_new(reader: R) -> Result<Self> {
+        let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, reader);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).await?;
+        if !magic.starts_with(ARROW_MAGIC) {
+            return Err(BallistaError::General(
+                "Partition file is not an Arrow IPC file".to_owned(),
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Read the next message, or `None` at the end of the stream of messages, which is
+    /// followed by the footer of the file
+    pub(crate) async fn next_message(&mut self) -> Result<Option<FlightData>> {
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len).await?;
+        // the length is not preceded by the marker in the legacy IPC format
+        if len == CONTINUATION_MARKER {
+            self.reader.read_exact(&mut len).await?;
+        }
+        let len = i32::from_le_bytes(len);
+        if len == 0 {
+            return Ok(None);
+        }
+        if len < 0 {
+            return Err(BallistaError::General(format!(
+                "Invalid IPC message length {}",
+                len
+            )));
+        }
+
+        let mut data_header = vec![0u8; len as usize];
+        self.reader.read_exact(&mut data_header).await?;
+        let message = arrow::ipc::root_as_message(&data_header)
+            .map_err(|e| BallistaError::General(format!("Failed to parse IPC message: {:?}", e)))?;
+        let mut data_body = vec![0u8; message.bodyLength() as usize];
+        self.reader.read_exact(&mut data_body).await?;
+        Ok(Some(FlightData {
+            data_header,
+            data_body,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Stream of the Flight data sent by the task that reads the shuffle files
+pub(crate) struct FlightDataStream {
+    receiver: mpsc::Receiver<FlightDataResult>,
+}
+
+impl Stream for FlightDataStream {
+    type Item = FlightDataResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::ShuffleWriter;
+    use arrow::array::{StringArray, UInt32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use arrow_flight::utils::flight_data_to_arrow_batch;
+    use futures::StreamExt;
+    use std::convert::TryFrom;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn write_file(
+        path: &Path,
+        batch: &RecordBatch,
+        compression: ShuffleCompression,
+    ) -> Result<String> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = ShuffleWriter::try_new(file, batch.schema().as_ref(), compression)?;
+        writer.write(batch)?;
+        writer.write(batch)?;
+        writer.finish()?;
+        Ok(path.to_str().unwrap().to_owned())
+    }
+
+    fn batch() -> Result<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::UInt32, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        Ok(RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(UInt32Array::from(vec![1, 2, 3])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            ],
+        )?)
+    }
+
+    #[tokio::test]
+    async fn stream_several_partitions() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let batch = batch()?;
+        let paths = vec![
+            write_file(
+                &dir.path().join("0.arrow"),
+                &batch,
+                ShuffleCompression::None,
+            )?,
+            write_file(&dir.path().join("1.arrow"), &batch, ShuffleCompression::Lz4)?,
+        ];
+
+        // the compressed file is decoded because the client does not accept its codec
+        let response = stream_partition_files(paths, &[], 1).await?;
+        assert!(response.metadata().get(COMPRESSION_KEY).is_none());
+        let messages = response
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<std::result::Result<Vec<_>, Status>>()
+            .unwrap();
+        assert_eq!(5, messages.len());
+        let schema = Arc::new(Schema::try_from(&messages[0])?);
+        assert_eq!(batch.schema(), schema);
+        for message in &messages[1..] {
+            let decoded = flight_data_to_arrow_batch(message, schema.clone(), &[])?;
+            assert_eq!(format!("{:?}", batch), format!("{:?}", decoded));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_compressed_partitions() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let batch = batch()?;
+        let paths = vec![
+            write_file(
+                &dir.path().join("0.arrow"),
+                &batch,
+                ShuffleCompression::Zstd,
+            )?,
+            write_file(
+                &dir.path().join("1.arrow"),
+                &batch,
+                ShuffleCompression::Zstd,
+            )?,
+        ];
+
+        let response = stream_partition_files(paths, &[ShuffleCompression::Zstd], 1).await?;
+        assert_eq!(
+            "zstd",
+            response
+                .metadata()
+                .get(COMPRESSION_KEY)
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        let messages = response
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<std::result::Result<Vec<_>, Status>>()
+            .unwrap();
+        // one chunk per file, separated by a marker
+        assert_eq!(3, messages.len());
+        assert_eq!(NEXT_PARTITION_MARKER, messages[1].app_metadata.as_slice());
+        Ok(())
+    }
+}
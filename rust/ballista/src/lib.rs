@@ -22,12 +22,15 @@ pub fn print_version() {
 
 pub mod client;
 pub mod columnar_batch;
+pub mod compression;
 pub mod context;
 pub mod error;
 pub mod executor;
 pub mod memory_stream;
 pub mod prelude;
+pub mod receiver_stream;
 pub mod scheduler;
+pub mod udf;
 pub mod utils;
 
 #[cfg(test)]
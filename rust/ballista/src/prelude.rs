@@ -18,6 +18,8 @@ pub use crate::{
     client::BallistaClient,
     context::BallistaContext,
     error::{BallistaError, Result},
+    scheduler::execution_plans::JsonReadOptions,
+    serde::extension::{register_extension_codec, BallistaCodec},
 };
 
 pub use futures::StreamExt;
@@ -0,0 +1,59 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A record batch stream that is fed by a background task through a bounded channel. The
+//! producer can only run ahead of the consumer by the capacity of the channel, which applies
+//! backpressure to the source of the batches, such as a Flight stream.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use arrow::{datatypes::SchemaRef, error::Result, record_batch::RecordBatch};
+use datafusion::physical_plan::RecordBatchStream;
+use futures::Stream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+/// Number of record batches that a producer can buffer ahead of the consumer
+pub const DEFAULT_BUFFER_SIZE: usize = 2;
+
+/// Stream over the record batches sent through a channel
+pub struct RecordBatchReceiverStream {
+    /// Schema of the record batches
+    schema: SchemaRef,
+    /// Receiving end of the channel
+    receiver: Receiver<Result<RecordBatch>>,
+}
+
+impl RecordBatchReceiverStream {
+    /// Create a stream together with the sender that feeds it. The stream ends once the
+    /// sender is dropped, and sends fail once the stream is dropped.
+    pub fn create(schema: SchemaRef, buffer_size: usize) -> (Sender<Result<RecordBatch>>, Self) {
+        let (sender, receiver) = mpsc::channel(buffer_size);
+        (sender, Self { schema, receiver })
+    }
+}
+
+impl Stream for RecordBatchReceiverStream {
+    type Item = Result<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl RecordBatchStream for RecordBatchReceiverStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::{any::Any, pin::Pin};
+
+use arrow::csv;
+use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
+use async_trait::async_trait;
+use datafusion::physical_plan::csv::CsvExec;
+use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use datafusion::{
+    error::{DataFusionError, Result},
+    physical_plan::RecordBatchStream,
+};
+use tokio::task;
+
+use super::FileSplit;
+use crate::receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE};
+
+/// CsvScanExec reads each of its splits, a byte range of a CSV file starting at the beginning
+/// of a line, as one partition. The options of the scan are those of the `CsvExec` that it
+/// replaces.
+#[derive(Debug)]
+pub struct CsvScanExec {
+    /// The scan that was split
+    pub(crate) csv: CsvExec,
+    /// Byte ranges read by each partition
+    pub(crate) splits: Vec<FileSplit>,
+    /// Number of rows per batch
+    batch_size: usize,
+}
+
+impl CsvScanExec {
+    pub fn new(csv: CsvExec, splits: Vec<FileSplit>, batch_size: usize) -> Self {
+        Self {
+            csv,
+            splits,
+            batch_size,
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for CsvScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.csv.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.splits.len())
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.is_empty() {
+            Ok(Arc::new(CsvScanExec::new(
+                self.csv.clone(),
+                self.splits.clone(),
+                self.batch_size,
+            )))
+        } else {
+            Err(DataFusionError::Plan(
+                "Ballista CsvScanExec does not have children".to_owned(),
+            ))
+        }
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
+        let split = self.splits[partition].clone();
+        // only the split at the start of a file contains the header
+        let has_header = self.csv.has_header() && split.start == 0;
+        let delimiter = self.csv.delimiter().cloned();
+        let projection = self.csv.projection().cloned();
+        let file_schema = self.csv.file_schema();
+        let batch_size = self.batch_size;
+        let (sender, stream) =
+            RecordBatchReceiverStream::create(self.schema(), DEFAULT_BUFFER_SIZE);
+        task::spawn_blocking(move || {
+            let result = File::open(&split.filename).and_then(|mut file| {
+                file.seek(SeekFrom::Start(split.start))?;
+                Ok(BufReader::new(file.take(split.end - split.start)))
+            });
+            match result {
+                Ok(reader) => {
+                    let batches = csv::Reader::new(
+                        reader,
+                        file_schema,
+                        has_header,
+                        delimiter,
+                        batch_size,
+                        None,
+                        projection,
+                    );
+                    for batch in batches {
+                        // sending fails when the consumer dropped the stream
+                        if sender.blocking_send(batch).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(ArrowError::from(e)));
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splitting of large files into ranges that are scanned as independent partitions.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+
+use datafusion::error::{DataFusionError, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+
+/// A range of a file that is scanned as one partition. The range is a range of row groups for
+/// Parquet files, and a range of bytes starting at the beginning of a line for CSV files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSplit {
+    pub filename: String,
+    /// First row group or byte of the range
+    pub start: u64,
+    /// End of the range, exclusive
+    pub end: u64,
+}
+
+impl FileSplit {
+    pub fn new(filename: &str, start: u64, end: u64) -> Self {
+        Self {
+            filename: filename.to_owned(),
+            start,
+            end,
+        }
+    }
+}
+
+/// Split a Parquet file into ranges of consecutive row groups of about `split_size` compressed
+/// bytes. A row group is never split, so a range can be larger than `split_size`.
+pub fn split_parquet_file(filename: &str, split_size: u64) -> Result<Vec<FileSplit>> {
+    check_split_size(split_size)?;
+    let reader = SerializedFileReader::new(File::open(filename)?)
+        .map_err(|e| DataFusionError::Execution(format!("{}: {:?}", filename, e)))?;
+    let row_groups = reader.metadata().row_groups();
+    if row_groups.is_empty() {
+        return Ok(vec![FileSplit::new(filename, 0, 0)]);
+    }
+
+    let mut splits = vec![];
+    let mut start = 0;
+    let mut size = 0;
+    for (i, row_group) in row_groups.iter().enumerate() {
+        size += row_group
+            .columns()
+            .iter()
+            .map(|column| column.compressed_size() as u64)
+            .sum::<u64>();
+        if size >= split_size {
+            splits.push(FileSplit::new(filename, start, i as u64 + 1));
+            start = i as u64 + 1;
+            size = 0;
+        }
+    }
+    if start < row_groups.len() as u64 {
+        splits.push(FileSplit::new(filename, start, row_groups.len() as u64));
+    }
+    Ok(splits)
+}
+
+/// Split a CSV file into byte ranges of about `split_size` bytes. Each range ends after a line
+/// break, so that every line is read by exactly one split. Line breaks within quoted values are
+/// not supported.
+pub fn split_csv_file(filename: &str, split_size: u64) -> Result<Vec<FileSplit>> {
+    check_split_size(split_size)?;
+    let len = std::fs::metadata(filename)?.len();
+    let mut reader = BufReader::new(File::open(filename)?);
+    let mut splits = vec![];
+    let mut start = 0;
+    let mut line = vec![];
+    while start < len {
+        let mut end = start + split_size;
+        if end < len {
+            // move the end of the range past the line break that ends the line containing
+            // the last byte of the range
+            reader.seek(SeekFrom::Start(end - 1))?;
+            line.clear();
+            end = end - 1 + reader.read_until(b'\n', &mut line)? as u64;
+        }
+        let end = end.min(len);
+        splits.push(FileSplit::new(filename, start, end));
+        start = end;
+    }
+    if splits.is_empty() {
+        splits.push(FileSplit::new(filename, 0, 0));
+    }
+    Ok(splits)
+}
+
+/// Ranges of zero bytes cannot cover a file. Files that should not be split are scanned whole
+/// instead of being passed to the split functions.
+fn check_split_size(split_size: u64) -> Result<()> {
+    if split_size == 0 {
+        return Err(DataFusionError::Plan(
+            "Files cannot be split into ranges of 0 bytes".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::Int32Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::ArrowWriter;
+
+    use super::*;
+
+    #[test]
+    fn split_parquet_row_groups() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.parquet");
+        let parquet_error = |e| DataFusionError::Execution(format!("{:?}", e));
+        let mut writer = ArrowWriter::try_new(File::create(&path)?, schema.clone(), None)
+            .map_err(parquet_error)?;
+        // each batch is written as a row group
+        for i in 0..3 {
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int32Array::from(vec![i; 100]))],
+            )?;
+            writer.write(&batch).map_err(parquet_error)?;
+        }
+        writer.close().map_err(parquet_error)?;
+        let filename = path.to_str().unwrap();
+
+        // a split size of one byte puts every row group in its own split
+        let splits = split_parquet_file(filename, 1)?;
+        assert_eq!(
+            splits,
+            vec![
+                FileSplit::new(filename, 0, 1),
+                FileSplit::new(filename, 1, 2),
+                FileSplit::new(filename, 2, 3),
+            ]
+        );
+
+        // the whole file fits in a large split
+        let splits = split_parquet_file(filename, u64::MAX)?;
+        assert_eq!(splits, vec![FileSplit::new(filename, 0, 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn split_csv_lines() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.csv");
+        std::fs::write(&path, b"a,1\nbb,2\nccc,3\n")?;
+        let filename = path.to_str().unwrap();
+
+        // every split ends after a line break
+        let splits = split_csv_file(filename, 5)?;
+        assert_eq!(
+            splits,
+            vec![
+                FileSplit::new(filename, 0, 9),
+                FileSplit::new(filename, 9, 15),
+            ]
+        );
+        assert!(split_csv_file(filename, 0).is_err());
+        Ok(())
+    }
+}
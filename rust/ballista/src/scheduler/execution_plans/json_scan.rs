@@ -0,0 +1,383 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scans of newline-delimited JSON files, which DataFusion does not support yet.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::{any::Any, pin::Pin};
+
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::json;
+use arrow::json::reader::infer_json_schema;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::datasource::datasource::Statistics;
+use datafusion::datasource::TableProvider;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::common::build_file_list;
+use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use datafusion::{
+    error::{DataFusionError, Result},
+    physical_plan::RecordBatchStream,
+};
+use tokio::task;
+
+use crate::receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE};
+
+/// Number of records that the schema of JSON files is inferred from by default
+pub const DEFAULT_SCHEMA_INFER_MAX_RECORDS: usize = 1000;
+
+/// Options for reading newline-delimited JSON files
+#[derive(Clone)]
+pub struct JsonReadOptions<'a> {
+    /// Schema of the files. When not set, the schema is inferred from the first records of the
+    /// first file.
+    pub schema: Option<&'a Schema>,
+    /// Maximum number of records to infer the schema from
+    pub schema_infer_max_records: usize,
+    /// Extension of the files to read in a directory
+    pub file_extension: &'a str,
+}
+
+impl<'a> JsonReadOptions<'a> {
+    pub fn new() -> Self {
+        Self {
+            schema: None,
+            schema_infer_max_records: DEFAULT_SCHEMA_INFER_MAX_RECORDS,
+            file_extension: ".json",
+        }
+    }
+
+    pub fn schema(mut self, schema: &'a Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn schema_infer_max_records(mut self, max_records: usize) -> Self {
+        self.schema_infer_max_records = max_records;
+        self
+    }
+
+    pub fn file_extension(mut self, file_extension: &'a str) -> Self {
+        self.file_extension = file_extension;
+        self
+    }
+}
+
+impl<'a> Default for JsonReadOptions<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Table of newline-delimited JSON files, which are scanned as one partition per file
+pub struct JsonTable {
+    path: String,
+    file_extension: String,
+    filenames: Vec<String>,
+    schema: SchemaRef,
+}
+
+impl JsonTable {
+    /// Create a table of the JSON files at the path. Unless the options contain a schema, the
+    /// schema is inferred from the first records of the first file only, so all files are
+    /// expected to share it: columns that only appear in later files are not read.
+    pub fn try_new(path: &str, options: JsonReadOptions) -> Result<Self> {
+        let mut filenames = vec![];
+        build_file_list(path, &mut filenames, options.file_extension)?;
+        if filenames.is_empty() {
+            return Err(DataFusionError::Plan(format!(
+                "No files with extension {} found at {}",
+                options.file_extension, path
+            )));
+        }
+        let schema = match options.schema {
+            Some(schema) => Arc::new(schema.clone()),
+            None => infer_schema(&filenames[0], options.schema_infer_max_records)?,
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            file_extension: options.file_extension.to_owned(),
+            filenames,
+            schema,
+        })
+    }
+
+    /// Path of the JSON file or directory of files
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn file_extension(&self) -> &str {
+        &self.file_extension
+    }
+
+    pub fn filenames(&self) -> &[String] {
+        &self.filenames
+    }
+}
+
+/// Infer the schema of a newline-delimited JSON file from its first records
+pub fn infer_schema(filename: &str, max_records: usize) -> Result<SchemaRef> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    Ok(infer_json_schema(&mut reader, Some(max_records))?)
+}
+
+impl TableProvider for JsonTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+        _filters: &[Expr],
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(JsonScanExec::try_new(
+            &self.path,
+            self.filenames.clone(),
+            self.schema.clone(),
+            projection.clone(),
+            batch_size,
+        )?))
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics {
+            num_rows: None,
+            total_byte_size: None,
+            column_statistics: None,
+        }
+    }
+}
+
+/// JsonScanExec reads one newline-delimited JSON file per partition, decoding only the
+/// projected columns
+#[derive(Debug, Clone)]
+pub struct JsonScanExec {
+    /// Path of the JSON file or directory of files
+    path: String,
+    /// File read by each partition
+    filenames: Vec<String>,
+    file_schema: SchemaRef,
+    projection: Option<Vec<usize>>,
+    projected_schema: SchemaRef,
+    batch_size: usize,
+}
+
+impl JsonScanExec {
+    pub fn try_new(
+        path: &str,
+        filenames: Vec<String>,
+        file_schema: SchemaRef,
+        projection: Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> Result<Self> {
+        let projected_schema = match &projection {
+            Some(columns) => {
+                let fields = columns
+                    .iter()
+                    .map(|i| {
+                        if *i < file_schema.fields().len() {
+                            Ok(file_schema.field(*i).clone())
+                        } else {
+                            Err(DataFusionError::Plan(format!(
+                                "Projected column {} is not in the schema of {}",
+                                i, path
+                            )))
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Arc::new(Schema::new(fields))
+            }
+            None => file_schema.clone(),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            filenames,
+            file_schema,
+            projection,
+            projected_schema,
+            batch_size,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn filenames(&self) -> &[String] {
+        &self.filenames
+    }
+
+    pub fn file_schema(&self) -> SchemaRef {
+        self.file_schema.clone()
+    }
+
+    pub fn projection(&self) -> Option<&Vec<usize>> {
+        self.projection.as_ref()
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Names of the projected columns, which the JSON reader selects the columns by. The reader
+    /// returns them in the order of the file schema, along with the index of each projected
+    /// column in its batches.
+    fn projected_columns(&self) -> Option<(Vec<String>, Vec<usize>)> {
+        self.projection.as_ref().map(|projection| {
+            let mut file_order = projection.clone();
+            file_order.sort_unstable();
+            file_order.dedup();
+            let names = file_order
+                .iter()
+                .map(|i| self.file_schema.field(*i).name().clone())
+                .collect();
+            let indices = projection
+                .iter()
+                .map(|i| file_order.binary_search(i).unwrap())
+                .collect();
+            (names, indices)
+        })
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for JsonScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.projected_schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.filenames.len())
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.is_empty() {
+            Ok(Arc::new(self.clone()))
+        } else {
+            Err(DataFusionError::Plan(
+                "Ballista JsonScanExec does not have children".to_owned(),
+            ))
+        }
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
+        let filename = self.filenames[partition].clone();
+        let file_schema = self.file_schema.clone();
+        let (projection, indices) = match self.projected_columns() {
+            Some((names, indices)) => (Some(names), Some(indices)),
+            None => (None, None),
+        };
+        let schema = self.schema();
+        let batch_size = self.batch_size;
+        let (sender, stream) =
+            RecordBatchReceiverStream::create(schema.clone(), DEFAULT_BUFFER_SIZE);
+        task::spawn_blocking(move || {
+            let file = match File::open(&filename) {
+                Ok(file) => file,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(ArrowError::from(e)));
+                    return;
+                }
+            };
+            let mut reader =
+                json::Reader::new(BufReader::new(file), file_schema, batch_size, projection);
+            loop {
+                let batch = match (reader.next(), &indices) {
+                    (Ok(Some(batch)), Some(indices)) => RecordBatch::try_new(
+                        schema.clone(),
+                        indices.iter().map(|i| batch.column(*i).clone()).collect(),
+                    ),
+                    (Ok(Some(batch)), None) => Ok(batch),
+                    (Ok(None), _) => break,
+                    (Err(e), _) => Err(e),
+                };
+                let is_err = batch.is_err();
+                // sending fails when the consumer dropped the stream
+                if sender.blocking_send(batch).is_err() || is_err {
+                    break;
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, Int64Array};
+    use arrow::datatypes::DataType;
+    use datafusion::physical_plan::common;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn scan_with_projection() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("logs.json");
+        File::create(&path)?.write_all(
+            b"{\"a\": 1, \"b\": \"x\", \"c\": 1.5}\n\
+              {\"a\": 2, \"b\": \"y\"}\n\
+              {\"a\": 3, \"c\": 2.5}\n",
+        )?;
+        let table = JsonTable::try_new(
+            path.to_str().unwrap(),
+            JsonReadOptions::new().schema_infer_max_records(2),
+        )?;
+        let schema = table.schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+        assert_eq!(schema.field(2).data_type(), &DataType::Float64);
+
+        let exec = table.scan(&Some(vec![2, 0]), 1024, &[])?;
+        assert_eq!(exec.output_partitioning().partition_count(), 1);
+        assert_eq!(exec.schema().field(0).name(), "c");
+        let batches = common::collect(exec.execute(0).await?).await?;
+        let batch = &batches[0];
+        assert_eq!(batch.num_columns(), 2);
+        assert_eq!(batch.num_rows(), 3);
+        let a = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(a.value(2), 3);
+        assert_eq!(batch.column(0).null_count(), 1);
+        Ok(())
+    }
+}
@@ -13,10 +13,25 @@
 //! This module contains execution plans that are needed to distribute Datafusion's execution plans into
 //! several Ballista executors.
 
+mod csv_scan;
+mod file_split;
+mod json_scan;
+mod parquet_scan;
+mod partitioned_table;
 mod query_stage;
 mod shuffle_reader;
 mod unresolved_shuffle;
+mod uploaded_table;
 
+pub use csv_scan::CsvScanExec;
+pub use file_split::{split_csv_file, split_parquet_file, FileSplit};
+pub use json_scan::{
+    infer_schema as infer_json_schema, JsonReadOptions, JsonScanExec, JsonTable,
+    DEFAULT_SCHEMA_INFER_MAX_RECORDS,
+};
+pub use parquet_scan::{ParquetScanExec, ParquetScanTable, DEFAULT_PARQUET_CONCURRENCY};
+pub use partitioned_table::{list_partitions, PartitionedTable, TablePartition};
 pub use query_stage::QueryStageExec;
 pub use shuffle_reader::ShuffleReaderExec;
 pub use unresolved_shuffle::UnresolvedShuffleExec;
+pub use uploaded_table::UploadedTable;
@@ -0,0 +1,317 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::{any::Any, pin::Pin};
+
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::{ArrowError, Result as ArrowResult};
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::datasource::datasource::{Statistics, TableProviderFilterPushDown};
+use datafusion::datasource::TableProvider;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::parquet::{ParquetExec, RowGroupPredicateBuilder};
+use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use datafusion::{
+    error::{DataFusionError, Result},
+    physical_plan::RecordBatchStream,
+};
+use parquet::arrow::{parquet_to_arrow_schema, ArrowReader, ParquetFileArrowReader};
+use parquet::errors::ParquetError;
+use parquet::file::metadata::RowGroupMetaData;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use tokio::sync::mpsc::Sender;
+use tokio::task;
+
+use super::FileSplit;
+use crate::error::BallistaError;
+use crate::receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE};
+use crate::scheduler::file_metadata::{merge_statistics, parquet_file_statistics};
+
+/// Maximum number of partitions of the scans of Parquet tables that are decoded from logical
+/// plans or read by the client, which do not carry the concurrency of the session
+pub const DEFAULT_PARQUET_CONCURRENCY: usize = 24;
+
+/// Parquet table that plans its scans as [`ParquetScanExec`], so that the filters pushed into
+/// a scan are kept when the plan is sent to the executors. The statistics of the table are
+/// combined from the footers of its files, so that DataFusion can use them when planning joins.
+pub struct ParquetScanTable {
+    path: String,
+    schema: SchemaRef,
+    statistics: Statistics,
+    max_concurrency: usize,
+}
+
+impl ParquetScanTable {
+    pub fn try_new(path: &str, max_concurrency: usize) -> Result<Self> {
+        let parquet_exec = ParquetExec::try_from_path(path, None, None, 1024, max_concurrency)?;
+        let statistics = parquet_exec
+            .partitions()
+            .iter()
+            .flat_map(|part| part.filenames())
+            .map(|filename| parquet_file_statistics(filename))
+            .collect::<std::result::Result<Vec<_>, BallistaError>>()
+            .map_err(|e| DataFusionError::Execution(e.to_string()))?;
+        Ok(Self {
+            path: path.to_owned(),
+            schema: parquet_exec.schema(),
+            statistics: merge_statistics(&statistics),
+            max_concurrency,
+        })
+    }
+
+    /// Path of the Parquet file or directory of files
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl TableProvider for ParquetScanTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+        filters: &[Expr],
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let predicate = filters
+            .split_first()
+            .map(|(first, rest)| rest.iter().fold(first.clone(), |acc, f| acc.and(f.clone())));
+        let exec = ParquetExec::try_from_path(
+            &self.path,
+            projection.clone(),
+            None,
+            batch_size,
+            self.max_concurrency,
+        )?;
+        Ok(Arc::new(ParquetScanExec::new(exec, predicate)))
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.statistics.clone()
+    }
+
+    fn supports_filter_pushdown(&self, _filter: &Expr) -> Result<TableProviderFilterPushDown> {
+        // row groups that cannot match are skipped, but the filter is still applied to the rows
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+}
+
+/// ParquetScanExec scans Parquet files like DataFusion's `ParquetExec`, but also holds the
+/// predicate pushed into the scan so that it can be serialized. The row groups that are
+/// skipped based on their min/max statistics are counted per partition, so that they can be
+/// reported in the statistics of the task.
+///
+/// When the scan has splits, each partition reads the range of row groups of one split
+/// instead of the files of a partition of the `ParquetExec`. Scans with a predicate read their
+/// files on a blocking thread too, counting the skipped row groups as they go. The predicate is
+/// only applied here, so the `ParquetExec` is created without one.
+#[derive(Debug)]
+pub struct ParquetScanExec {
+    /// The files and partitions of the scan
+    pub(crate) parquet: ParquetExec,
+    /// Filter pushed into the scan
+    pub(crate) predicate: Option<Expr>,
+    /// Row group ranges read by each partition, or empty to read the files of the partitions
+    /// of the `ParquetExec`
+    pub(crate) splits: Vec<FileSplit>,
+    /// Rows and bytes skipped by each partition
+    skipped: Arc<Vec<(AtomicU64, AtomicU64)>>,
+}
+
+impl ParquetScanExec {
+    pub fn new(parquet: ParquetExec, predicate: Option<Expr>) -> Self {
+        let partition_count = parquet.partitions().len();
+        Self {
+            parquet,
+            predicate,
+            splits: vec![],
+            skipped: new_skipped(partition_count),
+        }
+    }
+
+    /// Read each of the splits as one partition
+    pub fn with_splits(mut self, splits: Vec<FileSplit>) -> Self {
+        if !splits.is_empty() {
+            self.skipped = new_skipped(splits.len());
+        }
+        self.splits = splits;
+        self
+    }
+
+    /// Rows and bytes of the row groups skipped when executing a partition
+    pub fn skipped(&self, partition: usize) -> (u64, u64) {
+        let (rows, bytes) = &self.skipped[partition];
+        (rows.load(Ordering::SeqCst), bytes.load(Ordering::SeqCst))
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for ParquetScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.parquet.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        if self.splits.is_empty() {
+            self.parquet.output_partitioning()
+        } else {
+            Partitioning::UnknownPartitioning(self.splits.len())
+        }
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.is_empty() {
+            Ok(Arc::new(
+                ParquetScanExec::new(self.parquet.clone(), self.predicate.clone())
+                    .with_splits(self.splits.clone()),
+            ))
+        } else {
+            Err(DataFusionError::Plan(
+                "Ballista ParquetScanExec does not have children".to_owned(),
+            ))
+        }
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
+        // the row groups skipped by the predicate are counted while the files are read, so
+        // whole files are read like splits covering all of their row groups
+        let splits = if !self.splits.is_empty() {
+            vec![self.splits[partition].clone()]
+        } else if self.predicate.is_some() {
+            self.parquet.partitions()[partition]
+                .filenames()
+                .iter()
+                .map(|filename| FileSplit::new(filename, 0, u64::MAX))
+                .collect()
+        } else {
+            return self.parquet.execute(partition).await;
+        };
+        let projection = self.parquet.projection().to_vec();
+        let batch_size = self.parquet.batch_size();
+        let predicate = self.predicate.clone();
+        let skipped = self.skipped.clone();
+        let (sender, stream) =
+            RecordBatchReceiverStream::create(self.schema(), DEFAULT_BUFFER_SIZE);
+        task::spawn_blocking(move || {
+            for split in &splits {
+                if let Err(e) = read_split(
+                    split,
+                    projection.clone(),
+                    batch_size,
+                    predicate.as_ref(),
+                    &skipped[partition],
+                    &sender,
+                ) {
+                    let _ = sender.blocking_send(Err(ArrowError::ExternalError(Box::new(e))));
+                    return;
+                }
+                // the consumer dropped the stream
+                if sender.is_closed() {
+                    return;
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+fn new_skipped(partition_count: usize) -> Arc<Vec<(AtomicU64, AtomicU64)>> {
+    Arc::new(
+        (0..partition_count)
+            .map(|_| (AtomicU64::new(0), AtomicU64::new(0)))
+            .collect(),
+    )
+}
+
+/// Arrow schema of a Parquet file
+fn file_schema(reader: &SerializedFileReader<File>) -> std::result::Result<Schema, ParquetError> {
+    let metadata = reader.metadata().file_metadata();
+    parquet_to_arrow_schema(metadata.schema_descr(), metadata.key_value_metadata())
+}
+
+/// Read the row groups of a split that can match the predicate, and send the batches to the
+/// stream of the partition
+fn read_split(
+    split: &FileSplit,
+    projection: Vec<usize>,
+    batch_size: usize,
+    predicate: Option<&Expr>,
+    skipped: &(AtomicU64, AtomicU64),
+    sender: &Sender<ArrowResult<RecordBatch>>,
+) -> Result<()> {
+    let parquet_error =
+        |e: ParquetError| DataFusionError::Execution(format!("{}: {:?}", split.filename, e));
+    let mut reader =
+        SerializedFileReader::new(File::open(&split.filename)?).map_err(parquet_error)?;
+    let range = split.start as usize..split.end as usize;
+    let row_groups = reader.metadata().row_groups();
+    let mut keep: Vec<bool> = (0..row_groups.len()).map(|i| range.contains(&i)).collect();
+    if let Some(predicate) = predicate {
+        let schema = file_schema(&reader).map_err(parquet_error)?;
+        // the pushed down predicate only skips row groups, so every row group is read when it
+        // cannot be turned into a pruning predicate, like DataFusion's ParquetExec does
+        if let Ok(builder) = RowGroupPredicateBuilder::try_new(predicate, schema) {
+            let matches = builder.build_row_group_predicate(row_groups);
+            for (i, row_group) in row_groups.iter().enumerate() {
+                if keep[i] && !matches(row_group, i) {
+                    keep[i] = false;
+                    skipped
+                        .0
+                        .fetch_add(row_group.num_rows() as u64, Ordering::SeqCst);
+                    skipped
+                        .1
+                        .fetch_add(row_group.total_byte_size() as u64, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+    reader.filter_row_groups(&|_: &RowGroupMetaData, i: usize| keep[i]);
+
+    let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(reader));
+    let batches = arrow_reader
+        .get_record_reader_by_columns(projection, batch_size)
+        .map_err(parquet_error)?;
+    for batch in batches {
+        // sending fails when the consumer dropped the stream
+        if sender.blocking_send(batch).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
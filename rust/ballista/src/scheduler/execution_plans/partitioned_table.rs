@@ -0,0 +1,602 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tables stored as Hive-style partitioned directories, such as `year=2021/month=03/`, where
+//! the values of the partition columns are taken from the directory names.
+
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Arc;
+
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::datasource::datasource::{Statistics, TableProviderFilterPushDown};
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::logical_plan::{Expr, Operator};
+use datafusion::optimizer::utils::expr_to_column_names;
+use datafusion::physical_plan::csv::{CsvExec, CsvReadOptions};
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::expressions::{Column, Literal};
+use datafusion::physical_plan::parquet::ParquetExec;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::union::UnionExec;
+use datafusion::physical_plan::{ExecutionPlan, PhysicalExpr};
+use datafusion::scalar::ScalarValue;
+
+use super::ParquetScanExec;
+use crate::serde::protobuf::FileType;
+
+/// The files of one leaf directory of a partitioned table
+#[derive(Debug, Clone)]
+pub struct TablePartition {
+    pub directory: String,
+    /// Values of the partition columns, in the order of the partition columns of the table
+    pub values: Vec<String>,
+    pub filenames: Vec<String>,
+}
+
+/// A table of Parquet or CSV files in a tree of `column=value` directories. The partition
+/// columns are appended to the schema of the files as non-nullable strings, and the
+/// directories that cannot match the filters of a scan are not read.
+pub struct PartitionedTable {
+    path: String,
+    file_type: FileType,
+    /// CSV options
+    has_header: bool,
+    delimiter: u8,
+    file_extension: String,
+    partition_columns: Vec<String>,
+    file_schema: SchemaRef,
+    schema: SchemaRef,
+    partitions: Vec<TablePartition>,
+    max_concurrency: usize,
+}
+
+impl PartitionedTable {
+    /// Create a partitioned table of Parquet files
+    pub fn try_new_parquet(
+        path: &str,
+        partition_columns: &[String],
+        max_concurrency: usize,
+    ) -> Result<Self> {
+        let partitions = list_partitions(path, partition_columns, ".parquet")?;
+        let filenames: Vec<&str> = partitions[0].filenames.iter().map(|f| f.as_str()).collect();
+        let file_schema = ParquetExec::try_from_files(&filenames, None, None, 1024, 1)?.schema();
+        Self::try_new(
+            path,
+            FileType::Parquet,
+            CsvReadOptions::new().file_extension(".parquet"),
+            partition_columns,
+            file_schema,
+            partitions,
+            max_concurrency,
+        )
+    }
+
+    /// Create a partitioned table of CSV files. Unless the options contain a schema, the schema
+    /// is inferred from the files of the first directory.
+    pub fn try_new_csv(
+        path: &str,
+        partition_columns: &[String],
+        options: CsvReadOptions,
+    ) -> Result<Self> {
+        let partitions = list_partitions(path, partition_columns, options.file_extension)?;
+        let file_schema = match options.schema {
+            Some(schema) => Arc::new(schema.clone()),
+            None => CsvExec::try_new(&partitions[0].directory, options.clone(), None, 1024)?
+                .file_schema(),
+        };
+        Self::try_new(
+            path,
+            FileType::Csv,
+            options,
+            partition_columns,
+            file_schema,
+            partitions,
+            1,
+        )
+    }
+
+    fn try_new(
+        path: &str,
+        file_type: FileType,
+        options: CsvReadOptions,
+        partition_columns: &[String],
+        file_schema: SchemaRef,
+        partitions: Vec<TablePartition>,
+        max_concurrency: usize,
+    ) -> Result<Self> {
+        let mut fields = file_schema.fields().clone();
+        for column in partition_columns {
+            if file_schema.index_of(column).is_ok() {
+                return Err(DataFusionError::Plan(format!(
+                    "Partition column {} is also a column of the files of {}",
+                    column, path
+                )));
+            }
+            fields.push(Field::new(column, DataType::Utf8, false));
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            file_type,
+            has_header: options.has_header,
+            delimiter: options.delimiter,
+            file_extension: options.file_extension.to_owned(),
+            partition_columns: partition_columns.to_vec(),
+            file_schema,
+            schema: Arc::new(Schema::new(fields)),
+            partitions,
+            max_concurrency,
+        })
+    }
+
+    /// Path of the root directory of the table
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn has_header(&self) -> bool {
+        self.has_header
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    pub fn file_extension(&self) -> &str {
+        &self.file_extension
+    }
+
+    pub fn partition_columns(&self) -> &[String] {
+        &self.partition_columns
+    }
+
+    /// Schema of the files, without the partition columns
+    pub fn file_schema(&self) -> SchemaRef {
+        self.file_schema.clone()
+    }
+
+    /// The directories of the table that can contain rows matching all of the filters
+    pub fn partitions(&self, filters: &[Expr]) -> Vec<&TablePartition> {
+        self.partitions
+            .iter()
+            .filter(|partition| {
+                filters.iter().all(|filter| {
+                    evaluate(filter, &self.partition_columns, &partition.values) != Some(false)
+                })
+            })
+            .collect()
+    }
+
+    /// Plan the scan of the files of one directory, reading the given columns of the files
+    fn scan_partition(
+        &self,
+        partition: &TablePartition,
+        file_projection: Vec<usize>,
+        predicate: Option<Expr>,
+        batch_size: usize,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        match self.file_type {
+            FileType::Parquet => {
+                let filenames: Vec<&str> = partition.filenames.iter().map(|f| f.as_str()).collect();
+                let exec = ParquetExec::try_from_files(
+                    &filenames,
+                    Some(file_projection),
+                    None,
+                    batch_size,
+                    self.max_concurrency,
+                )?;
+                Ok(Arc::new(ParquetScanExec::new(exec, predicate)))
+            }
+            _ => {
+                let options = CsvReadOptions::new()
+                    .schema(self.file_schema.as_ref())
+                    .has_header(self.has_header)
+                    .delimiter(self.delimiter)
+                    .file_extension(&self.file_extension);
+                Ok(Arc::new(CsvExec::try_new(
+                    &partition.directory,
+                    options,
+                    Some(file_projection),
+                    batch_size,
+                )?))
+            }
+        }
+    }
+}
+
+impl TableProvider for PartitionedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+        filters: &[Expr],
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let projection = projection
+            .clone()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let num_file_columns = self.file_schema.fields().len();
+        let mut file_projection: Vec<usize> = projection
+            .iter()
+            .filter(|i| **i < num_file_columns)
+            .cloned()
+            .collect();
+        file_projection.sort_unstable();
+        if file_projection.is_empty() && num_file_columns > 0 {
+            // the files are still read when only partition columns are selected, so that
+            // there is one row per row of the files
+            file_projection.push(0);
+        }
+
+        // only the filters on columns of the files can be used to skip Parquet row groups
+        let predicate = filters
+            .iter()
+            .filter(|filter| {
+                let mut columns = HashSet::new();
+                expr_to_column_names(filter, &mut columns).is_ok()
+                    && columns
+                        .iter()
+                        .all(|column| self.file_schema.index_of(column).is_ok())
+            })
+            .cloned()
+            .fold(None, |acc: Option<Expr>, f| match acc {
+                Some(acc) => Some(acc.and(f)),
+                None => Some(f),
+            });
+
+        let mut inputs = self
+            .partitions(filters)
+            .into_iter()
+            .map(|partition| {
+                let input = self.scan_partition(
+                    partition,
+                    file_projection.clone(),
+                    predicate.clone(),
+                    batch_size,
+                )?;
+                let exprs = projection
+                    .iter()
+                    .map(|i| {
+                        let name = self.schema.field(*i).name();
+                        let expr: Arc<dyn PhysicalExpr> = if *i < num_file_columns {
+                            Arc::new(Column::new(name))
+                        } else {
+                            let value = partition.values[*i - num_file_columns].clone();
+                            Arc::new(Literal::new(ScalarValue::Utf8(Some(value))))
+                        };
+                        (expr, name.to_owned())
+                    })
+                    .collect();
+                Ok(Arc::new(ProjectionExec::try_new(exprs, input)?) as Arc<dyn ExecutionPlan>)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match inputs.len() {
+            0 => {
+                let fields = projection
+                    .iter()
+                    .map(|i| self.schema.field(*i).clone())
+                    .collect();
+                Ok(Arc::new(EmptyExec::new(
+                    false,
+                    Arc::new(Schema::new(fields)),
+                )))
+            }
+            1 => Ok(inputs.remove(0)),
+            _ => Ok(Arc::new(UnionExec::new(inputs))),
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics {
+            num_rows: None,
+            total_byte_size: None,
+            column_statistics: None,
+        }
+    }
+
+    fn supports_filter_pushdown(&self, _filter: &Expr) -> Result<TableProviderFilterPushDown> {
+        // directories that cannot match are skipped, but the filter is still applied to the rows
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+}
+
+/// List the leaf directories of a partitioned table and the files in them with the given
+/// extension. Every level of directories must be named `column=value` after the partition
+/// column of that level, and directories without files are skipped. Files and directories
+/// starting with `.` or `_` are ignored.
+pub fn list_partitions(
+    path: &str,
+    partition_columns: &[String],
+    file_extension: &str,
+) -> Result<Vec<TablePartition>> {
+    let mut partitions = vec![];
+    list_directory(
+        path,
+        partition_columns,
+        file_extension,
+        &mut vec![],
+        &mut partitions,
+    )?;
+    if partitions.is_empty() {
+        return Err(DataFusionError::Plan(format!(
+            "No files with extension {} found in partitioned table {}",
+            file_extension, path
+        )));
+    }
+    Ok(partitions)
+}
+
+fn list_directory(
+    directory: &str,
+    partition_columns: &[String],
+    file_extension: &str,
+    values: &mut Vec<String>,
+    partitions: &mut Vec<TablePartition>,
+) -> Result<()> {
+    let mut entries = fs::read_dir(directory)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    let entries = entries.into_iter().filter(|entry| {
+        entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| !name.starts_with('.') && !name.starts_with('_'))
+            .unwrap_or(false)
+    });
+
+    let column = match partition_columns.get(values.len()) {
+        Some(column) => column,
+        None => {
+            let filenames: Vec<String> = entries
+                .filter(|entry| entry.is_file())
+                .filter_map(|entry| entry.to_str().map(|s| s.to_owned()))
+                .filter(|filename| filename.ends_with(file_extension))
+                .collect();
+            if !filenames.is_empty() {
+                partitions.push(TablePartition {
+                    directory: directory.to_owned(),
+                    values: values.clone(),
+                    filenames,
+                });
+            }
+            return Ok(());
+        }
+    };
+
+    let prefix = format!("{}=", column);
+    for entry in entries.filter(|entry| entry.is_dir()) {
+        let name = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if !name.starts_with(&prefix) {
+            return Err(DataFusionError::Plan(format!(
+                "Directory {} does not match partition column {}",
+                entry.display(),
+                column
+            )));
+        }
+        let entry = entry
+            .to_str()
+            .ok_or_else(|| DataFusionError::Plan(format!("Invalid path {}", entry.display())))?;
+        values.push(name[prefix.len()..].to_owned());
+        list_directory(entry, partition_columns, file_extension, values, partitions)?;
+        values.pop();
+    }
+    Ok(())
+}
+
+/// Evaluate a filter against the values of the partition columns of a directory. Returns
+/// `None` when the result also depends on the columns of the files, or when the filter cannot
+/// be evaluated.
+fn evaluate(expr: &Expr, columns: &[String], values: &[String]) -> Option<bool> {
+    match expr {
+        Expr::Alias(expr, _) => evaluate(expr, columns, values),
+        Expr::Not(expr) => evaluate(expr, columns, values).map(|b| !b),
+        Expr::IsNull(expr) => partition_value(expr, columns, values).map(|_| false),
+        Expr::IsNotNull(expr) => partition_value(expr, columns, values).map(|_| true),
+        Expr::BinaryExpr { left, op, right } => match op {
+            Operator::And => match (
+                evaluate(left, columns, values),
+                evaluate(right, columns, values),
+            ) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Operator::Or => match (
+                evaluate(left, columns, values),
+                evaluate(right, columns, values),
+            ) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            _ => {
+                // compare with the literal on the right hand side
+                let (value, literal, op) = match (left.as_ref(), right.as_ref()) {
+                    (column, Expr::Literal(literal)) => {
+                        (partition_value(column, columns, values)?, literal, *op)
+                    }
+                    (Expr::Literal(literal), column) => (
+                        partition_value(column, columns, values)?,
+                        literal,
+                        swap_operator(*op)?,
+                    ),
+                    _ => return None,
+                };
+                let ordering = compare(value, literal)?;
+                match op {
+                    Operator::Eq => Some(ordering == Ordering::Equal),
+                    Operator::NotEq => Some(ordering != Ordering::Equal),
+                    Operator::Lt => Some(ordering == Ordering::Less),
+                    Operator::LtEq => Some(ordering != Ordering::Greater),
+                    Operator::Gt => Some(ordering == Ordering::Greater),
+                    Operator::GtEq => Some(ordering != Ordering::Less),
+                    _ => None,
+                }
+            }
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = partition_value(expr, columns, values)?;
+            let mut found = false;
+            for item in list {
+                match item {
+                    Expr::Literal(literal) => {
+                        found |= compare(value, literal)? == Ordering::Equal;
+                    }
+                    _ => return None,
+                }
+            }
+            Some(found != *negated)
+        }
+        _ => None,
+    }
+}
+
+/// The value of a partition column in a directory, or `None` when the expression is not a
+/// partition column
+fn partition_value<'a>(expr: &Expr, columns: &[String], values: &'a [String]) -> Option<&'a str> {
+    match expr {
+        Expr::Column(name) => columns
+            .iter()
+            .position(|column| column == name)
+            .map(|i| values[i].as_str()),
+        _ => None,
+    }
+}
+
+/// The operator that gives the same result when the operands are swapped
+fn swap_operator(op: Operator) -> Option<Operator> {
+    match op {
+        Operator::Eq | Operator::NotEq => Some(op),
+        Operator::Lt => Some(Operator::Gt),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Gt => Some(Operator::Lt),
+        Operator::GtEq => Some(Operator::LtEq),
+        _ => None,
+    }
+}
+
+/// Compare the value of a partition column with a literal. Values are compared as strings
+/// with string literals and as numbers with numeric literals.
+fn compare(value: &str, literal: &ScalarValue) -> Option<Ordering> {
+    let number = match literal {
+        ScalarValue::Utf8(Some(s)) | ScalarValue::LargeUtf8(Some(s)) => {
+            return Some(value.cmp(s.as_str()))
+        }
+        ScalarValue::Int8(Some(v)) => *v as f64,
+        ScalarValue::Int16(Some(v)) => *v as f64,
+        ScalarValue::Int32(Some(v)) => *v as f64,
+        ScalarValue::Int64(Some(v)) => *v as f64,
+        ScalarValue::UInt8(Some(v)) => *v as f64,
+        ScalarValue::UInt16(Some(v)) => *v as f64,
+        ScalarValue::UInt32(Some(v)) => *v as f64,
+        ScalarValue::UInt64(Some(v)) => *v as f64,
+        ScalarValue::Float32(Some(v)) => *v as f64,
+        ScalarValue::Float64(Some(v)) => *v,
+        _ => return None,
+    };
+    value.parse::<f64>().ok()?.partial_cmp(&number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::logical_plan::{col, lit};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn write_file(dir: &std::path::Path, name: &str, contents: &str) -> std::io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let mut file = fs::File::create(dir.join(name))?;
+        file.write_all(contents.as_bytes())
+    }
+
+    #[test]
+    fn prune_directories() -> Result<()> {
+        let dir = TempDir::new()?;
+        for (year, month) in &[("2020", "12"), ("2021", "01"), ("2021", "03")] {
+            let path = dir
+                .path()
+                .join(format!("year={}", year))
+                .join(format!("month={}", month));
+            write_file(&path, "data.csv", "a,b\n1,x\n2,y\n")?;
+        }
+        // files and directories that are not part of the table
+        write_file(dir.path(), "_SUCCESS", "")?;
+        write_file(&dir.path().join("year=2021").join(".tmp"), "data.csv", "")?;
+
+        let columns = vec!["year".to_owned(), "month".to_owned()];
+        let table = PartitionedTable::try_new_csv(
+            dir.path().to_str().unwrap(),
+            &columns,
+            CsvReadOptions::new(),
+        )?;
+        let names: Vec<&str> = table
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect();
+        assert_eq!(vec!["a", "b", "year", "month"], names);
+        assert_eq!(3, table.partitions(&[]).len());
+
+        let filters = vec![col("year").eq(lit("2021")), col("month").gt(lit(1))];
+        let partitions = table.partitions(&filters);
+        assert_eq!(1, partitions.len());
+        assert_eq!(
+            vec!["2021".to_owned(), "03".to_owned()],
+            partitions[0].values
+        );
+
+        // filters on the columns of the files do not prune directories
+        let filters = vec![col("a").eq(lit(5)).or(col("year").eq(lit("2020")))];
+        assert_eq!(3, table.partitions(&filters).len());
+
+        let exec = table.scan(&Some(vec![1, 3]), 1024, &[col("month").eq(lit("12"))])?;
+        let names: Vec<String> = exec
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(vec!["b".to_owned(), "month".to_owned()], names);
+        assert_eq!(1, exec.output_partitioning().partition_count());
+
+        let exec = table.scan(&None, 1024, &[col("year").eq(lit("2019"))])?;
+        assert!(exec.as_any().downcast_ref::<EmptyExec>().is_some());
+        Ok(())
+    }
+}
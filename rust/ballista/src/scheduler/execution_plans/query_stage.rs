@@ -18,13 +18,19 @@ use std::{any::Any, pin::Pin};
 use arrow::datatypes::SchemaRef;
 use async_trait::async_trait;
 use datafusion::physical_plan::{ExecutionPlan, Partitioning};
-use datafusion::{error::Result, physical_plan::RecordBatchStream};
+use datafusion::{
+    error::{DataFusionError, Result},
+    physical_plan::RecordBatchStream,
+};
 use uuid::Uuid;
 
 /// QueryStageExec represents a section of a query plan that has consistent partitioning and
 /// can be executed as one unit with each partition being executed in parallel. The output of
 /// a query stage either forms the input of another query stage or can be the final result of
 /// a query.
+///
+/// When the stage has a hash partitioning for its output, each task splits the output of its
+/// input partition into one shuffle file per output partition.
 #[derive(Debug, Clone)]
 pub struct QueryStageExec {
     /// Unique ID for the job (query) that this stage is a part of
@@ -33,16 +39,30 @@ pub struct QueryStageExec {
     pub(crate) stage_id: usize,
     /// Physical execution plan for this query stage
     pub(crate) child: Arc<dyn ExecutionPlan>,
+    /// Optional hash partitioning of the shuffle output
+    pub(crate) shuffle_output_partitioning: Option<Partitioning>,
 }
 
 impl QueryStageExec {
     /// Create a new query stage
-    pub fn try_new(job_uuid: Uuid, stage_id: usize, child: Arc<dyn ExecutionPlan>) -> Result<Self> {
-        Ok(Self {
-            job_uuid,
-            stage_id,
-            child,
-        })
+    pub fn try_new(
+        job_uuid: Uuid,
+        stage_id: usize,
+        child: Arc<dyn ExecutionPlan>,
+        shuffle_output_partitioning: Option<Partitioning>,
+    ) -> Result<Self> {
+        match &shuffle_output_partitioning {
+            None | Some(Partitioning::Hash(_, _)) => Ok(Self {
+                job_uuid,
+                stage_id,
+                child,
+                shuffle_output_partitioning,
+            }),
+            Some(other) => Err(DataFusionError::Plan(format!(
+                "Ballista QueryStageExec does not support shuffle output partitioning {:?}",
+                other
+            ))),
+        }
     }
 }
 
@@ -57,7 +77,10 @@ impl ExecutionPlan for QueryStageExec {
     }
 
     fn output_partitioning(&self) -> Partitioning {
-        self.child.output_partitioning()
+        match &self.shuffle_output_partitioning {
+            Some(partitioning) => partitioning.clone(),
+            None => self.child.output_partitioning(),
+        }
     }
 
     fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
@@ -73,9 +96,12 @@ impl ExecutionPlan for QueryStageExec {
             self.job_uuid,
             self.stage_id,
             children[0].clone(),
+            self.shuffle_output_partitioning.clone(),
         )?))
     }
 
+    /// Execute one input partition of the stage. The partitions of a stage with a hash
+    /// partitioned output are split into shuffle files by the executor.
     async fn execute(
         &self,
         partition: usize,
@@ -16,35 +16,54 @@ use std::sync::Arc;
 use std::{any::Any, pin::Pin};
 
 use crate::client::BallistaClient;
-use crate::memory_stream::MemoryStream;
+use crate::receiver_stream::{RecordBatchReceiverStream, DEFAULT_BUFFER_SIZE};
 use crate::scheduler::planner::PartitionLocation;
+use crate::serde::scheduler::{ExecutorMeta, PartitionId};
 
 use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
 use async_trait::async_trait;
-use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use datafusion::physical_plan::{ExecutionPlan, Partitioning, PhysicalExpr};
 use datafusion::{
     error::{DataFusionError, Result},
     physical_plan::RecordBatchStream,
 };
+use futures::StreamExt;
 use log::info;
 
 /// ShuffleReaderExec reads partitions that have already been materialized by an executor.
+///
+/// Each output partition is read from one or more shuffle partitions. When the upstream query
+/// stages hash-partitioned their output, output partition n is gathered from output partition n
+/// of every upstream task. Otherwise each output partition reads the whole output of one task.
 #[derive(Debug, Clone)]
 pub struct ShuffleReaderExec {
-    // The query stage that is responsible for producing the shuffle partitions that
-    // this operator will read
-    pub(crate) partition_location: Vec<PartitionLocation>,
+    // The shuffle partitions to read for each output partition of this operator, which were
+    // produced by the query stages that this operator depends on
+    pub(crate) partition: Vec<Vec<PartitionLocation>>,
     pub(crate) schema: SchemaRef,
+    // The expressions that the query stages hash-partitioned the shuffle partitions on, if any
+    pub(crate) hash_exprs: Option<Vec<Arc<dyn PhysicalExpr>>>,
 }
 
 impl ShuffleReaderExec {
     /// Create a new ShuffleReaderExec
-    pub fn try_new(partition_meta: Vec<PartitionLocation>, schema: SchemaRef) -> Result<Self> {
+    pub fn try_new(
+        partition: Vec<Vec<PartitionLocation>>,
+        schema: SchemaRef,
+        hash_exprs: Option<Vec<Arc<dyn PhysicalExpr>>>,
+    ) -> Result<Self> {
         Ok(Self {
-            partition_location: partition_meta,
+            partition,
             schema,
+            hash_exprs,
         })
     }
+
+    /// Whether the shuffle partitions were written by hash-partitioned query stages
+    pub fn hash_partitioned(&self) -> bool {
+        self.hash_exprs.is_some()
+    }
 }
 
 #[async_trait]
@@ -58,7 +77,10 @@ impl ExecutionPlan for ShuffleReaderExec {
     }
 
     fn output_partitioning(&self) -> Partitioning {
-        Partitioning::UnknownPartitioning(self.partition_location.len())
+        match &self.hash_exprs {
+            Some(exprs) => Partitioning::Hash(exprs.clone(), self.partition.len()),
+            None => Partitioning::UnknownPartitioning(self.partition.len()),
+        }
     }
 
     fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
@@ -79,28 +101,92 @@ impl ExecutionPlan for ShuffleReaderExec {
         partition: usize,
     ) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
         info!("ShuffleReaderExec::execute({})", partition);
-        let partition_location = &self.partition_location[partition];
+        let output_partition = if self.hash_partitioned() {
+            Some(partition)
+        } else {
+            None
+        };
+        // the shuffle partitions held by the same executor are fetched with one request
+        let mut fetches: Vec<(ExecutorMeta, Vec<PartitionId>)> = vec![];
+        for location in &self.partition[partition] {
+            match fetches
+                .iter_mut()
+                .find(|(executor_meta, _)| executor_meta.id == location.executor_meta.id)
+            {
+                Some((_, partition_ids)) => partition_ids.push(location.partition_id),
+                None => fetches.push((location.executor_meta.clone(), vec![location.partition_id])),
+            }
+        }
+        if fetches.len() == 1 {
+            let (executor_meta, partition_ids) = fetches.remove(0);
+            return fetch_partitions(&executor_meta, partition_ids, output_partition).await;
+        }
 
-        let mut client = BallistaClient::try_new(
-            &partition_location.executor_meta.host,
-            partition_location.executor_meta.port,
-        )
+        // read from the executors one after the other
+        let (sender, result) =
+            RecordBatchReceiverStream::create(self.schema.clone(), DEFAULT_BUFFER_SIZE);
+        tokio::spawn(async move {
+            for (executor_meta, partition_ids) in fetches {
+                let mut stream =
+                    match fetch_partitions(&executor_meta, partition_ids, output_partition).await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            // the consumer might already have dropped the stream
+                            let _ = sender
+                                .send(Err(ArrowError::ExternalError(Box::new(e))))
+                                .await;
+                            return;
+                        }
+                    };
+                while let Some(batch) = stream.next().await {
+                    if sender.send(batch).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(result))
+    }
+}
+
+/// Fetch shuffle partitions from the executor that holds them. For hash-partitioned shuffles,
+/// only the requested output partition of each task is fetched.
+async fn fetch_partitions(
+    executor_meta: &ExecutorMeta,
+    mut partition_ids: Vec<PartitionId>,
+    output_partition: Option<usize>,
+) -> Result<Pin<Box<dyn RecordBatchStream + Send + Sync>>> {
+    let mut client = BallistaClient::try_new(&executor_meta.host, executor_meta.port)
         .await
         .map_err(|e| DataFusionError::Execution(format!("Ballista Error: {:?}", e)))?;
 
-        let batches = client
-            .fetch_partition(
-                &partition_location.partition_id.job_uuid,
-                partition_location.partition_id.stage_id,
-                partition,
-            )
+    if partition_ids.len() > 1 {
+        return client
+            .fetch_partitions(partition_ids, output_partition)
             .await
-            .map_err(|e| DataFusionError::Execution(format!("Ballista Error: {:?}", e)))?;
-
-        Ok(Box::pin(MemoryStream::try_new(
-            batches,
-            self.schema(),
-            None,
-        )?))
+            .map_err(|e| DataFusionError::Execution(format!("Ballista Error: {:?}", e)));
+    }
+    let partition_id = partition_ids.remove(0);
+    match output_partition {
+        Some(output_partition) => {
+            client
+                .fetch_shuffle_partition(
+                    &partition_id.job_uuid,
+                    partition_id.stage_id,
+                    partition_id.partition_id,
+                    output_partition,
+                )
+                .await
+        }
+        None => {
+            client
+                .fetch_partition(
+                    &partition_id.job_uuid,
+                    partition_id.stage_id,
+                    partition_id.partition_id,
+                )
+                .await
+        }
     }
+    .map_err(|e| DataFusionError::Execution(format!("Ballista Error: {:?}", e)))
 }
@@ -19,7 +19,7 @@ use crate::scheduler::planner::PartitionLocation;
 
 use arrow::datatypes::SchemaRef;
 use async_trait::async_trait;
-use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use datafusion::physical_plan::{ExecutionPlan, Partitioning, PhysicalExpr};
 use datafusion::{
     error::{DataFusionError, Result},
     physical_plan::RecordBatchStream,
@@ -40,17 +40,32 @@ pub struct UnresolvedShuffleExec {
 
     // The partition count this node will have once it is replaced with a ShuffleReaderExec
     pub(crate) partition_count: usize,
+
+    // The expressions that the query stages hash-partition their output on into
+    // `partition_count` partitions, if any
+    pub(crate) hash_exprs: Option<Vec<Arc<dyn PhysicalExpr>>>,
 }
 
 impl UnresolvedShuffleExec {
     /// Create a new UnresolvedShuffleExec
-    pub fn new(query_stage_ids: Vec<usize>, schema: SchemaRef, partition_count: usize) -> Self {
+    pub fn new(
+        query_stage_ids: Vec<usize>,
+        schema: SchemaRef,
+        partition_count: usize,
+        hash_exprs: Option<Vec<Arc<dyn PhysicalExpr>>>,
+    ) -> Self {
         Self {
             query_stage_ids,
             schema,
             partition_count,
+            hash_exprs,
         }
     }
+
+    /// Whether the query stages hash-partition their output
+    pub fn hash_partitioned(&self) -> bool {
+        self.hash_exprs.is_some()
+    }
 }
 
 #[async_trait]
@@ -64,7 +79,10 @@ impl ExecutionPlan for UnresolvedShuffleExec {
     }
 
     fn output_partitioning(&self) -> Partitioning {
-        Partitioning::UnknownPartitioning(self.partition_count)
+        match &self.hash_exprs {
+            Some(exprs) => Partitioning::Hash(exprs.clone(), self.partition_count),
+            None => Partitioning::UnknownPartitioning(self.partition_count),
+        }
     }
 
     fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
@@ -0,0 +1,118 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tables of record batches that a client uploaded to the executors with Flight `do_put`.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::client::BallistaClient;
+use crate::scheduler::execution_plans::ShuffleReaderExec;
+use crate::scheduler::planner::PartitionLocation;
+
+use arrow::datatypes::SchemaRef;
+use datafusion::datasource::datasource::Statistics;
+use datafusion::datasource::TableProvider;
+use datafusion::error::Result;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::{ExecutionPlan, PhysicalExpr};
+
+/// Table whose partitions are held by executors. The partitions are read in place, like the
+/// output of a query stage, so the data is never sent through the scheduler.
+pub struct UploadedTable {
+    schema: SchemaRef,
+    partitions: Vec<PartitionLocation>,
+}
+
+impl UploadedTable {
+    pub fn new(schema: SchemaRef, partitions: Vec<PartitionLocation>) -> Self {
+        Self { schema, partitions }
+    }
+
+    /// The executors holding each partition of the table
+    pub fn partitions(&self) -> &[PartitionLocation] {
+        &self.partitions
+    }
+
+    /// Remove the partitions of the table from the executors holding them. The table cannot be
+    /// scanned afterwards.
+    pub async fn remove(&self) -> crate::error::Result<()> {
+        let mut removed: Vec<&str> = vec![];
+        for location in &self.partitions {
+            let executor_meta = &location.executor_meta;
+            if removed.contains(&executor_meta.id.as_str()) {
+                continue;
+            }
+            let mut client =
+                BallistaClient::try_new(executor_meta.host.as_str(), executor_meta.port).await?;
+            client
+                .remove_uploaded_table(&location.partition_id.job_uuid)
+                .await?;
+            removed.push(&executor_meta.id);
+        }
+        Ok(())
+    }
+}
+
+impl TableProvider for UploadedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn scan(
+        &self,
+        projection: &Option<Vec<usize>>,
+        _batch_size: usize,
+        _filters: &[Expr],
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let partitions = self
+            .partitions
+            .iter()
+            .map(|location| vec![location.clone()])
+            .collect();
+        let exec = Arc::new(ShuffleReaderExec::try_new(
+            partitions,
+            self.schema.clone(),
+            None,
+        )?);
+        match projection {
+            Some(projection) => {
+                let exprs = projection
+                    .iter()
+                    .map(|i| {
+                        let name = self.schema.field(*i).name();
+                        let expr: Arc<dyn PhysicalExpr> = Arc::new(Column::new(name));
+                        (expr, name.to_owned())
+                    })
+                    .collect();
+                Ok(Arc::new(ProjectionExec::try_new(exprs, exec)?))
+            }
+            None => Ok(exec),
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics {
+            num_rows: None,
+            total_byte_size: None,
+            column_statistics: None,
+        }
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Statistics of the files of a table, which the scheduler returns from `GetFileMetadata` and
+//! which are used when planning queries.
+
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fs::File;
+
+use arrow::datatypes::DataType;
+use datafusion::datasource::datasource::{ColumnStatistics, Statistics};
+use datafusion::datasource::TableProvider;
+use datafusion::physical_plan::csv::{CsvExec, CsvReadOptions};
+use datafusion::physical_plan::parquet::ParquetExec;
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::scalar::ScalarValue;
+use parquet::arrow::parquet_to_arrow_schema;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::statistics::Statistics as ParquetStatistics;
+
+use crate::error::{BallistaError, Result};
+use crate::scheduler::execution_plans::{JsonReadOptions, JsonTable, PartitionedTable};
+use crate::serde::protobuf::{
+    FilePartitionMetadata, FileType, GetFileMetadataParams, GetFileMetadataResult,
+};
+
+/// Get the schema and the partitions of the files of a table. The statistics of Parquet files
+/// are read from their footers, the schema of CSV files is inferred with the options of the
+/// request, and the schema of JSON files from their first records.
+pub fn get_file_metadata(params: &GetFileMetadataParams) -> Result<GetFileMetadataResult> {
+    let file_type: FileType = params.file_type.try_into()?;
+    if !params.partition_columns.is_empty() {
+        return partitioned_file_metadata(params, file_type);
+    }
+    match file_type {
+        FileType::Parquet => {
+            let parquet_exec = ParquetExec::try_from_path(&params.path, None, None, 1024, 1)?;
+            let partitions = parquet_exec
+                .partitions()
+                .iter()
+                .map(|part| {
+                    let statistics = part
+                        .filenames()
+                        .iter()
+                        .map(|filename| (&parquet_file_statistics(filename)?).try_into())
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(FilePartitionMetadata {
+                        filename: part.filenames().to_vec(),
+                        statistics,
+                        partition_values: vec![],
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(GetFileMetadataResult {
+                schema: Some(parquet_exec.schema().as_ref().into()),
+                partitions,
+            })
+        }
+        FileType::Csv => {
+            let csv_exec = CsvExec::try_new(&params.path, csv_options(params), None, 1024)?;
+            Ok(GetFileMetadataResult {
+                schema: Some(csv_exec.file_schema().as_ref().into()),
+                partitions: csv_exec
+                    .filenames()
+                    .iter()
+                    .map(|filename| FilePartitionMetadata {
+                        filename: vec![filename.clone()],
+                        statistics: vec![],
+                        partition_values: vec![],
+                    })
+                    .collect(),
+            })
+        }
+        FileType::NdJson => {
+            let extension = if params.file_extension.is_empty() {
+                ".json"
+            } else {
+                &params.file_extension
+            };
+            let table = JsonTable::try_new(
+                &params.path,
+                JsonReadOptions::new().file_extension(extension),
+            )?;
+            Ok(GetFileMetadataResult {
+                schema: Some(table.schema().as_ref().into()),
+                partitions: table
+                    .filenames()
+                    .iter()
+                    .map(|filename| FilePartitionMetadata {
+                        filename: vec![filename.clone()],
+                        statistics: vec![],
+                        partition_values: vec![],
+                    })
+                    .collect(),
+            })
+        }
+    }
+}
+
+/// Get the metadata of a table stored in Hive-style `column=value` directories. There is one
+/// partition per directory. The directories are pruned later, when the scheduler plans a scan
+/// of the table with the filters of the query.
+fn partitioned_file_metadata(
+    params: &GetFileMetadataParams,
+    file_type: FileType,
+) -> Result<GetFileMetadataResult> {
+    let table = match file_type {
+        FileType::Parquet => {
+            PartitionedTable::try_new_parquet(&params.path, &params.partition_columns, 1)?
+        }
+        FileType::Csv => PartitionedTable::try_new_csv(
+            &params.path,
+            &params.partition_columns,
+            csv_options(params),
+        )?,
+        FileType::NdJson => {
+            return Err(BallistaError::NotImplemented(
+                "get_file_metadata unsupported file type NdJson".to_owned(),
+            ))
+        }
+    };
+    let partitions = table
+        .partitions(&[])
+        .into_iter()
+        .map(|partition| {
+            let statistics = match file_type {
+                FileType::Parquet => partition
+                    .filenames
+                    .iter()
+                    .map(|filename| (&parquet_file_statistics(filename)?).try_into())
+                    .collect::<Result<Vec<_>>>()?,
+                _ => vec![],
+            };
+            Ok(FilePartitionMetadata {
+                filename: partition.filenames.clone(),
+                statistics,
+                partition_values: partition.values.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(GetFileMetadataResult {
+        schema: Some(table.schema().as_ref().into()),
+        partitions,
+    })
+}
+
+/// The options of a request that are used to read CSV files
+fn csv_options(params: &GetFileMetadataParams) -> CsvReadOptions {
+    let mut options = CsvReadOptions::new().has_header(params.has_header);
+    if let Some(delimiter) = params.delimiter.as_bytes().first() {
+        options = options.delimiter(*delimiter);
+    }
+    if !params.file_extension.is_empty() {
+        options = options.file_extension(&params.file_extension);
+    }
+    options
+}
+
+/// Read the row counts, sizes and column statistics of a Parquet file from its footer
+pub fn parquet_file_statistics(filename: &str) -> Result<Statistics> {
+    let parquet_error = |e| BallistaError::General(format!("{}: {:?}", filename, e));
+    let reader = SerializedFileReader::new(File::open(filename)?).map_err(parquet_error)?;
+    let metadata = reader.metadata();
+    let schema = parquet_to_arrow_schema(
+        metadata.file_metadata().schema_descr(),
+        metadata.file_metadata().key_value_metadata(),
+    )
+    .map_err(parquet_error)?;
+
+    let row_groups: Vec<Statistics> = metadata
+        .row_groups()
+        .iter()
+        .map(|row_group| {
+            // column chunks only map to the fields of flat schemas
+            let column_statistics = if row_group.num_columns() == schema.fields().len() {
+                Some(
+                    schema
+                        .fields()
+                        .iter()
+                        .zip(row_group.columns())
+                        .map(|(field, column)| {
+                            column_statistics(column.statistics(), field.data_type())
+                        })
+                        .collect(),
+                )
+            } else {
+                None
+            };
+            Statistics {
+                num_rows: Some(row_group.num_rows() as usize),
+                total_byte_size: Some(row_group.total_byte_size() as usize),
+                column_statistics,
+            }
+        })
+        .collect();
+    if row_groups.is_empty() {
+        return Ok(Statistics {
+            num_rows: Some(0),
+            total_byte_size: Some(0),
+            column_statistics: None,
+        });
+    }
+    Ok(merge_statistics(&row_groups))
+}
+
+/// Combine the statistics of several files or row groups. A value is only known in the result
+/// when it is known for all of the inputs.
+pub fn merge_statistics(statistics: &[Statistics]) -> Statistics {
+    let num_rows = statistics.iter().map(|s| s.num_rows).sum();
+    let total_byte_size = statistics.iter().map(|s| s.total_byte_size).sum();
+    let column_statistics = statistics
+        .iter()
+        .map(|s| s.column_statistics.as_ref())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|columns| {
+            let num_columns = columns.first()?.len();
+            if columns.iter().any(|c| c.len() != num_columns) {
+                return None;
+            }
+            Some(
+                (0..num_columns)
+                    .map(|i| merge_column_statistics(columns.iter().map(|c| &c[i])))
+                    .collect(),
+            )
+        });
+    Statistics {
+        num_rows,
+        total_byte_size,
+        column_statistics,
+    }
+}
+
+fn merge_column_statistics<'a>(
+    mut columns: impl Iterator<Item = &'a ColumnStatistics>,
+) -> ColumnStatistics {
+    let first = match columns.next() {
+        Some(first) => first.clone(),
+        None => return unknown_column_statistics(),
+    };
+    columns.fold(first, |acc, column| ColumnStatistics {
+        null_count: acc
+            .null_count
+            .and_then(|n| column.null_count.map(|m| n + m)),
+        min_value: merge_bound(acc.min_value, &column.min_value, Ordering::Less),
+        max_value: merge_bound(acc.max_value, &column.max_value, Ordering::Greater),
+        distinct_count: None,
+    })
+}
+
+/// The bound that is `ordering` compared to the other one, if both are known and comparable
+fn merge_bound(
+    acc: Option<ScalarValue>,
+    value: &Option<ScalarValue>,
+    ordering: Ordering,
+) -> Option<ScalarValue> {
+    let (acc, value) = (acc?, value.as_ref()?);
+    if compare(value, &acc)? == ordering {
+        Some(value.clone())
+    } else {
+        Some(acc)
+    }
+}
+
+fn compare(left: &ScalarValue, right: &ScalarValue) -> Option<Ordering> {
+    match (left, right) {
+        (ScalarValue::Boolean(Some(l)), ScalarValue::Boolean(Some(r))) => l.partial_cmp(r),
+        (ScalarValue::Int32(Some(l)), ScalarValue::Int32(Some(r))) => l.partial_cmp(r),
+        (ScalarValue::Int64(Some(l)), ScalarValue::Int64(Some(r))) => l.partial_cmp(r),
+        (ScalarValue::Float32(Some(l)), ScalarValue::Float32(Some(r))) => l.partial_cmp(r),
+        (ScalarValue::Float64(Some(l)), ScalarValue::Float64(Some(r))) => l.partial_cmp(r),
+        (ScalarValue::Utf8(Some(l)), ScalarValue::Utf8(Some(r))) => l.partial_cmp(r),
+        _ => None,
+    }
+}
+
+fn unknown_column_statistics() -> ColumnStatistics {
+    ColumnStatistics {
+        null_count: None,
+        max_value: None,
+        min_value: None,
+        distinct_count: None,
+    }
+}
+
+/// Convert the statistics of a column chunk. Min/max values are only kept for the types that
+/// they can be compared for.
+fn column_statistics(
+    statistics: Option<&ParquetStatistics>,
+    data_type: &DataType,
+) -> ColumnStatistics {
+    let statistics = match statistics {
+        Some(statistics) => statistics,
+        None => return unknown_column_statistics(),
+    };
+    let bounds = if statistics.has_min_max_set() {
+        match (statistics, data_type) {
+            (ParquetStatistics::Boolean(s), DataType::Boolean) => Some((
+                ScalarValue::Boolean(Some(*s.min())),
+                ScalarValue::Boolean(Some(*s.max())),
+            )),
+            (ParquetStatistics::Int32(s), DataType::Int32) => Some((
+                ScalarValue::Int32(Some(*s.min())),
+                ScalarValue::Int32(Some(*s.max())),
+            )),
+            (ParquetStatistics::Int64(s), DataType::Int64) => Some((
+                ScalarValue::Int64(Some(*s.min())),
+                ScalarValue::Int64(Some(*s.max())),
+            )),
+            (ParquetStatistics::Float(s), DataType::Float32) => Some((
+                ScalarValue::Float32(Some(*s.min())),
+                ScalarValue::Float32(Some(*s.max())),
+            )),
+            (ParquetStatistics::Double(s), DataType::Float64) => Some((
+                ScalarValue::Float64(Some(*s.min())),
+                ScalarValue::Float64(Some(*s.max())),
+            )),
+            (ParquetStatistics::ByteArray(s), DataType::Utf8) => {
+                match (s.min().as_utf8(), s.max().as_utf8()) {
+                    (Ok(min), Ok(max)) => Some((
+                        ScalarValue::Utf8(Some(min.to_owned())),
+                        ScalarValue::Utf8(Some(max.to_owned())),
+                    )),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    } else {
+        None
+    };
+    let (min_value, max_value) = match bounds {
+        Some((min, max)) => (Some(min), Some(max)),
+        None => (None, None),
+    };
+    ColumnStatistics {
+        null_count: Some(statistics.null_count() as usize),
+        max_value,
+        min_value,
+        distinct_count: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::sync::Arc;
+
+    use arrow::array::{Int32Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use datafusion::datasource::datasource::Statistics;
+    use datafusion::scalar::ScalarValue;
+    use parquet::arrow::ArrowWriter;
+
+    use super::*;
+
+    #[test]
+    fn parquet_statistics() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, true),
+            Field::new("b", DataType::Utf8, false),
+        ]));
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.parquet");
+        let parquet_error = |e| BallistaError::General(format!("{:?}", e));
+        let mut writer = ArrowWriter::try_new(File::create(&path)?, schema.clone(), None)
+            .map_err(parquet_error)?;
+        // each batch is written as a row group
+        for (a, b) in vec![
+            (vec![Some(3), None, Some(1)], vec!["x", "y", "z"]),
+            (vec![Some(7), Some(5), None], vec!["c", "d", "e"]),
+        ] {
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int32Array::from(a)),
+                    Arc::new(StringArray::from(b)),
+                ],
+            )?;
+            writer.write(&batch).map_err(parquet_error)?;
+        }
+        writer.close().map_err(parquet_error)?;
+
+        let statistics = parquet_file_statistics(path.to_str().unwrap())?;
+        assert_eq!(statistics.num_rows, Some(6));
+        let columns = statistics.column_statistics.as_ref().unwrap();
+        assert_eq!(columns[0].null_count, Some(2));
+        assert_eq!(columns[0].min_value, Some(ScalarValue::Int32(Some(1))));
+        assert_eq!(columns[0].max_value, Some(ScalarValue::Int32(Some(7))));
+        assert_eq!(columns[1].null_count, Some(0));
+        assert_eq!(
+            columns[1].min_value,
+            Some(ScalarValue::Utf8(Some("c".to_owned())))
+        );
+        assert_eq!(
+            columns[1].max_value,
+            Some(ScalarValue::Utf8(Some("z".to_owned())))
+        );
+
+        // statistics are sent to clients in GetFileMetadata
+        let proto: crate::serde::protobuf::FileStatistics = (&statistics).try_into()?;
+        let roundtrip: Statistics = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", statistics), format!("{:?}", roundtrip));
+
+        // unknown statistics stay unknown rather than becoming zero
+        let unknown = Statistics::default();
+        let proto: crate::serde::protobuf::FileStatistics = (&unknown).try_into()?;
+        let roundtrip: Statistics = (&proto).try_into()?;
+        assert_eq!(roundtrip.num_rows, None);
+        assert_eq!(roundtrip.total_byte_size, None);
+        Ok(())
+    }
+
+    #[test]
+    fn csv_metadata() -> Result<()> {
+        let metadata = get_file_metadata(&GetFileMetadataParams {
+            path: "testdata/nation".to_owned(),
+            file_type: FileType::Csv as i32,
+            has_header: false,
+            delimiter: "|".to_owned(),
+            file_extension: ".tbl".to_owned(),
+            partition_columns: vec![],
+        })?;
+        assert_eq!(metadata.partitions.len(), 1);
+        assert!(metadata.partitions[0].filename[0].ends_with("nation.tbl"));
+        let schema: Schema = metadata.schema.as_ref().unwrap().try_into()?;
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+        Ok(())
+    }
+
+    #[test]
+    fn partitioned_csv_metadata() -> Result<()> {
+        use std::io::Write;
+
+        let dir = tempfile::TempDir::new()?;
+        for region in &["africa", "europe"] {
+            let path = dir.path().join(format!("region={}", region));
+            std::fs::create_dir(&path)?;
+            File::create(path.join("nation.tbl"))?.write_all(b"1|x\n2|y\n")?;
+        }
+        let metadata = get_file_metadata(&GetFileMetadataParams {
+            path: dir.path().to_str().unwrap().to_owned(),
+            file_type: FileType::Csv as i32,
+            has_header: false,
+            delimiter: "|".to_owned(),
+            file_extension: ".tbl".to_owned(),
+            partition_columns: vec!["region".to_owned()],
+        })?;
+        assert_eq!(metadata.partitions.len(), 2);
+        let mut values: Vec<_> = metadata
+            .partitions
+            .iter()
+            .map(|partition| partition.partition_values.clone())
+            .collect();
+        values.sort();
+        assert_eq!(values, vec![vec!["africa"], vec!["europe"]]);
+        let schema: Schema = metadata.schema.as_ref().unwrap().try_into()?;
+        assert_eq!(schema.fields().len(), 3);
+        assert_eq!(schema.field(2).name(), "region");
+        Ok(())
+    }
+}
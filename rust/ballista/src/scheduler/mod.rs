@@ -15,22 +15,28 @@
 //! Support for distributed schedulers, such as Kubernetes
 
 pub mod execution_plans;
+pub mod file_metadata;
 pub mod planner;
 pub mod state;
 
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fmt;
+use std::sync::Arc;
 
+use crate::serde::extension::extension_config;
 use crate::serde::protobuf::{
-    execute_query_params::Query, job_status, scheduler_grpc_server::SchedulerGrpc, CompletedJob,
-    ExecuteQueryParams, ExecuteQueryResult, ExecuteSqlParams, ExecutorMetadata, FailedJob,
-    FilePartitionMetadata, FileType, GetExecutorMetadataParams, GetExecutorMetadataResult,
-    GetFileMetadataParams, GetFileMetadataResult, GetJobStatusParams, GetJobStatusResult,
-    JobStatus, PartitionLocation, QueuedJob, RegisterExecutorParams, RegisterExecutorResult,
-    RunningJob,
+    execute_query_params::Query, job_status, scheduler_grpc_server::SchedulerGrpc, CancelJobParams,
+    CancelJobResult, CompletedJob, CreateSessionParams, CreateSessionResult, ExecuteQueryParams,
+    ExecuteQueryResult, ExecuteSqlParams, ExecutorMetadata, FailedJob, GetExecutorMetadataParams,
+    GetExecutorMetadataResult, GetFileMetadataParams, GetFileMetadataResult, GetJobStatusParams,
+    GetJobStatusResult, JobState, JobStatus, ListJobsParams, ListJobsResult, LogicalPlanNode,
+    PartitionLocation, PhysicalPlanNode, PollWorkParams, PollWorkResult, QueuedJob,
+    RegisterExecutorParams, RegisterExecutorResult, RegisterTableParams, RegisterTableResult,
+    RunningJob, SessionState, TaskDefinition, TaskStatus,
 };
-use crate::serde::scheduler::ExecutorMeta;
+use crate::serde::scheduler::{ExecutorMeta, PartitionId};
 
 use clap::arg_enum;
 use datafusion::physical_plan::ExecutionPlan;
@@ -51,35 +57,228 @@ impl parse_arg::ParseArgFromStr for ConfigBackend {
     }
 }
 
-use crate::{client::BallistaClient, error::Result, serde::scheduler::Action};
+use crate::client::BallistaClient;
+use crate::context::{register_udfs, DFTableAdapter};
+use crate::{error::Result, serde::scheduler::Action};
 use crate::{prelude::BallistaError, scheduler::planner::DistributedPlanner};
-use execution_plans::ShuffleReaderExec;
 
 use arrow::datatypes::{Schema, SchemaRef};
 use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
+use datafusion::logical_plan::{LogicalPlan, LogicalPlanBuilder};
+use datafusion::physical_plan::csv::CsvReadOptions;
 use log::{debug, error, info, warn};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use tonic::{Request, Response};
+use uuid::Uuid;
 
 use self::state::{ConfigBackendClient, SchedulerState};
 use crate::utils::format_plan;
-use datafusion::physical_plan::parquet::ParquetExec;
 use std::time::Instant;
 
+/// Number of times a task is attempted before its job fails, unless configured otherwise
+pub const DEFAULT_MAX_TASK_ATTEMPTS: usize = 3;
+
+/// Target size in bytes of the ranges that large files are split into, unless configured
+/// otherwise
+pub const DEFAULT_SPLIT_SIZE: u64 = 128 * 1024 * 1024;
+
 pub struct SchedulerServer<Config: ConfigBackendClient> {
     state: SchedulerState<Config>,
     namespace: String,
+    split_size: u64,
 }
 
 impl<Config: ConfigBackendClient> SchedulerServer<Config> {
-    pub fn new(config: Config, namespace: String) -> Self {
+    /// Create a scheduler that gives up on a job once one of its tasks has been attempted
+    /// `max_task_attempts` times. Parquet and CSV files larger than `split_size` bytes are
+    /// scanned as several partitions, unless `split_size` is zero.
+    pub fn new(
+        config: Config,
+        namespace: String,
+        max_task_attempts: usize,
+        split_size: u64,
+    ) -> Self {
         Self {
-            state: SchedulerState::new(config),
+            state: SchedulerState::new(config, max_task_attempts),
             namespace,
+            split_size,
         }
     }
 }
 
+impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerServer<T> {
+    /// Create a job for a logical plan and plan its query stages in the background. The
+    /// DataFusion context is used to create the physical plan.
+    async fn submit_job(
+        &self,
+        plan: LogicalPlan,
+        datafusion_ctx: ExecutionContext,
+    ) -> std::result::Result<String, tonic::Status> {
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+
+        // Save placeholder job metadata
+        self.state
+            .create_job(
+                &self.namespace,
+                &job_id,
+                format!("{}", plan.display_indent()),
+            )
+            .await
+            .map_err(|e| tonic::Status::internal(format!("Could not save job metadata: {}", e)))?;
+
+        let namespace = self.namespace.to_owned();
+        let state = self.state.clone();
+        let split_size = self.split_size;
+        // the scans of split files read batches of the size configured for the session
+        let batch_size = datafusion_ctx.state.lock().unwrap().config.batch_size;
+        let job_id_spawn = job_id.clone();
+        tokio::spawn(async move {
+            // create physical plan using DataFusion
+            macro_rules! fail_job {
+                ($code :expr) => {{
+                    match $code {
+                        Err(error) => {
+                            warn!("Job {} failed with {}", job_id_spawn, error);
+                            state
+                                .save_job_metadata_unless_cancelled(
+                                    &namespace,
+                                    &job_id_spawn,
+                                    &JobStatus {
+                                        status: Some(job_status::Status::Failed(FailedJob {
+                                            error: format!("{}", error),
+                                        })),
+                                        task_attempts: 0,
+                                    },
+                                )
+                                .await
+                                .unwrap();
+                            return;
+                        }
+                        Ok(value) => value,
+                    }
+                }};
+            };
+
+            let start = Instant::now();
+
+            let plan = fail_job!(datafusion_ctx
+                .optimize(&plan)
+                .and_then(|plan| datafusion_ctx.create_physical_plan(&plan))
+                .map_err(|e| {
+                    let msg = format!("Could not create physical plan: {}", e);
+                    error!("{}", msg);
+                    tonic::Status::internal(msg)
+                }));
+
+            info!(
+                "DataFusion created physical plan in {} seconds",
+                start.elapsed().as_secs(),
+            );
+
+            // create distributed physical plan using Ballista
+            let mut planner = DistributedPlanner::with_split_size(split_size, batch_size);
+            let stages = fail_job!(planner.plan_query_stages(&job_uuid, plan).map_err(|e| {
+                let msg = format!("Could not plan query stages: {}", e);
+                error!("{}", msg);
+                tonic::Status::internal(msg)
+            }));
+
+            // save the stage plans and a pending task for each input partition. The whole
+            // stage is saved so that executors know how to partition the shuffle output.
+            for stage in &stages {
+                info!("{}", fail_job!(format_plan(stage.as_ref(), 0)));
+                fail_job!(
+                    state
+                        .save_stage_plan(&namespace, &job_id_spawn, stage.stage_id, stage.clone())
+                        .await
+                );
+                let num_partitions = stage.children()[0].output_partitioning().partition_count();
+                for partition_id in 0..num_partitions {
+                    let pending_status = TaskStatus {
+                        partition_id: Some(
+                            PartitionId::new(job_uuid, stage.stage_id, partition_id).into(),
+                        ),
+                        status: None,
+                        attempts: 0,
+                        failed_executor_ids: vec![],
+                    };
+                    fail_job!(state.save_task_status(&namespace, &pending_status).await);
+                }
+            }
+
+            // tasks are only handed out to executors once their job is running
+            match state
+                .save_job_metadata_unless_cancelled(
+                    &namespace,
+                    &job_id_spawn,
+                    &JobStatus {
+                        status: Some(job_status::Status::Running(RunningJob {})),
+                        task_attempts: 0,
+                    },
+                )
+                .await
+            {
+                Ok(true) => {
+                    // record the stages of the job and their initial progress
+                    if let Err(e) = state
+                        .synchronize_job_status(&namespace, &job_id_spawn)
+                        .await
+                    {
+                        warn!(
+                            "Could not synchronize status of job {}: {}",
+                            job_id_spawn, e
+                        );
+                    }
+                }
+                Ok(false) => info!("Job {} was cancelled while it was planned", job_id_spawn),
+                Err(e) => warn!(
+                    "Could not update job {} status to running: {}",
+                    job_id_spawn, e
+                ),
+            }
+        });
+
+        Ok(job_id)
+    }
+
+    /// Tell the executors to remove the work files of a finished job. The output of the final
+    /// stage of a completed job is kept, because the results of the job are fetched from it.
+    async fn remove_job_data(&self, job_id: &str, status: &JobStatus) -> Result<()> {
+        let job_uuid = Uuid::parse_str(job_id)
+            .map_err(|e| BallistaError::General(format!("Invalid job id {}: {}", job_id, e)))?;
+        let keep_stages: Vec<usize> = match &status.status {
+            Some(job_status::Status::Completed(completed)) => completed
+                .partition_location
+                .iter()
+                .filter_map(|location| location.partition_id.as_ref())
+                .map(|partition_id| partition_id.stage_id as usize)
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect(),
+            _ => vec![],
+        };
+        let executors = self.state.get_executors_metadata(&self.namespace).await?;
+        tokio::spawn(async move {
+            for executor in executors {
+                let result = async {
+                    BallistaClient::try_new(&executor.host, executor.port)
+                        .await?
+                        .remove_job_data(&job_uuid, keep_stages.clone())
+                        .await
+                }
+                .await;
+                if let Err(e) = result {
+                    warn!(
+                        "Could not remove the work files of job {} on executor {}: {}",
+                        job_uuid, executor.id, e
+                    );
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
 #[tonic::async_trait]
 impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for SchedulerServer<T> {
     async fn get_executors_metadata(
@@ -113,6 +312,7 @@ impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for Scheduler
         } = request.into_inner()
         {
             info!("Received register_executor request for {:?}", metadata);
+            check_task_slots(&metadata)?;
             self.state
                 .save_executor_metadata(&self.namespace, metadata.into())
                 .await
@@ -134,40 +334,21 @@ impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for Scheduler
         &self,
         request: Request<GetFileMetadataParams>,
     ) -> std::result::Result<Response<GetFileMetadataResult>, tonic::Status> {
-        let GetFileMetadataParams { path, file_type } = request.into_inner();
-
-        let file_type: FileType = file_type.try_into().map_err(|e| {
-            let msg = format!("Error reading request: {}", e);
-            error!("{}", msg);
-            tonic::Status::internal(msg)
-        })?;
-
-        match file_type {
-            FileType::Parquet => {
-                let parquet_exec =
-                    ParquetExec::try_from_path(&path, None, None, 1024, 1).map_err(|e| {
-                        let msg = format!("Error opening parquet files: {}", e);
-                        error!("{}", msg);
-                        tonic::Status::internal(msg)
-                    })?;
-
-                //TODO include statistics and any other info needed to reconstruct ParquetExec
-                Ok(Response::new(GetFileMetadataResult {
-                    schema: Some(parquet_exec.schema().as_ref().into()),
-                    partitions: parquet_exec
-                        .partitions()
-                        .iter()
-                        .map(|part| FilePartitionMetadata {
-                            filename: part.filenames().to_vec(),
-                        })
-                        .collect(),
-                }))
-            }
-            //TODO implement for CSV
-            _ => Err(tonic::Status::unimplemented(
-                "get_file_metadata unsupported file type",
-            )),
-        }
+        let params = request.into_inner();
+        // listing and reading the files blocks, so it is done on a blocking thread
+        let path = params.path.clone();
+        tokio::task::spawn_blocking(move || file_metadata::get_file_metadata(&params))
+            .await
+            .map_err(|e| tonic::Status::internal(format!("{:?}", e)))?
+            .map(Response::new)
+            .map_err(|e| match e {
+                BallistaError::NotImplemented(msg) => tonic::Status::unimplemented(msg),
+                e => {
+                    let msg = format!("Error reading metadata of {}: {}", path, e);
+                    error!("{}", msg);
+                    tonic::Status::internal(msg)
+                }
+            })
     }
 
     async fn execute_query(
@@ -175,6 +356,11 @@ impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for Scheduler
         request: Request<ExecuteQueryParams>,
     ) -> std::result::Result<Response<ExecuteQueryResult>, tonic::Status> {
         if let ExecuteQueryParams { query: Some(query) } = request.into_inner() {
+            let config = extension_config().map_err(|e| {
+                let msg = format!("Could not create execution context: {}", e);
+                error!("{}", msg);
+                tonic::Status::internal(msg)
+            })?;
             let plan = match query {
                 Query::LogicalPlan(logical_plan) => {
                     // parse protobuf
@@ -185,9 +371,9 @@ impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for Scheduler
                     })?
                 }
                 Query::Sql(sql) => {
-                    //TODO we can't just create a new context because we need a context that has
-                    // tables registered from previous SQL statements that have been executed
-                    let mut ctx = ExecutionContext::new();
+                    // SQL sent without a session cannot reference any tables. Use ExecuteSql
+                    // with a session to query registered tables.
+                    let mut ctx = ExecutionContext::with_config(config.clone());
                     let df = ctx.sql(&sql).map_err(|e| {
                         let msg = format!("Error parsing SQL: {}", e);
                         error!("{}", msg);
@@ -197,140 +383,261 @@ impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for Scheduler
                 }
             };
             debug!("Received plan for execution: {:?}", plan);
-            let executors = self
-                .state
-                .get_executors_metadata(&self.namespace)
+            let job_id = self
+                .submit_job(plan, ExecutionContext::with_config(config))
+                .await?;
+            Ok(Response::new(ExecuteQueryResult { job_id }))
+        } else {
+            Err(tonic::Status::internal("Error parsing request"))
+        }
+    }
+
+    async fn create_session(
+        &self,
+        request: Request<CreateSessionParams>,
+    ) -> std::result::Result<Response<CreateSessionResult>, tonic::Status> {
+        let CreateSessionParams { settings } = request.into_inner();
+        let session = SessionState {
+            session_id: Uuid::new_v4().to_string(),
+            tables: vec![],
+            settings,
+        };
+        // reject invalid settings up front rather than on every query
+        session_config(&session).map_err(|e| {
+            let msg = format!("Invalid session settings: {}", e);
+            error!("{}", msg);
+            tonic::Status::invalid_argument(msg)
+        })?;
+        self.state
+            .save_session(&self.namespace, &session)
+            .await
+            .map_err(|e| {
+                let msg = format!("Could not save session: {}", e);
+                error!("{}", msg);
+                tonic::Status::internal(msg)
+            })?;
+        info!("Created session {}", session.session_id);
+        Ok(Response::new(CreateSessionResult {
+            session_id: session.session_id,
+        }))
+    }
+
+    async fn register_table(
+        &self,
+        request: Request<RegisterTableParams>,
+    ) -> std::result::Result<Response<RegisterTableResult>, tonic::Status> {
+        if let RegisterTableParams {
+            session_id,
+            name,
+            plan: Some(plan),
+        } = request.into_inner()
+        {
+            info!("Registering table {} in session {}", name, session_id);
+            self.state
+                .register_session_table(&self.namespace, &session_id, &name, plan)
                 .await
                 .map_err(|e| {
-                    let msg = format!("Error reading executors metadata: {}", e);
+                    let msg = format!("Could not register table {}: {}", name, e);
                     error!("{}", msg);
                     tonic::Status::internal(msg)
                 })?;
-            debug!("Found executors: {:?}", executors);
-
-            let job_id: String = {
-                let mut rng = thread_rng();
-                std::iter::repeat(())
-                    .map(|()| rng.sample(Alphanumeric))
-                    .map(char::from)
-                    .take(7)
-                    .collect()
-            };
+            Ok(Response::new(RegisterTableResult {}))
+        } else {
+            Err(tonic::Status::invalid_argument("Missing plan in request"))
+        }
+    }
 
-            // Save placeholder job metadata
-            self.state
-                .save_job_metadata(
-                    &self.namespace,
-                    &job_id,
-                    &JobStatus {
-                        status: Some(job_status::Status::Queued(QueuedJob {})),
-                    },
-                )
-                .await
+    async fn execute_sql(
+        &self,
+        request: Request<ExecuteSqlParams>,
+    ) -> std::result::Result<Response<ExecuteQueryResult>, tonic::Status> {
+        let ExecuteSqlParams { sql, session_id } = request.into_inner();
+        let session = self
+            .state
+            .get_session(&self.namespace, &session_id)
+            .await
+            .map_err(|e| {
+                let msg = format!("Could not read session {}: {}", session_id, e);
+                error!("{}", msg);
+                tonic::Status::internal(msg)
+            })?
+            .ok_or_else(|| {
+                tonic::Status::not_found(format!("Session {} does not exist", session_id))
+            })?;
+        let (ctx, config) = session_context(&session).map_err(|e| {
+            let msg = format!("Could not create context for session {}: {}", session_id, e);
+            error!("{}", msg);
+            tonic::Status::internal(msg)
+        })?;
+        let plan = ctx.create_logical_plan(&sql).map_err(|e| {
+            let msg = format!("Error parsing SQL: {}", e);
+            error!("{}", msg);
+            tonic::Status::invalid_argument(msg)
+        })?;
+
+        match plan {
+            LogicalPlan::CreateExternalTable {
+                schema,
+                name,
+                location,
+                file_type,
+                has_header,
+            } => {
+                let table_plan = match file_type {
+                    datafusion::sql::parser::FileType::CSV => {
+                        let schema: Schema = schema.as_ref().clone().into();
+                        let options = CsvReadOptions::new().schema(&schema).has_header(has_header);
+                        LogicalPlanBuilder::scan_csv(&location, options, None)
+                            .and_then(|builder| builder.build())
+                    }
+                    datafusion::sql::parser::FileType::Parquet => {
+                        LogicalPlanBuilder::scan_parquet(&location, None, config.concurrency)
+                            .and_then(|builder| builder.build())
+                    }
+                    other => {
+                        return Err(tonic::Status::unimplemented(format!(
+                            "Unsupported file type {:?}",
+                            other
+                        )))
+                    }
+                }
                 .map_err(|e| {
-                    tonic::Status::internal(format!("Could not save job metadata: {}", e))
+                    let msg = format!("Could not scan {}: {}", location, e);
+                    error!("{}", msg);
+                    tonic::Status::internal(msg)
                 })?;
-
-            // TODO: handle errors once we have more job metadata
-            let namespace = self.namespace.to_owned();
-            let state = self.state.clone();
-            let job_id_spawn = job_id.clone();
-            tokio::spawn(async move {
-                // create physical plan using DataFusion
-                let datafusion_ctx = ExecutionContext::new();
-                macro_rules! fail_job {
-                    ($code :expr) => {{
-                        match $code {
-                            Err(error) => {
-                                warn!("Job {} failed with {}", job_id_spawn, error);
-                                state
-                                    .save_job_metadata(
-                                        &namespace,
-                                        &job_id_spawn,
-                                        &JobStatus {
-                                            status: Some(job_status::Status::Failed(FailedJob {
-                                                error: format!("{}", error),
-                                            })),
-                                        },
-                                    )
-                                    .await
-                                    .unwrap();
-                                return;
-                            }
-                            Ok(value) => value,
-                        }
-                    }};
-                };
-
-                let start = Instant::now();
-
-                let plan = fail_job!(datafusion_ctx
-                    .optimize(&plan)
-                    .and_then(|plan| datafusion_ctx.create_physical_plan(&plan))
+                let table_plan: LogicalPlanNode = (&table_plan).try_into().map_err(|e| {
+                    let msg = format!("Could not serialize plan of table {}: {}", name, e);
+                    error!("{}", msg);
+                    tonic::Status::internal(msg)
+                })?;
+                self.state
+                    .register_session_table(&self.namespace, &session_id, &name, table_plan)
+                    .await
                     .map_err(|e| {
-                        let msg = format!("Could not create physical plan: {}", e);
+                        let msg = format!("Could not register table {}: {}", name, e);
                         error!("{}", msg);
                         tonic::Status::internal(msg)
-                    }));
-
-                info!(
-                    "DataFusion created physical plan in {} seconds",
-                    start.elapsed().as_secs(),
-                );
+                    })?;
+                info!("Registered table {} in session {}", name, session_id);
 
-                // create distributed physical plan using Ballista
-                if let Err(e) = state
+                // the statement has no results, so its job completes straight away
+                let job_id = Uuid::new_v4().to_string();
+                self.state
+                    .create_job(&self.namespace, &job_id, sql)
+                    .await
+                    .map_err(|e| {
+                        tonic::Status::internal(format!("Could not save job metadata: {}", e))
+                    })?;
+                self.state
                     .save_job_metadata(
-                        &namespace,
-                        &job_id_spawn,
+                        &self.namespace,
+                        &job_id,
                         &JobStatus {
-                            status: Some(job_status::Status::Running(RunningJob {})),
+                            status: Some(job_status::Status::Completed(CompletedJob {
+                                partition_location: vec![],
+                            })),
+                            task_attempts: 0,
                         },
                     )
                     .await
-                {
-                    warn!(
-                        "Could not update job {} status to running: {}",
-                        job_id_spawn, e
-                    );
-                }
-                let mut planner = fail_job!(DistributedPlanner::try_new(executors).map_err(|e| {
-                    let msg = format!("Could not create distributed planner: {}", e);
-                    error!("{}", msg);
-                    tonic::Status::internal(msg)
-                }));
-                let plan = fail_job!(planner.execute_distributed_query(plan).await.map_err(|e| {
-                    let msg = format!("Could not execute distributed plan: {}", e);
+                    .map_err(|e| {
+                        tonic::Status::internal(format!("Could not save job metadata: {}", e))
+                    })?;
+                Ok(Response::new(ExecuteQueryResult { job_id }))
+            }
+            plan => {
+                debug!("Received plan for execution: {:?}", plan);
+                let job_id = self.submit_job(plan, ctx).await?;
+                Ok(Response::new(ExecuteQueryResult { job_id }))
+            }
+        }
+    }
+
+    async fn poll_work(
+        &self,
+        request: Request<PollWorkParams>,
+    ) -> std::result::Result<Response<PollWorkResult>, tonic::Status> {
+        if let PollWorkParams {
+            metadata: Some(metadata),
+            can_accept_task,
+            task_status,
+        } = request.into_inner()
+        {
+            debug!("Received poll_work request for {:?}", metadata);
+            let executor_id = metadata.id.clone();
+            check_task_slots(&metadata)?;
+            // polling for work doubles as a heartbeat for the executor
+            self.state
+                .save_executor_metadata(&self.namespace, metadata.into())
+                .await
+                .map_err(|e| {
+                    let msg = format!("Could not save executor metadata: {}", e);
                     error!("{}", msg);
                     tonic::Status::internal(msg)
-                }));
+                })?;
 
-                // save partition info into job's state
-                let plan = plan
-                    .as_any()
-                    .downcast_ref::<ShuffleReaderExec>()
-                    .expect("Expected plan final operator to be ShuffleReaderExec");
-                let mut partition_location = vec![];
-                for loc in &plan.partition_location {
-                    partition_location.push(loc.clone().try_into().unwrap());
+            let mut jobs = HashSet::new();
+            for status in task_status {
+                if let Some(partition_id) = &status.partition_id {
+                    jobs.insert(partition_id.job_uuid.clone());
                 }
-                state
-                    .save_job_metadata(
-                        &namespace,
-                        &job_id_spawn,
-                        &JobStatus {
-                            status: Some(job_status::Status::Completed(CompletedJob {
-                                partition_location,
-                            })),
-                        },
-                    )
+                self.state
+                    .update_task_status(&self.namespace, &executor_id, status)
                     .await
-                    .unwrap();
-            });
+                    .map_err(|e| {
+                        let msg = format!("Could not save task status: {}", e);
+                        error!("{}", msg);
+                        tonic::Status::internal(msg)
+                    })?;
+            }
+            for job_id in jobs {
+                let finished = self
+                    .state
+                    .synchronize_job_status(&self.namespace, &job_id)
+                    .await
+                    .map_err(|e| {
+                        let msg = format!("Could not synchronize status of job {}: {}", job_id, e);
+                        error!("{}", msg);
+                        tonic::Status::internal(msg)
+                    })?;
+                // the shuffle files of the job are no longer needed once it has finished
+                if let Some(status) = finished {
+                    if let Err(e) = self.remove_job_data(&job_id, &status).await {
+                        warn!("Could not remove the work files of job {}: {}", job_id, e);
+                    }
+                }
+            }
 
-            Ok(Response::new(ExecuteQueryResult { job_id }))
+            let mut task = None;
+            if can_accept_task {
+                let assignment = self
+                    .state
+                    .assign_next_schedulable_task(&self.namespace, &executor_id)
+                    .await
+                    .map_err(|e| {
+                        let msg = format!("Error finding next assignable task: {}", e);
+                        error!("{}", msg);
+                        tonic::Status::internal(msg)
+                    })?;
+                if let Some((status, plan)) = assignment {
+                    let plan: PhysicalPlanNode = plan.try_into().map_err(|e| {
+                        let msg = format!("Could not serialize task plan: {}", e);
+                        error!("{}", msg);
+                        tonic::Status::internal(msg)
+                    })?;
+                    task = Some(TaskDefinition {
+                        task_id: status.partition_id,
+                        plan: Some(plan),
+                    });
+                }
+            }
+            Ok(Response::new(PollWorkResult { task }))
         } else {
-            Err(tonic::Status::internal("Error parsing request"))
+            warn!("Received invalid executor poll_work request");
+            Err(tonic::Status::invalid_argument(
+                "Missing metadata in request",
+            ))
         }
     }
 
@@ -348,9 +655,281 @@ impl<T: ConfigBackendClient + Send + Sync + 'static> SchedulerGrpc for Scheduler
                 let msg = format!("Error reading job metadata: {}", e);
                 error!("{}", msg);
                 tonic::Status::internal(msg)
-            })?;
+            })?
+            .ok_or_else(|| tonic::Status::not_found(format!("Job {} does not exist", job_id)))?;
         Ok(Response::new(GetJobStatusResult {
             status: Some(job_meta),
         }))
     }
+
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsParams>,
+    ) -> std::result::Result<Response<ListJobsResult>, tonic::Status> {
+        let ListJobsParams { namespace, status } = request.into_inner();
+        let namespace = if namespace.is_empty() {
+            self.namespace.clone()
+        } else {
+            namespace
+        };
+        let states = status
+            .into_iter()
+            .map(|state| {
+                JobState::from_i32(state).ok_or_else(|| {
+                    tonic::Status::invalid_argument(format!("Invalid job state {}", state))
+                })
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        debug!(
+            "Received list_jobs request for namespace {} and states {:?}",
+            namespace, states
+        );
+        let jobs = self
+            .state
+            .list_jobs(&namespace, &states)
+            .await
+            .map_err(|e| {
+                let msg = format!("Error reading jobs: {}", e);
+                error!("{}", msg);
+                tonic::Status::internal(msg)
+            })?;
+        Ok(Response::new(ListJobsResult { jobs }))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobParams>,
+    ) -> std::result::Result<Response<CancelJobResult>, tonic::Status> {
+        let job_id = request.into_inner().job_id;
+        info!("Received cancel_job request for job {}", job_id);
+        let job_uuid = Uuid::parse_str(&job_id).map_err(|e| {
+            let msg = format!("Invalid job id {}: {}", job_id, e);
+            error!("{}", msg);
+            tonic::Status::invalid_argument(msg)
+        })?;
+        let cancelled = self
+            .state
+            .cancel_job(&self.namespace, &job_id)
+            .await
+            .map_err(|e| {
+                let msg = format!("Could not cancel job {}: {}", job_id, e);
+                error!("{}", msg);
+                tonic::Status::internal(msg)
+            })?;
+
+        if cancelled {
+            // abort the tasks that executors are running for the job and remove its files
+            let executors = self
+                .state
+                .get_executors_metadata(&self.namespace)
+                .await
+                .map_err(|e| {
+                    let msg = format!("Error reading executors metadata: {}", e);
+                    error!("{}", msg);
+                    tonic::Status::internal(msg)
+                })?;
+            tokio::spawn(async move {
+                for executor in executors {
+                    let result = async {
+                        BallistaClient::try_new(&executor.host, executor.port)
+                            .await?
+                            .cancel_job(&job_uuid)
+                            .await
+                    }
+                    .await;
+                    if let Err(e) = result {
+                        warn!(
+                            "Could not cancel job {} on executor {}: {}",
+                            job_uuid, executor.id, e
+                        );
+                    }
+                }
+            });
+        }
+        Ok(Response::new(CancelJobResult { cancelled }))
+    }
+}
+
+/// Reject executors without task slots, which could never be assigned a task
+fn check_task_slots(metadata: &ExecutorMetadata) -> std::result::Result<(), tonic::Status> {
+    if metadata.task_slots == 0 {
+        warn!("Executor {} registered without task slots", metadata.id);
+        Err(tonic::Status::invalid_argument(format!(
+            "Executor {} has no task slots",
+            metadata.id
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Create the DataFusion configuration for the settings of a session. Settings that are not
+/// supported are rejected rather than ignored.
+fn session_config(session: &SessionState) -> Result<ExecutionConfig> {
+    let mut config = extension_config()?;
+    for setting in &session.settings {
+        match setting.key.as_str() {
+            "batch.size" => {
+                let batch_size = setting.value.parse::<usize>().map_err(|e| {
+                    BallistaError::General(format!("Invalid batch.size {}: {}", setting.value, e))
+                })?;
+                config = config.with_batch_size(batch_size);
+            }
+            key => {
+                return Err(BallistaError::General(format!(
+                    "Unknown session setting {}",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Create a DataFusion context in which the tables of a session are registered
+fn session_context(session: &SessionState) -> Result<(ExecutionContext, ExecutionConfig)> {
+    let config = session_config(session)?;
+    let mut ctx = ExecutionContext::with_config(config.clone());
+    register_udfs(&mut ctx);
+    for table in &session.tables {
+        let plan: LogicalPlan = table
+            .plan
+            .as_ref()
+            .ok_or_else(|| BallistaError::General(format!("Missing plan of table {}", table.name)))?
+            .try_into()?;
+        let plan = ctx.optimize(&plan)?;
+        let execution_plan = ctx.create_physical_plan(&plan)?;
+        ctx.register_table(
+            &table.name,
+            Arc::new(DFTableAdapter::new(plan, execution_plan)),
+        );
+    }
+    Ok((ctx, config))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use tonic::Request;
+
+    use super::{state::StandaloneClient, SchedulerGrpc, SchedulerServer, DEFAULT_SPLIT_SIZE};
+    use crate::error::BallistaError;
+    use crate::serde::protobuf::{
+        job_status, CreateSessionParams, ExecuteSqlParams, ExecutorMetadata, GetJobStatusParams,
+        KeyValuePair, RegisterExecutorParams,
+    };
+
+    #[tokio::test]
+    async fn reject_executor_without_task_slots() -> Result<(), BallistaError> {
+        let scheduler = SchedulerServer::new(
+            StandaloneClient::try_new_temporary()?,
+            "default".to_owned(),
+            1,
+            DEFAULT_SPLIT_SIZE,
+        );
+        let register = |task_slots| RegisterExecutorParams {
+            metadata: Some(ExecutorMetadata {
+                id: "executor".to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+                task_slots,
+            }),
+        };
+        let status = scheduler
+            .register_executor(Request::new(register(0)))
+            .await
+            .unwrap_err();
+        assert_eq!(tonic::Code::InvalidArgument, status.code());
+        assert!(scheduler
+            .register_executor(Request::new(register(1)))
+            .await
+            .is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sql_in_session() -> Result<(), BallistaError> {
+        let scheduler = SchedulerServer::new(
+            StandaloneClient::try_new_temporary()?,
+            "default".to_owned(),
+            1,
+            DEFAULT_SPLIT_SIZE,
+        );
+        let status = scheduler
+            .create_session(Request::new(CreateSessionParams {
+                settings: vec![KeyValuePair {
+                    key: "batch.sise".to_owned(),
+                    value: "1024".to_owned(),
+                }],
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(tonic::Code::InvalidArgument, status.code());
+
+        let session_id = scheduler
+            .create_session(Request::new(CreateSessionParams {
+                settings: vec![KeyValuePair {
+                    key: "batch.size".to_owned(),
+                    value: "1024".to_owned(),
+                }],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .session_id;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("test.csv");
+        writeln!(std::fs::File::create(&path)?, "a,b\n1,2")?;
+        let create_table = format!(
+            "CREATE EXTERNAL TABLE t (a INT, b INT) STORED AS CSV WITH HEADER ROW LOCATION '{}'",
+            path.to_str().unwrap()
+        );
+
+        // tables are not visible before they are created in the session
+        let select = "SELECT a FROM t WHERE b > 1";
+        let execute_sql = |sql: &str| ExecuteSqlParams {
+            sql: sql.to_owned(),
+            session_id: session_id.clone(),
+        };
+        assert!(scheduler
+            .execute_sql(Request::new(execute_sql(select)))
+            .await
+            .is_err());
+
+        // creating the table completes without running any task
+        let job_id = scheduler
+            .execute_sql(Request::new(execute_sql(&create_table)))
+            .await
+            .unwrap()
+            .into_inner()
+            .job_id;
+        let status = scheduler
+            .get_job_status(Request::new(GetJobStatusParams { job_id }))
+            .await
+            .unwrap()
+            .into_inner()
+            .status
+            .unwrap();
+        assert!(matches!(
+            status.status,
+            Some(job_status::Status::Completed(_))
+        ));
+
+        assert!(scheduler
+            .execute_sql(Request::new(execute_sql(select)))
+            .await
+            .is_ok());
+
+        // other sessions do not see the table
+        assert!(scheduler
+            .execute_sql(Request::new(ExecuteSqlParams {
+                sql: select.to_owned(),
+                session_id: "unknown".to_owned(),
+            }))
+            .await
+            .is_err());
+        Ok(())
+    }
 }
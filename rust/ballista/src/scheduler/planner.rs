@@ -16,37 +16,30 @@
 //!
 //! This code is EXPERIMENTAL and still under development
 
-use std::pin::Pin;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::{collections::HashMap, future::Future};
 
-use super::execution_plans::{self, QueryStageExec, ShuffleReaderExec, UnresolvedShuffleExec};
-use crate::client::BallistaClient;
+use super::execution_plans::{
+    split_csv_file, split_parquet_file, CsvScanExec, FileSplit, ParquetScanExec, QueryStageExec,
+    ShuffleReaderExec, UnresolvedShuffleExec,
+};
 use crate::context::DFTableAdapter;
-use crate::error::{BallistaError, Result};
-use crate::executor::collect::CollectExec;
+use crate::error::Result;
+use crate::serde::extension::extension_config;
 use crate::serde::scheduler::ExecutorMeta;
 use crate::serde::scheduler::PartitionId;
-use crate::utils;
 
-use crate::utils::format_plan;
-use arrow::record_batch::RecordBatch;
-use datafusion::error::DataFusionError;
-use datafusion::execution::context::ExecutionContext;
-use datafusion::logical_plan::LogicalPlan;
+use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
 use datafusion::physical_plan::csv::CsvExec;
 use datafusion::physical_plan::expressions::Column;
-use datafusion::physical_plan::hash_aggregate::{AggregateMode, HashAggregateExec};
 use datafusion::physical_plan::hash_join::HashJoinExec;
 use datafusion::physical_plan::merge::MergeExec;
-use datafusion::physical_plan::{
-    AggregateExpr, ExecutionPlan, PhysicalExpr, SendableRecordBatchStream,
-};
-use log::{debug, info};
-use std::time::Instant;
+use datafusion::physical_plan::parquet::ParquetExec;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::{Distribution, ExecutionPlan, Partitioning, PhysicalExpr};
+use log::info;
 use uuid::Uuid;
 
-type SendableExecutionPlan = Pin<Box<dyn Future<Output = Result<Arc<dyn ExecutionPlan>>> + Send>>;
 type PartialQueryStageResult = (Arc<dyn ExecutionPlan>, Vec<Arc<QueryStageExec>>);
 
 #[derive(Debug, Clone)]
@@ -55,56 +48,48 @@ pub struct PartitionLocation {
     pub(crate) executor_meta: ExecutorMeta,
 }
 
+/// The DistributedPlanner breaks a physical plan into query stages. The stages are executed
+/// by the executors, which poll the scheduler for tasks.
 pub struct DistributedPlanner {
-    executors: Vec<ExecutorMeta>,
     next_stage_id: usize,
+    /// Target size in bytes of the ranges that Parquet and CSV files are split into, or zero
+    /// to scan whole files
+    split_size: u64,
+    /// Number of rows per batch of the scans of split files
+    batch_size: usize,
 }
 
 impl DistributedPlanner {
-    pub fn try_new(executors: Vec<ExecutorMeta>) -> Result<Self> {
-        if executors.is_empty() {
-            Err(BallistaError::General(
-                "DistributedPlanner requires at least one executor".to_owned(),
-            ))
-        } else {
-            Ok(Self {
-                executors,
-                next_stage_id: 0,
-            })
+    pub fn new() -> Self {
+        Self {
+            next_stage_id: 0,
+            split_size: 0,
+            batch_size: ExecutionConfig::new().batch_size,
         }
     }
-}
-
-impl DistributedPlanner {
-    /// Execute a distributed query against a cluster, leaving the final results on the
-    /// executors. The [ExecutionPlan] returned by this method is guaranteed to be a
-    /// [ShuffleReaderExec] that can be used to fetch the final results from the executors
-    /// in parallel.
-    pub async fn execute_distributed_query(
-        &mut self,
-        execution_plan: Arc<dyn ExecutionPlan>,
-    ) -> Result<Arc<dyn ExecutionPlan>> {
-        let job_uuid = Uuid::new_v4();
 
-        let now = Instant::now();
-        let execution_plans = self.plan_query_stages(&job_uuid, execution_plan)?;
-
-        info!(
-            "DistributedPlanner created {} execution plans in {} seconds:",
-            execution_plans.len(),
-            now.elapsed().as_secs()
-        );
-
-        for plan in &execution_plans {
-            info!("{}", format_plan(plan.as_ref(), 0)?);
+    /// Create a planner that splits the files larger than `split_size` bytes into several
+    /// scan partitions, which read batches of `batch_size` rows
+    pub fn with_split_size(split_size: u64, batch_size: usize) -> Self {
+        Self {
+            next_stage_id: 0,
+            split_size,
+            batch_size,
         }
+    }
+}
 
-        execute(execution_plans, self.executors.clone()).await
+impl Default for DistributedPlanner {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
+impl DistributedPlanner {
     /// Returns a vector of ExecutionPlans, where the root node is a [QueryStageExec].
     /// Plans that depend on the input of other plans will have leaf nodes of type [UnresolvedShuffleExec].
-    /// A [QueryStageExec] is created whenever the partitioning changes.
+    /// A [QueryStageExec] is created wherever the partitioning of an input does not satisfy the
+    /// distribution that its operator requires, and for the input of a [MergeExec].
     ///
     /// Returns an empty vector if the execution_plan doesn't need to be sliced into several stages.
     pub fn plan_query_stages(
@@ -118,6 +103,7 @@ impl DistributedPlanner {
             job_uuid,
             self.next_stage_id(),
             new_plan,
+            None,
         )?);
         Ok(stages)
     }
@@ -132,7 +118,10 @@ impl DistributedPlanner {
     ) -> Result<PartialQueryStageResult> {
         // recurse down and replace children
         if execution_plan.children().is_empty() {
-            return Ok((execution_plan, vec![]));
+            return Ok((
+                split_file_scan(execution_plan, self.split_size, self.batch_size)?,
+                vec![],
+            ));
         }
 
         let mut stages = vec![];
@@ -145,61 +134,135 @@ impl DistributedPlanner {
         }
 
         if let Some(adapter) = execution_plan.as_any().downcast_ref::<DFTableAdapter>() {
-            let ctx = ExecutionContext::new();
-            Ok((ctx.create_physical_plan(&adapter.logical_plan)?, stages))
-        } else if let Some(merge) = execution_plan.as_any().downcast_ref::<MergeExec>() {
-            let query_stage =
-                create_query_stage(job_uuid, self.next_stage_id(), merge.children()[0].clone())?;
-            let unresolved_shuffle = Arc::new(UnresolvedShuffleExec::new(
-                vec![query_stage.stage_id],
-                query_stage.schema(),
-                query_stage.output_partitioning().partition_count(),
-            ));
-            stages.push(query_stage);
-            Ok((merge.with_new_children(vec![unresolved_shuffle])?, stages))
-        } else if let Some(agg) = execution_plan.as_any().downcast_ref::<HashAggregateExec>() {
-            //TODO should insert query stages in more generic way based on partitioning metadata
-            // and not specifically for this operator
-            match agg.mode() {
-                AggregateMode::Final => {
-                    let mut new_children: Vec<Arc<dyn ExecutionPlan>> = vec![];
-                    for child in &children {
-                        let new_stage =
-                            create_query_stage(job_uuid, self.next_stage_id(), child.clone())?;
-                        new_children.push(Arc::new(UnresolvedShuffleExec::new(
-                            vec![new_stage.stage_id],
-                            new_stage.schema().clone(),
-                            new_stage.output_partitioning().partition_count(),
-                        )));
-                        stages.push(new_stage);
+            let ctx = ExecutionContext::with_config(extension_config()?);
+            return Ok((ctx.create_physical_plan(&adapter.logical_plan)?, stages));
+        }
+
+        if let Some(repartition) = execution_plan.as_any().downcast_ref::<RepartitionExec>() {
+            match repartition.output_partitioning() {
+                Partitioning::Hash(exprs, partition_count) => {
+                    // the repartitioning is done by the executors when they write the shuffle
+                    // output of the stage, unless the input is already partitioned that way
+                    let child = children[0].clone();
+                    if satisfies_hash_partitioning(
+                        &child.output_partitioning(),
+                        &exprs,
+                        partition_count,
+                    ) {
+                        return Ok((child, stages));
                     }
-                    Ok((agg.with_new_children(new_children)?, stages))
+                    let shuffle = self.create_hash_shuffle(
+                        job_uuid,
+                        child,
+                        exprs,
+                        partition_count,
+                        &mut stages,
+                    )?;
+                    return Ok((shuffle, stages));
                 }
-                AggregateMode::Partial => Ok((agg.with_new_children(children)?, stages)),
-            }
-        } else if let Some(join) = execution_plan.as_any().downcast_ref::<HashJoinExec>() {
-            Ok((join.with_new_children(children)?, stages))
-        } else {
-            // TODO check for compatible partitioning schema, not just count
-            if execution_plan.output_partitioning().partition_count()
-                != children[0].output_partitioning().partition_count()
-            {
-                let mut new_children: Vec<Arc<dyn ExecutionPlan>> = vec![];
-                for child in &children {
-                    let new_stage =
-                        create_query_stage(job_uuid, self.next_stage_id(), child.clone())?;
-                    new_children.push(Arc::new(UnresolvedShuffleExec::new(
-                        vec![new_stage.stage_id],
-                        new_stage.schema().clone(),
-                        new_stage.output_partitioning().partition_count(),
-                    )));
-                    stages.push(new_stage);
+                Partitioning::RoundRobinBatch(_) => {
+                    // round-robin repartitioning only spreads batches across the threads of one
+                    // process, and executing one of its partitions in a task would compute the
+                    // whole input. The partitions of a stage already run in parallel on the
+                    // executors, so the repartitioning is dropped rather than given a stage.
+                    return Ok((children[0].clone(), stages));
                 }
-                Ok((execution_plan.with_new_children(new_children)?, stages))
-            } else {
-                Ok((execution_plan.with_new_children(children)?, stages))
+                Partitioning::UnknownPartitioning(_) => {}
             }
         }
+
+        // insert a stage boundary wherever the partitioning of a child does not satisfy the
+        // distribution that the operator requires of it
+        let required = required_input_distribution(execution_plan.as_ref(), &children);
+        let mut new_children: Vec<Arc<dyn ExecutionPlan>> = vec![];
+        for (child, required) in children.into_iter().zip(required) {
+            let new_child = match required {
+                RequiredDistribution::Unspecified => child,
+                RequiredDistribution::Distributed => {
+                    if child.output_partitioning().partition_count() > 1 && !is_shuffle(&child) {
+                        self.create_shuffle(job_uuid, child, &mut stages)?
+                    } else {
+                        child
+                    }
+                }
+                RequiredDistribution::SinglePartition => {
+                    if child.output_partitioning().partition_count() > 1 {
+                        let shuffle = if is_shuffle(&child) {
+                            child
+                        } else {
+                            self.create_shuffle(job_uuid, child, &mut stages)?
+                        };
+                        Arc::new(MergeExec::new(shuffle))
+                    } else {
+                        child
+                    }
+                }
+                RequiredDistribution::HashPartitioned(exprs, partition_count) => {
+                    if satisfies_hash_partitioning(
+                        &child.output_partitioning(),
+                        &exprs,
+                        partition_count,
+                    ) {
+                        child
+                    } else {
+                        self.create_hash_shuffle(
+                            job_uuid,
+                            child,
+                            exprs,
+                            partition_count,
+                            &mut stages,
+                        )?
+                    }
+                }
+            };
+            new_children.push(new_child);
+        }
+        Ok((execution_plan.with_new_children(new_children)?, stages))
+    }
+
+    /// Create a query stage for the plan, and return the shuffle that reads each output
+    /// partition of the new stage as one partition
+    fn create_shuffle(
+        &mut self,
+        job_uuid: &Uuid,
+        plan: Arc<dyn ExecutionPlan>,
+        stages: &mut Vec<Arc<QueryStageExec>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let stage = create_query_stage(job_uuid, self.next_stage_id(), plan, None)?;
+        let shuffle = Arc::new(UnresolvedShuffleExec::new(
+            vec![stage.stage_id],
+            stage.schema(),
+            stage.output_partitioning().partition_count(),
+            None,
+        ));
+        stages.push(stage);
+        Ok(shuffle)
+    }
+
+    /// Create a query stage that hash-partitions the output of the plan on the given
+    /// expressions, and return the shuffle that reads the output of the new stage
+    fn create_hash_shuffle(
+        &mut self,
+        job_uuid: &Uuid,
+        plan: Arc<dyn ExecutionPlan>,
+        exprs: Vec<Arc<dyn PhysicalExpr>>,
+        partition_count: usize,
+        stages: &mut Vec<Arc<QueryStageExec>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let stage = create_query_stage(
+            job_uuid,
+            self.next_stage_id(),
+            plan,
+            Some(Partitioning::Hash(exprs.clone(), partition_count)),
+        )?;
+        let shuffle = Arc::new(UnresolvedShuffleExec::new(
+            vec![stage.stage_id],
+            stage.schema(),
+            partition_count,
+            Some(exprs),
+        ));
+        stages.push(stage);
+        Ok(shuffle)
     }
 
     /// Generate a new stage ID
@@ -209,36 +272,139 @@ impl DistributedPlanner {
     }
 }
 
-fn execute(
-    stages: Vec<Arc<QueryStageExec>>,
-    executors: Vec<ExecutorMeta>,
-) -> SendableExecutionPlan {
-    Box::pin(async move {
-        let mut partition_locations: HashMap<usize, Vec<PartitionLocation>> = HashMap::new();
-        let mut result_partition_locations = vec![];
-        for stage in &stages {
-            debug!("execute() {}", &format!("{:?}", stage)[0..60]);
-            let stage = remove_unresolved_shuffles(stage.as_ref(), &partition_locations)?;
-            let stage = stage.as_any().downcast_ref::<QueryStageExec>().unwrap();
-            result_partition_locations = execute_query_stage(
-                &stage.job_uuid.clone(),
-                stage.stage_id,
-                stage.children()[0].clone(),
-                executors.clone(),
-            )
-            .await?;
-            partition_locations.insert(stage.stage_id, result_partition_locations.clone());
+/// Replace a scan of Parquet or CSV files with a scan that reads ranges of the files as
+/// separate partitions, when a file is larger than the split size
+fn split_file_scan(
+    plan: Arc<dyn ExecutionPlan>,
+    split_size: u64,
+    batch_size: usize,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    if split_size == 0 {
+        return Ok(plan);
+    }
+    let parquet = if let Some(exec) = plan.as_any().downcast_ref::<ParquetScanExec>() {
+        Some((&exec.parquet, exec.predicate.clone()))
+    } else {
+        plan.as_any()
+            .downcast_ref::<ParquetExec>()
+            .map(|exec| (exec, None))
+    };
+    if let Some((parquet, predicate)) = parquet {
+        let mut splits = vec![];
+        for part in parquet.partitions() {
+            for filename in part.filenames() {
+                splits.append(&mut split_parquet_file(filename, split_size)?);
+            }
+        }
+        if has_split_files(&splits) {
+            return Ok(Arc::new(
+                ParquetScanExec::new(parquet.clone(), predicate).with_splits(splits),
+            ));
+        }
+    } else if let Some(csv) = plan.as_any().downcast_ref::<CsvExec>() {
+        let mut splits = vec![];
+        for filename in csv.filenames() {
+            splits.append(&mut split_csv_file(filename, split_size)?);
+        }
+        if has_split_files(&splits) {
+            return Ok(Arc::new(CsvScanExec::new(csv.clone(), splits, batch_size)));
         }
+    }
+    Ok(plan)
+}
 
-        let shuffle_reader: Arc<dyn ExecutionPlan> = Arc::new(ShuffleReaderExec::try_new(
-            result_partition_locations,
-            stages.last().unwrap().schema(),
-        )?);
-        Ok(shuffle_reader)
-    })
+/// Whether any file has more than one split
+fn has_split_files(splits: &[FileSplit]) -> bool {
+    splits
+        .windows(2)
+        .any(|pair| pair[0].filename == pair[1].filename)
 }
 
-fn remove_unresolved_shuffles(
+/// The distribution that an operator requires of one of its inputs
+enum RequiredDistribution {
+    /// The input can be executed in the same stage as the operator
+    Unspecified,
+    /// The partitions of the input are computed in parallel by a separate stage, because the
+    /// operator reads all of them from one partition
+    Distributed,
+    /// The input must have a single partition
+    SinglePartition,
+    /// The input must be hash-partitioned on the expressions into the number of partitions
+    HashPartitioned(Vec<Arc<dyn PhysicalExpr>>, usize),
+}
+
+/// Returns the distribution that the operator requires of each of its children
+fn required_input_distribution(
+    plan: &dyn ExecutionPlan,
+    children: &[Arc<dyn ExecutionPlan>],
+) -> Vec<RequiredDistribution> {
+    if plan.as_any().downcast_ref::<MergeExec>().is_some() {
+        vec![RequiredDistribution::Distributed]
+    } else if plan.as_any().downcast_ref::<HashJoinExec>().is_some() {
+        // HashJoinExec collects every partition of the build (left) side into one hash table
+        // in each of its partitions, and probes it with one partition of the right side, so
+        // the join is already partitioned like its probe side. Hash-partitioning the inputs
+        // would add stages without partitioning the join; an operator above the join that
+        // needs its output hash-partitioned shuffles it instead.
+        vec![
+            RequiredDistribution::Unspecified,
+            RequiredDistribution::Unspecified,
+        ]
+    } else {
+        // operators such as SortExec, GlobalLimitExec and final aggregates require a single
+        // partition
+        children
+            .iter()
+            .map(|_| match plan.required_child_distribution() {
+                Distribution::SinglePartition => RequiredDistribution::SinglePartition,
+                Distribution::UnspecifiedDistribution => RequiredDistribution::Unspecified,
+            })
+            .collect()
+    }
+}
+
+/// Returns true if the plan reads the output of other query stages
+fn is_shuffle(plan: &Arc<dyn ExecutionPlan>) -> bool {
+    plan.as_any()
+        .downcast_ref::<UnresolvedShuffleExec>()
+        .is_some()
+}
+
+/// Returns true if the partitioning is a hash partitioning on the same expressions into the
+/// same number of partitions
+fn satisfies_hash_partitioning(
+    partitioning: &Partitioning,
+    exprs: &[Arc<dyn PhysicalExpr>],
+    partition_count: usize,
+) -> bool {
+    match partitioning {
+        Partitioning::Hash(hash_exprs, n) => {
+            *n == partition_count
+                && hash_exprs.len() == exprs.len()
+                && hash_exprs
+                    .iter()
+                    .zip(exprs)
+                    .all(|(a, b)| same_column(a.as_ref(), b.as_ref()))
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if both expressions are references to the same column. Other expressions are
+/// never considered equal, which at worst adds an unneeded shuffle.
+fn same_column(a: &dyn PhysicalExpr, b: &dyn PhysicalExpr) -> bool {
+    match (
+        a.as_any().downcast_ref::<Column>(),
+        b.as_any().downcast_ref::<Column>(),
+    ) {
+        (Some(a), Some(b)) => a.name() == b.name(),
+        _ => false,
+    }
+}
+
+/// Replace any [UnresolvedShuffleExec] in the stage with a [ShuffleReaderExec] that reads the
+/// partitions produced by the query stages that it depends on.
+pub fn remove_unresolved_shuffles(
     stage: &dyn ExecutionPlan,
     partition_locations: &HashMap<usize, Vec<PartitionLocation>>,
 ) -> Result<Arc<dyn ExecutionPlan>> {
@@ -250,9 +416,19 @@ fn remove_unresolved_shuffles(
                 .iter()
                 .flat_map(|id| partition_locations[id].clone())
                 .collect();
+            let partitions = if unresolved_shuffle.hash_partitioned() {
+                // every output partition is gathered from all of the shuffle partitions
+                vec![relevant_locations; unresolved_shuffle.partition_count]
+            } else {
+                relevant_locations
+                    .into_iter()
+                    .map(|location| vec![location])
+                    .collect()
+            };
             new_children.push(Arc::new(ShuffleReaderExec::try_new(
-                relevant_locations,
+                partitions,
                 unresolved_shuffle.schema().clone(),
+                unresolved_shuffle.hash_exprs.clone(),
             )?))
         } else {
             new_children.push(remove_unresolved_shuffles(
@@ -268,110 +444,45 @@ fn create_query_stage(
     job_uuid: &Uuid,
     stage_id: usize,
     plan: Arc<dyn ExecutionPlan>,
+    shuffle_output_partitioning: Option<Partitioning>,
 ) -> Result<Arc<QueryStageExec>> {
     Ok(Arc::new(QueryStageExec::try_new(
-        *job_uuid, stage_id, plan,
-    )?))
-}
-
-/// Execute a query stage by sending each partition to an executor
-async fn execute_query_stage(
-    job_uuid: &Uuid,
-    stage_id: usize,
-    plan: Arc<dyn ExecutionPlan>,
-    executors: Vec<ExecutorMeta>,
-) -> Result<Vec<PartitionLocation>> {
-    info!(
-        "execute_query_stage() stage_id={}\n{}",
+        *job_uuid,
         stage_id,
-        format_plan(plan.as_ref(), 0)?
-    );
-
-    let _job_uuid = *job_uuid;
-    let partition_count = plan.output_partitioning().partition_count();
-    let mut meta = Vec::with_capacity(partition_count);
-
-    let partition_chunks: Vec<Vec<usize>> = (0..partition_count)
-        .collect::<Vec<usize>>()
-        .chunks(partition_count / executors.len())
-        .map(|r| r.to_vec())
-        .collect();
-
-    info!(
-        "Executing query stage with {} chunks of partition ranges",
-        partition_chunks.len()
-    );
-
-    // build metadata for partition locations
-    for i in 0..partition_chunks.len() {
-        let executor_meta = &executors[i % executors.len()];
-        for part in &partition_chunks[i] {
-            meta.push(PartitionLocation {
-                partition_id: PartitionId::new(_job_uuid, stage_id, *part),
-                executor_meta: executor_meta.clone(),
-            });
-        }
-    }
-
-    let mut executions = Vec::with_capacity(partition_count);
-    for i in 0..partition_chunks.len() {
-        let _plan = plan.clone();
-        let executor_meta = executors[i % executors.len()].clone();
-        let partition_ids = partition_chunks[i].to_vec();
-        executions.push(tokio::spawn(async move {
-            let mut client =
-                BallistaClient::try_new(&executor_meta.host, executor_meta.port).await?;
-            client
-                .execute_partition(_job_uuid, stage_id, partition_ids, _plan)
-                .await
-        }));
-    }
-
-    // wait for all partitions to complete
-    let results = futures::future::join_all(executions).await;
-
-    // check for errors
-    for result in results {
-        match result {
-            Ok(partition_result) => {
-                let final_result = partition_result?;
-                debug!("Query stage partition result: {:?}", final_result);
-            }
-            Err(e) => {
-                return Err(BallistaError::General(format!(
-                    "Query stage {} failed: {:?}",
-                    stage_id, e
-                )))
-            }
-        }
-    }
-
-    debug!(
-        "execute_query_stage() stage_id={} produced {:?}",
-        stage_id, meta
-    );
-
-    Ok(meta)
+        plan,
+        shuffle_output_partitioning,
+    )?))
 }
 
 #[cfg(test)]
 mod test {
+    use super::{remove_unresolved_shuffles, satisfies_hash_partitioning, PartitionLocation};
     use crate::scheduler::execution_plans::QueryStageExec;
     use crate::scheduler::planner::DistributedPlanner;
     use crate::serde::protobuf;
-    use crate::serde::scheduler::ExecutorMeta;
+    use crate::serde::scheduler::{ExecutorMeta, PartitionId};
     use crate::test_utils;
     use crate::test_utils::{datafusion_test_context, TPCH_TABLES};
-    use crate::utils::format_plan;
-    use crate::{error::BallistaError, scheduler::execution_plans::UnresolvedShuffleExec};
+    use crate::utils::{format_expr, format_plan};
+    use crate::{
+        error::BallistaError,
+        scheduler::execution_plans::{CsvScanExec, UnresolvedShuffleExec},
+    };
     use arrow::datatypes::DataType;
-    use datafusion::physical_plan::csv::CsvReadOptions;
+    use datafusion::error::DataFusionError;
+    use datafusion::physical_plan::common::collect;
+    use datafusion::physical_plan::csv::{CsvExec, CsvReadOptions};
+    use datafusion::physical_plan::expressions::Column;
     use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
+    use datafusion::physical_plan::hash_join::HashJoinExec;
+    use datafusion::physical_plan::hash_utils::JoinType;
     use datafusion::physical_plan::projection::ProjectionExec;
+    use datafusion::physical_plan::repartition::RepartitionExec;
     use datafusion::physical_plan::sort::SortExec;
-    use datafusion::physical_plan::ExecutionPlan;
+    use datafusion::physical_plan::{Distribution, ExecutionPlan, Partitioning};
     use datafusion::prelude::*;
     use datafusion::{execution::context::ExecutionContext, physical_plan::merge::MergeExec};
+    use std::collections::HashMap;
     use std::convert::TryInto;
     use std::sync::Arc;
     use uuid::Uuid;
@@ -398,11 +509,7 @@ mod test {
         let plan = ctx.optimize(&plan)?;
         let plan = ctx.create_physical_plan(&plan)?;
 
-        let mut planner = DistributedPlanner::try_new(vec![ExecutorMeta {
-            id: "".to_string(),
-            host: "".to_string(),
-            port: 0,
-        }])?;
+        let mut planner = DistributedPlanner::new();
         let job_uuid = Uuid::new_v4();
         let stages = planner.plan_query_stages(&job_uuid, plan)?;
         for stage in &stages {
@@ -415,17 +522,15 @@ mod test {
           CsvExec: testdata/lineitem; partitions=2
 
         QueryStageExec: job=f011432e-e424-4016-915d-e3d8b84f6dbd, stage=2
-         MergeExec
-          UnresolvedShuffleExec: stages=[1]
-
-        QueryStageExec: job=f011432e-e424-4016-915d-e3d8b84f6dbd, stage=3
          SortExec { input: ProjectionExec { expr: [(Column { name: "l_returnflag" }, "l_returnflag"), (Column { name: "SUM(l_ext
           ProjectionExec { expr: [(Column { name: "l_returnflag" }, "l_returnflag"), (Column { name: "SUM(l_extendedprice Multip
            HashAggregateExec: groupBy=["l_returnflag"], aggrExpr=["SUM(l_extendedprice Multiply Int64(1)) [\"l_extendedprice * CAST(1 AS Float64)\"]"]
-            UnresolvedShuffleExec: stages=[2]
+            MergeExec
+             UnresolvedShuffleExec: stages=[1]
         */
+        assert_eq!(stages.len(), 2);
 
-        let sort = stages[2].children()[0].clone();
+        let sort = stages[1].children()[0].clone();
         let sort = downcast_exec!(sort, SortExec);
 
         let projection = sort.children()[0].clone();
@@ -435,11 +540,7 @@ mod test {
         let final_hash = projection.children()[0].clone();
         let final_hash = downcast_exec!(final_hash, HashAggregateExec);
 
-        let unresolved_shuffle = final_hash.children()[0].clone();
-        let unresolved_shuffle = downcast_exec!(unresolved_shuffle, UnresolvedShuffleExec);
-        assert_eq!(unresolved_shuffle.query_stage_ids, vec![2]);
-
-        let merge_exec = stages[1].children()[0].clone();
+        let merge_exec = final_hash.children()[0].clone();
         let merge_exec = downcast_exec!(merge_exec, MergeExec);
 
         let unresolved_shuffle = merge_exec.children()[0].clone();
@@ -460,6 +561,292 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn hash_partitioned_join() -> Result<(), BallistaError> {
+        let mut ctx = datafusion_test_context("testdata")?;
+        let customer = ctx.table("customer")?.to_logical_plan();
+        let customer = ctx.create_physical_plan(&ctx.optimize(&customer)?)?;
+        let orders = ctx.table("orders")?.to_logical_plan();
+        let orders = ctx.create_physical_plan(&ctx.optimize(&orders)?)?;
+        let partition_count = 3;
+        let orders = Arc::new(RepartitionExec::try_new(
+            orders,
+            Partitioning::Hash(vec![Arc::new(Column::new("o_custkey"))], partition_count),
+        )?);
+        let plan = Arc::new(HashJoinExec::try_new(
+            customer,
+            orders,
+            &[("c_custkey".to_string(), "o_custkey".to_string())],
+            &JoinType::Inner,
+        )?);
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid, plan)?;
+        for stage in &stages {
+            println!("{}", format_plan(stage.as_ref(), 0)?);
+        }
+
+        /* Expected result:
+        QueryStageExec: job=..., stage=1, hash=["o_custkey"], partitions=3
+         CsvExec: testdata/orders; partitions=N
+
+        QueryStageExec: job=..., stage=2
+         HashJoinExec: joinType=Inner, on=[("c_custkey", "o_custkey")]
+          CsvExec: testdata/customer; partitions=N
+          UnresolvedShuffleExec: stages=[1], hash_partitioned
+        */
+        assert_eq!(stages.len(), 2);
+        match &stages[0].shuffle_output_partitioning {
+            Some(Partitioning::Hash(exprs, n)) => {
+                assert_eq!(*n, partition_count);
+                assert_eq!(exprs.len(), 1);
+                assert_eq!(format_expr(exprs[0].as_ref()), "o_custkey");
+            }
+            other => panic!("unexpected shuffle output partitioning {:?}", other),
+        }
+        assert!(stages[1].shuffle_output_partitioning.is_none());
+
+        // every partition of the join builds its hash table from the whole customer table
+        // in the same stage, and probes it with one hash partition of the orders
+        let join = stages[1].children()[0].clone();
+        let join = downcast_exec!(join, HashJoinExec);
+        let children = join.children();
+        downcast_exec!(children[0], CsvExec);
+        let unresolved_shuffle = downcast_exec!(children[1], UnresolvedShuffleExec);
+        assert_eq!(unresolved_shuffle.query_stage_ids, vec![1]);
+        assert_eq!(unresolved_shuffle.partition_count, partition_count);
+        assert!(unresolved_shuffle.hash_partitioned());
+        assert_eq!(
+            stages[1].output_partitioning().partition_count(),
+            partition_count
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reuse_hash_partitioning_of_shuffle() -> Result<(), BallistaError> {
+        let mut ctx = datafusion_test_context("testdata")?;
+        let customer = ctx.table("customer")?.to_logical_plan();
+        let customer = ctx.create_physical_plan(&ctx.optimize(&customer)?)?;
+        let orders = ctx.table("orders")?.to_logical_plan();
+        let orders = ctx.create_physical_plan(&ctx.optimize(&orders)?)?;
+        let hash_partitioning = || Partitioning::Hash(vec![Arc::new(Column::new("o_custkey"))], 3);
+        let orders = Arc::new(RepartitionExec::try_new(orders, hash_partitioning())?);
+        let join = Arc::new(HashJoinExec::try_new(
+            customer,
+            orders,
+            &[("c_custkey".to_string(), "o_custkey".to_string())],
+            &JoinType::Inner,
+        )?);
+        // the join is already partitioned like the orders shuffle that it probes
+        let plan = Arc::new(RepartitionExec::try_new(join, hash_partitioning())?);
+
+        let mut planner = DistributedPlanner::new();
+        let stages = planner.plan_query_stages(&Uuid::new_v4(), plan)?;
+        assert_eq!(stages.len(), 2);
+        let join = stages[1].children()[0].clone();
+        let join = downcast_exec!(join, HashJoinExec);
+        match join.output_partitioning() {
+            Partitioning::Hash(exprs, n) => {
+                assert_eq!(n, 3);
+                assert_eq!(format_expr(exprs[0].as_ref()), "o_custkey");
+            }
+            other => panic!("unexpected partitioning {:?}", other),
+        }
+
+        // the shuffle still reports its partitioning once it is resolved and deserialized
+        let partition_id = PartitionId::new(Uuid::new_v4(), 1, 0);
+        let executor_meta = ExecutorMeta {
+            id: "executor".to_owned(),
+            host: "localhost".to_owned(),
+            port: 50051,
+            task_slots: 1,
+        };
+        let mut partition_locations = HashMap::new();
+        partition_locations.insert(
+            1,
+            vec![PartitionLocation {
+                partition_id,
+                executor_meta,
+            }],
+        );
+        let stage = remove_unresolved_shuffles(stages[1].as_ref(), &partition_locations)?;
+        let stage = roundtrip_operator(stage)?;
+        assert!(satisfies_hash_partitioning(
+            &stage.output_partitioning(),
+            &[Arc::new(Column::new("o_custkey"))],
+            3
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn drop_round_robin_repartition() -> Result<(), BallistaError> {
+        let mut ctx = datafusion_test_context("testdata")?;
+        let orders = ctx.table("orders")?.to_logical_plan();
+        let orders = ctx.create_physical_plan(&ctx.optimize(&orders)?)?;
+        let plan = Arc::new(RepartitionExec::try_new(
+            orders,
+            Partitioning::RoundRobinBatch(4),
+        )?);
+
+        let mut planner = DistributedPlanner::new();
+        let stages = planner.plan_query_stages(&Uuid::new_v4(), plan)?;
+        assert_eq!(stages.len(), 1);
+        downcast_exec!(stages[0].children()[0], CsvExec);
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_tpch_queries() -> Result<(), BallistaError> {
+        let mut ctx = datafusion_test_context("testdata")?;
+
+        let mut paths = std::fs::read_dir("../benchmarks/tpch/queries")?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+
+        // DataFusion cannot plan these queries yet, because of the SQL features listed here. The
+        // benchmark script only runs the other queries.
+        let unsupported = [
+            ("q2.sql", "scalar subquery"),
+            ("q4.sql", "EXISTS subquery"),
+            ("q7.sql", "EXTRACT expression"),
+            ("q8.sql", "EXTRACT expression"),
+            ("q9.sql", "EXTRACT expression"),
+            ("q11.sql", "scalar subquery"),
+            ("q13.sql", "non-equality join condition"),
+            ("q14.sql", "expression of aggregates"),
+            ("q16.sql", "NOT IN subquery"),
+            ("q17.sql", "scalar subquery"),
+            ("q18.sql", "IN subquery"),
+            ("q19.sql", "join condition in a disjunction"),
+            ("q20.sql", "IN subquery"),
+            ("q21.sql", "EXISTS subquery"),
+            ("q22.sql", "scalar subquery"),
+        ];
+        let supported = paths.len() - unsupported.len();
+        let mut planned = 0;
+        for path in paths {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let sql = std::fs::read_to_string(&path)?;
+            let plan = ctx.sql(&sql).and_then(|df| {
+                let plan = ctx.optimize(&df.to_logical_plan())?;
+                ctx.create_physical_plan(&plan)
+            });
+            let plan = match plan {
+                Ok(plan) => plan,
+                Err(e) => match unsupported.iter().find(|(query, _)| *query == name) {
+                    // the query must fail in the planner rather than, for example, on a file
+                    // that cannot be read
+                    Some((_, feature)) => {
+                        assert!(
+                            matches!(
+                                e,
+                                DataFusionError::NotImplemented(_) | DataFusionError::Plan(_)
+                            ),
+                            "{} should fail to plan its {}, but failed with: {}",
+                            name,
+                            feature,
+                            e
+                        );
+                        continue;
+                    }
+                    None => panic!("failed to plan {}: {}", name, e),
+                },
+            };
+
+            let mut planner = DistributedPlanner::new();
+            let stages = planner.plan_query_stages(&Uuid::new_v4(), plan)?;
+            let mut planned_stage_ids = vec![];
+            for stage in &stages {
+                check_stage_plan(stage.children()[0].as_ref(), &planned_stage_ids);
+                planned_stage_ids.push(stage.stage_id);
+            }
+            // only the final stage has no consumers
+            assert!(stages[stages.len() - 1]
+                .shuffle_output_partitioning
+                .is_none());
+            planned += 1;
+        }
+        assert!(planned >= supported);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn split_csv_scan() -> Result<(), BallistaError> {
+        let mut ctx = datafusion_test_context("testdata")?;
+        let lineitem = ctx.table("lineitem")?.to_logical_plan();
+        let plan = ctx.create_physical_plan(&ctx.optimize(&lineitem)?)?;
+        let num_rows = count_rows(plan.as_ref()).await?;
+
+        let mut planner = DistributedPlanner::with_split_size(256, 1024);
+        let stages = planner.plan_query_stages(&Uuid::new_v4(), plan)?;
+        assert_eq!(stages.len(), 1);
+        let scan = roundtrip_operator(stages[0].children()[0].clone())?;
+        let csv_scan = downcast_exec!(scan, CsvScanExec);
+        assert!(csv_scan.splits.len() > 2);
+        assert_eq!(csv_scan.batch_size(), 1024);
+        for split in &csv_scan.splits {
+            // every split ends with a complete line
+            let data = std::fs::read(&split.filename)?;
+            assert_eq!(data[split.end as usize - 1], b'\n');
+        }
+        assert_eq!(num_rows, count_rows(scan.as_ref()).await?);
+
+        Ok(())
+    }
+
+    async fn count_rows(plan: &dyn ExecutionPlan) -> Result<usize, BallistaError> {
+        let mut num_rows = 0;
+        for partition in 0..plan.output_partitioning().partition_count() {
+            let batches = collect(plan.execute(partition).await?).await?;
+            num_rows += batches.iter().map(|batch| batch.num_rows()).sum::<usize>();
+        }
+        Ok(num_rows)
+    }
+
+    /// Check that every operator in the stage gets its input with the distribution that it
+    /// requires, and that the stage only reads from stages that are planned before it
+    fn check_stage_plan(plan: &dyn ExecutionPlan, planned_stage_ids: &[usize]) {
+        assert!(plan.as_any().downcast_ref::<QueryStageExec>().is_none());
+        if let Some(unresolved_shuffle) = plan.as_any().downcast_ref::<UnresolvedShuffleExec>() {
+            for id in &unresolved_shuffle.query_stage_ids {
+                assert!(planned_stage_ids.contains(id));
+            }
+        } else if let Some(repartition) = plan.as_any().downcast_ref::<RepartitionExec>() {
+            assert!(!matches!(
+                repartition.output_partitioning(),
+                Partitioning::Hash(_, _)
+            ));
+        } else if let Some(merge) = plan.as_any().downcast_ref::<MergeExec>() {
+            let input = merge.children()[0].clone();
+            assert!(
+                input.output_partitioning().partition_count() == 1
+                    || input.as_any().is::<UnresolvedShuffleExec>()
+            );
+        } else if let Some(join) = plan.as_any().downcast_ref::<HashJoinExec>() {
+            // the build side of the join is never hash-shuffled
+            let left = join.children()[0].clone();
+            if let Some(shuffle) = left.as_any().downcast_ref::<UnresolvedShuffleExec>() {
+                assert!(!shuffle.hash_partitioned());
+            }
+        } else if let Distribution::SinglePartition = plan.required_child_distribution() {
+            for child in plan.children() {
+                assert_eq!(child.output_partitioning().partition_count(), 1);
+            }
+        }
+
+        for child in plan.children() {
+            check_stage_plan(child.as_ref(), planned_stage_ids);
+        }
+    }
+
     fn roundtrip_operator(
         plan: Arc<dyn ExecutionPlan>,
     ) -> Result<Arc<dyn ExecutionPlan>, BallistaError> {
@@ -19,7 +19,7 @@ use std::time::Duration;
 use crate::error::{ballista_error, Result};
 use crate::scheduler::state::ConfigBackendClient;
 
-use etcd_client::{GetOptions, PutOptions};
+use etcd_client::{DeleteOptions, GetOptions, PutOptions};
 use log::warn;
 
 /// A [`ConfigBackendClient`] implementation that uses etcd to save cluster configuration.
@@ -87,4 +87,15 @@ impl ConfigBackendClient for EtcdClient {
             })
             .map(|_| ())
     }
+
+    async fn delete_from_prefix(&mut self, prefix: &str) -> Result<()> {
+        self.etcd
+            .delete(prefix, Some(DeleteOptions::new().with_prefix()))
+            .await
+            .map_err(|e| {
+                warn!("etcd delete failed: {}", e);
+                ballista_error("etcd delete failed")
+            })
+            .map(|_| ())
+    }
 }
@@ -12,15 +12,26 @@
 
 use std::{
     any::type_name,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryInto,
     io::{Cursor, Read},
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use log::debug;
+use datafusion::physical_plan::ExecutionPlan;
+use log::{debug, info, warn};
 use prost::Message;
+use tokio::sync::Mutex;
 
 use crate::error::Result;
-use crate::serde::protobuf::{ExecutorMetadata, JobStatus};
+use crate::scheduler::execution_plans::UnresolvedShuffleExec;
+use crate::scheduler::planner::{remove_unresolved_shuffles, PartitionLocation};
+use crate::serde::protobuf::{
+    self, job_status, task_status, CancelledJob, CompletedJob, CompletedTask, ExecutorMetadata,
+    FailedJob, FailedTask, JobInfo, JobState, JobStatus, LogicalPlanNode, PhysicalPlanNode,
+    QueuedJob, RunningTask, SessionState, SessionTable, StageProgress, TaskStatus,
+};
 use crate::{error::ballista_error, prelude::BallistaError, serde::scheduler::ExecutorMeta};
 
 use super::SchedulerServer;
@@ -29,7 +40,7 @@ mod etcd;
 mod standalone;
 
 pub use etcd::EtcdClient;
-pub use standalone::StandaloneClient;
+pub use standalone::{StandaloneClient, DEFAULT_SWEEP_INTERVAL};
 
 const LEASE_TIME: Duration = Duration::from_secs(60);
 
@@ -51,16 +62,37 @@ pub trait ConfigBackendClient: Clone {
         value: Vec<u8>,
         lease_time: Option<Duration>,
     ) -> Result<()>;
+
+    /// Remove all keys that start with the prefix.
+    async fn delete_from_prefix(&mut self, prefix: &str) -> Result<()>;
 }
 
 #[derive(Clone)]
 pub(super) struct SchedulerState<Config: ConfigBackendClient> {
     config_client: Config,
+    /// Serializes task assignment so that a pending task is never handed to two executors
+    assign_lock: Arc<Mutex<()>>,
+    /// Serializes job status changes so that a cancelled job is never resumed
+    job_status_lock: Arc<Mutex<()>>,
+    /// Serializes session changes so that concurrently registered tables are not lost
+    session_lock: Arc<Mutex<()>>,
+    /// Executors that were alive when lost tasks were last recovered, and when that was. None
+    /// until the first recovery.
+    live_executors: Arc<Mutex<Option<(HashSet<String>, Instant)>>>,
+    /// Number of times a task is attempted before its job is marked as failed
+    max_task_attempts: usize,
 }
 
 impl<Config: ConfigBackendClient> SchedulerState<Config> {
-    pub fn new(config_client: Config) -> Self {
-        Self { config_client }
+    pub fn new(config_client: Config, max_task_attempts: usize) -> Self {
+        Self {
+            config_client,
+            assign_lock: Arc::new(Mutex::new(())),
+            job_status_lock: Arc::new(Mutex::new(())),
+            session_lock: Arc::new(Mutex::new(())),
+            live_executors: Arc::new(Mutex::new(None)),
+            max_task_attempts,
+        }
     }
 
     pub async fn get_executors_metadata(&self, namespace: &str) -> Result<Vec<ExecutorMeta>> {
@@ -88,23 +120,786 @@ impl<Config: ConfigBackendClient> SchedulerState<Config> {
             .await
     }
 
+    /// Record a new queued job together with the text of its plan
+    pub async fn create_job(&self, namespace: &str, job_id: &str, plan: String) -> Result<()> {
+        let info = JobInfo {
+            job_id: job_id.to_owned(),
+            status: Some(JobStatus {
+                status: Some(job_status::Status::Queued(QueuedJob {})),
+                task_attempts: 0,
+            }),
+            plan,
+            start_time: now_millis(),
+            ..Default::default()
+        };
+        self.save_job_info(namespace, &info).await
+    }
+
+    /// Save the information of a job. The tasks and stage plans of a job are only kept until
+    /// it has finished, and only the jobs that have not finished are scanned for tasks to
+    /// assign, so the scheduler does not slow down as jobs accumulate.
+    pub async fn save_job_info(&self, namespace: &str, info: &JobInfo) -> Result<()> {
+        let key = get_job_key(namespace, &info.job_id);
+        let value = encode_protobuf(info)?;
+        let mut client = self.config_client.clone();
+        client.put(key, value, None).await?;
+        let active_key = get_active_job_key(namespace, &info.job_id);
+        if info.status.as_ref().map_or(false, job_finished) {
+            client.delete_from_prefix(&active_key).await?;
+            client
+                .delete_from_prefix(&get_task_prefix_for_job(namespace, &info.job_id))
+                .await?;
+            client
+                .delete_from_prefix(&get_stage_plan_prefix(namespace, &info.job_id))
+                .await
+        } else {
+            client
+                .put(active_key, info.job_id.as_bytes().to_vec(), None)
+                .await
+        }
+    }
+
+    /// Ids of the jobs that have not finished
+    async fn get_active_jobs(&self, namespace: &str) -> Result<Vec<String>> {
+        self.config_client
+            .clone()
+            .get_from_prefix(&get_active_jobs_prefix(namespace))
+            .await?
+            .into_iter()
+            .map(|job_id| {
+                String::from_utf8(job_id)
+                    .map_err(|e| BallistaError::Internal(format!("Invalid job id: {}", e)))
+            })
+            .collect()
+    }
+
+    /// Returns None if the job does not exist
+    pub async fn get_job_info(&self, namespace: &str, job_id: &str) -> Result<Option<JobInfo>> {
+        let key = get_job_key(namespace, job_id);
+        let value = self.config_client.clone().get(&key).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(decode_protobuf(&value)?))
+    }
+
+    /// List the jobs of a namespace that are in one of the given states, or all jobs if no
+    /// state is given. The most recently started jobs come first.
+    pub async fn list_jobs(&self, namespace: &str, states: &[JobState]) -> Result<Vec<JobInfo>> {
+        let entries = self
+            .config_client
+            .clone()
+            .get_from_prefix(&get_jobs_prefix(namespace))
+            .await?;
+        let mut jobs = vec![];
+        for entry in entries {
+            let info: JobInfo = decode_protobuf(&entry)?;
+            if states.is_empty() || states.contains(&job_state(&info)) {
+                jobs.push(info);
+            }
+        }
+        jobs.sort_by(|a, b| b.start_time.cmp(&a.start_time));
+        Ok(jobs)
+    }
+
+    /// Update the status of a job. The end time of the job is recorded once it has finished.
     pub async fn save_job_metadata(
         &self,
         namespace: &str,
         job_id: &str,
         status: &JobStatus,
+    ) -> Result<()> {
+        let _guard = self.job_status_lock.lock().await;
+        self.write_job_metadata(namespace, job_id, status).await
+    }
+
+    /// Update the status of a job while holding the job status lock
+    async fn write_job_metadata(
+        &self,
+        namespace: &str,
+        job_id: &str,
+        status: &JobStatus,
     ) -> Result<()> {
         debug!("Saving job metadata: {:?}", status);
-        let key = get_job_key(namespace, job_id);
+        let mut info = self
+            .get_job_info(namespace, job_id)
+            .await?
+            .unwrap_or_else(|| JobInfo {
+                job_id: job_id.to_owned(),
+                start_time: now_millis(),
+                ..Default::default()
+            });
+        set_job_status(&mut info, status.clone());
+        self.save_job_info(namespace, &info).await
+    }
+
+    /// Returns None if the job does not exist
+    pub async fn get_job_metadata(
+        &self,
+        namespace: &str,
+        job_id: &str,
+    ) -> Result<Option<JobStatus>> {
+        Ok(self
+            .get_job_info(namespace, job_id)
+            .await?
+            .map(|info| info.status.unwrap_or_default()))
+    }
+
+    /// Save the status of a job unless the job has been cancelled. Returns false if the job
+    /// was cancelled.
+    pub async fn save_job_metadata_unless_cancelled(
+        &self,
+        namespace: &str,
+        job_id: &str,
+        status: &JobStatus,
+    ) -> Result<bool> {
+        let _guard = self.job_status_lock.lock().await;
+        let current = self.get_job_metadata(namespace, job_id).await?;
+        if let Some(job_status::Status::Cancelled(_)) = current.and_then(|c| c.status) {
+            return Ok(false);
+        }
+        self.write_job_metadata(namespace, job_id, status).await?;
+        Ok(true)
+    }
+
+    /// Mark a queued or running job as cancelled so that no more of its tasks are assigned.
+    /// Returns false if the job had already finished.
+    pub async fn cancel_job(&self, namespace: &str, job_id: &str) -> Result<bool> {
+        let _guard = self.job_status_lock.lock().await;
+        let job_status = match self.get_job_metadata(namespace, job_id).await? {
+            Some(job_status) => job_status,
+            None => return Ok(false),
+        };
+        match job_status.status {
+            Some(job_status::Status::Queued(_)) | Some(job_status::Status::Running(_)) => {
+                info!("Cancelling job {}", job_id);
+                let status = JobStatus {
+                    status: Some(job_status::Status::Cancelled(CancelledJob {})),
+                    task_attempts: job_status.task_attempts,
+                };
+                self.write_job_metadata(namespace, job_id, &status).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub async fn save_session(&self, namespace: &str, session: &SessionState) -> Result<()> {
+        let key = get_session_key(namespace, &session.session_id);
+        let value = encode_protobuf(session)?;
+        self.config_client.clone().put(key, value, None).await
+    }
+
+    /// Returns None if the session does not exist
+    pub async fn get_session(
+        &self,
+        namespace: &str,
+        session_id: &str,
+    ) -> Result<Option<SessionState>> {
+        let key = get_session_key(namespace, session_id);
+        let value = self.config_client.clone().get(&key).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(decode_protobuf(&value)?))
+    }
+
+    /// Register a table in a session, replacing any table with the same name
+    pub async fn register_session_table(
+        &self,
+        namespace: &str,
+        session_id: &str,
+        name: &str,
+        plan: LogicalPlanNode,
+    ) -> Result<()> {
+        let _guard = self.session_lock.lock().await;
+        let mut session = self
+            .get_session(namespace, session_id)
+            .await?
+            .ok_or_else(|| {
+                BallistaError::General(format!("Session {} does not exist", session_id))
+            })?;
+        session.tables.retain(|table| table.name != name);
+        session.tables.push(SessionTable {
+            name: name.to_owned(),
+            plan: Some(plan),
+        });
+        self.save_session(namespace, &session).await
+    }
+
+    pub async fn save_stage_plan(
+        &self,
+        namespace: &str,
+        job_id: &str,
+        stage_id: usize,
+        plan: Arc<dyn ExecutionPlan>,
+    ) -> Result<()> {
+        let key = get_stage_plan_key(namespace, job_id, stage_id);
+        let value = {
+            let proto: PhysicalPlanNode = plan.try_into()?;
+            encode_protobuf(&proto)?
+        };
+        self.config_client.clone().put(key, value, None).await
+    }
+
+    pub async fn get_stage_plan(
+        &self,
+        namespace: &str,
+        job_id: &str,
+        stage_id: usize,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let key = get_stage_plan_key(namespace, job_id, stage_id);
+        let value = &self.config_client.clone().get(&key).await?;
+        let value: PhysicalPlanNode = decode_protobuf(value)?;
+        Ok((&value).try_into()?)
+    }
+
+    pub async fn save_task_status(&self, namespace: &str, status: &TaskStatus) -> Result<()> {
+        let partition_id = status.partition_id.as_ref().ok_or_else(|| {
+            BallistaError::Internal("Task status is missing the partition id".to_owned())
+        })?;
+        let key = get_task_status_key(
+            namespace,
+            &partition_id.job_uuid,
+            partition_id.stage_id as usize,
+            partition_id.partition_id as usize,
+        );
         let value = encode_protobuf(status)?;
         self.config_client.clone().put(key, value, None).await
     }
 
-    pub async fn get_job_metadata(&self, namespace: &str, job_id: &str) -> Result<JobStatus> {
-        let key = get_job_key(namespace, job_id);
+    async fn get_task_status(
+        &self,
+        namespace: &str,
+        partition_id: &protobuf::PartitionId,
+    ) -> Result<TaskStatus> {
+        let key = get_task_status_key(
+            namespace,
+            &partition_id.job_uuid,
+            partition_id.stage_id as usize,
+            partition_id.partition_id as usize,
+        );
         let value = &self.config_client.clone().get(&key).await?;
-        let value: JobStatus = decode_protobuf(value)?;
-        Ok(value)
+        decode_protobuf(value)
+    }
+
+    /// Apply a task status reported by an executor. Reports for tasks that are no longer
+    /// assigned to that executor are ignored, and failed tasks are put back into the pending
+    /// state until they run out of attempts.
+    pub async fn update_task_status(
+        &self,
+        namespace: &str,
+        executor_id: &str,
+        status: TaskStatus,
+    ) -> Result<()> {
+        let partition_id = status.partition_id.as_ref().ok_or_else(|| {
+            BallistaError::Internal("Task status is missing the partition id".to_owned())
+        })?;
+        let mut task = self.get_task_status(namespace, partition_id).await?;
+        match &task.status {
+            Some(task_status::Status::Running(running)) if running.executor_id == executor_id => {}
+            _ => {
+                warn!(
+                    "Ignoring status of task {}/{}/{} from executor {} since the task is not \
+                     running there",
+                    partition_id.job_uuid,
+                    partition_id.stage_id,
+                    partition_id.partition_id,
+                    executor_id
+                );
+                return Ok(());
+            }
+        }
+        match status.status {
+            Some(task_status::Status::Failed(FailedTask { error })) => {
+                self.fail_task_attempt(&mut task, executor_id, error)
+            }
+            other => task.status = other,
+        }
+        self.save_task_status(namespace, &task).await
+    }
+
+    /// Record a failed attempt of a task. The task becomes pending again so that it is
+    /// retried, preferably on another executor, unless it has used up all its attempts.
+    fn fail_task_attempt(&self, task: &mut TaskStatus, executor_id: &str, error: String) {
+        task.failed_executor_ids.push(executor_id.to_owned());
+        if (task.attempts as usize) < self.max_task_attempts {
+            if let Some(partition_id) = &task.partition_id {
+                warn!(
+                    "Attempt {} of task {}/{}/{} failed on executor {} and will be retried: {}",
+                    task.attempts,
+                    partition_id.job_uuid,
+                    partition_id.stage_id,
+                    partition_id.partition_id,
+                    executor_id,
+                    error
+                );
+            }
+            task.status = None;
+        } else {
+            task.status = Some(task_status::Status::Failed(FailedTask {
+                error: format!("{} (after {} attempts)", error, task.attempts),
+            }));
+        }
+    }
+
+    /// Returns true if tasks may have been lost since they were last recovered, which is the
+    /// case when an executor that was alive back then has gone away. Tasks are also recovered
+    /// once per lease time, since other schedulers sharing the backing store may have assigned
+    /// tasks to executors that this scheduler has never seen.
+    async fn executors_lost(&self, executors: &HashMap<String, ExecutorMeta>) -> bool {
+        match &*self.live_executors.lock().await {
+            Some((live, recovered_at)) => {
+                recovered_at.elapsed() >= LEASE_TIME
+                    || live.iter().any(|id| !executors.contains_key(id))
+            }
+            None => true,
+        }
+    }
+
+    /// Remember the executors that are alive, along with the time of the last recovery
+    async fn save_live_executors(
+        &self,
+        executors: &HashMap<String, ExecutorMeta>,
+        recovered: bool,
+    ) {
+        let mut live_executors = self.live_executors.lock().await;
+        let recovered_at = match &*live_executors {
+            Some((_, recovered_at)) if !recovered => *recovered_at,
+            _ => Instant::now(),
+        };
+        *live_executors = Some((executors.keys().cloned().collect(), recovered_at));
+    }
+
+    /// Re-run the tasks whose output was lost with an executor that is no longer alive.
+    /// Tasks running on a lost executor are retried and completed tasks are re-run when
+    /// their output is still needed by the job. Returns the updated task list.
+    async fn recover_lost_tasks(
+        &self,
+        namespace: &str,
+        executors: &HashMap<String, ExecutorMeta>,
+        tasks: Vec<TaskStatus>,
+    ) -> Result<Vec<TaskStatus>> {
+        let mut tasks_by_job: HashMap<String, Vec<TaskStatus>> = HashMap::new();
+        for task in tasks {
+            let job_id = task
+                .partition_id
+                .as_ref()
+                .map(|p| p.job_uuid.clone())
+                .unwrap_or_default();
+            tasks_by_job.entry(job_id).or_default().push(task);
+        }
+
+        let mut result = vec![];
+        for (job_id, mut job_tasks) in tasks_by_job {
+            let job_status = self.get_job_metadata(namespace, &job_id).await?;
+            if !matches!(
+                job_status.and_then(|s| s.status),
+                Some(job_status::Status::Running(_))
+            ) {
+                result.append(&mut job_tasks);
+                continue;
+            }
+
+            // the output of the final stage is read by the client, the output of any other
+            // stage is needed for as long as the stages reading from it have not completed
+            let final_stage_id = job_tasks
+                .iter()
+                .filter_map(|t| t.partition_id.as_ref().map(|p| p.stage_id as usize))
+                .max()
+                .unwrap_or_default();
+            let mut needed_stages: HashSet<usize> = HashSet::new();
+            needed_stages.insert(final_stage_id);
+            let incomplete_stages: HashSet<usize> = job_tasks
+                .iter()
+                .filter(|t| !matches!(t.status, Some(task_status::Status::Completed(_))))
+                .filter_map(|t| t.partition_id.as_ref().map(|p| p.stage_id as usize))
+                .collect();
+            for stage_id in incomplete_stages {
+                let plan = self.get_stage_plan(namespace, &job_id, stage_id).await?;
+                needed_stages.extend(find_unresolved_shuffle_stages(plan.as_ref()));
+            }
+
+            let mut job_changed = false;
+            for task in job_tasks.iter_mut() {
+                let stage_id = task
+                    .partition_id
+                    .as_ref()
+                    .map(|p| p.stage_id as usize)
+                    .unwrap_or_default();
+                let lost = match &task.status {
+                    Some(task_status::Status::Running(RunningTask { executor_id }))
+                        if !executors.contains_key(executor_id) =>
+                    {
+                        Some((
+                            executor_id.clone(),
+                            "Executor was lost while running the task",
+                        ))
+                    }
+                    Some(task_status::Status::Completed(CompletedTask { executor_id, .. }))
+                        if !executors.contains_key(executor_id)
+                            && needed_stages.contains(&stage_id) =>
+                    {
+                        Some((
+                            executor_id.clone(),
+                            "Executor holding the task output was lost",
+                        ))
+                    }
+                    _ => None,
+                };
+                if let Some((executor_id, error)) = lost {
+                    self.fail_task_attempt(task, &executor_id, error.to_owned());
+                    self.save_task_status(namespace, task).await?;
+                    job_changed = true;
+                }
+            }
+            result.append(&mut job_tasks);
+            if job_changed {
+                self.synchronize_job_status(namespace, &job_id).await?;
+            }
+        }
+        Ok(result)
+    }
+
+    pub async fn get_job_tasks(&self, namespace: &str, job_id: &str) -> Result<Vec<TaskStatus>> {
+        self.config_client
+            .clone()
+            .get_from_prefix(&get_task_prefix_for_job(namespace, job_id))
+            .await?
+            .iter()
+            .map(|bytes| decode_protobuf(bytes))
+            .collect()
+    }
+
+    /// Find a pending task whose input stages have all completed and assign it to the
+    /// executor. Returns the task together with a plan that is ready to be executed.
+    pub async fn assign_next_schedulable_task(
+        &self,
+        namespace: &str,
+        executor_id: &str,
+    ) -> Result<Option<(TaskStatus, Arc<dyn ExecutionPlan>)>> {
+        let _guard = self.assign_lock.lock().await;
+        let executors: HashMap<String, ExecutorMeta> = self
+            .get_executors_metadata(namespace)
+            .await?
+            .into_iter()
+            .map(|meta| (meta.id.clone(), meta))
+            .collect();
+        // only the tasks of running jobs can be assigned or hold task slots
+        let mut tasks: Vec<TaskStatus> = vec![];
+        for job_id in self.get_active_jobs(namespace).await? {
+            let job_status = self.get_job_metadata(namespace, &job_id).await?;
+            if matches!(
+                job_status.and_then(|s| s.status),
+                Some(job_status::Status::Running(_))
+            ) {
+                tasks.extend(self.get_job_tasks(namespace, &job_id).await?);
+            }
+        }
+        let recover = self.executors_lost(&executors).await;
+        let tasks = if recover {
+            self.recover_lost_tasks(namespace, &executors, tasks)
+                .await?
+        } else {
+            tasks
+        };
+        self.save_live_executors(&executors, recover).await;
+
+        // never place more tasks on an executor than it has task slots. Tasks that cannot be
+        // placed stay pending until a slot frees up. An executor whose registration expired in
+        // the meantime is not assigned anything until it has registered again.
+        let task_slots = executors
+            .get(executor_id)
+            .map(|meta| meta.task_slots)
+            .unwrap_or_default();
+        let running_tasks = tasks
+            .iter()
+            .filter(|t| match &t.status {
+                Some(task_status::Status::Running(running)) => running.executor_id == executor_id,
+                _ => false,
+            })
+            .count();
+        if running_tasks >= task_slots {
+            debug!(
+                "Executor {} has no free task slots ({} running, {} slots)",
+                executor_id, running_tasks, task_slots
+            );
+            return Ok(None);
+        }
+
+        // the tasks of a stage share its plan, which is only decoded once
+        let mut plans: HashMap<(String, usize), Arc<dyn ExecutionPlan>> = HashMap::new();
+        for status in &tasks {
+            if status.status.is_some() {
+                continue;
+            }
+            let partition_id = status.partition_id.as_ref().ok_or_else(|| {
+                BallistaError::Internal("Task status is missing the partition id".to_owned())
+            })?;
+            let job_id = &partition_id.job_uuid;
+            // retry failed tasks on a different executor whenever there is one
+            if status
+                .failed_executor_ids
+                .iter()
+                .any(|id| id == executor_id)
+                && executors
+                    .keys()
+                    .any(|id| !status.failed_executor_ids.contains(id))
+            {
+                continue;
+            }
+            let stage_key = (job_id.clone(), partition_id.stage_id as usize);
+            let plan = match plans.get(&stage_key) {
+                Some(plan) => plan.clone(),
+                None => {
+                    let plan = self
+                        .get_stage_plan(namespace, job_id, partition_id.stage_id as usize)
+                        .await?;
+                    plans.insert(stage_key, plan.clone());
+                    plan
+                }
+            };
+
+            // the task can only run once all the stages it reads from have completed
+            let mut partition_locations: HashMap<usize, Vec<PartitionLocation>> = HashMap::new();
+            let mut dependencies_completed = true;
+            for stage_id in find_unresolved_shuffle_stages(plan.as_ref()) {
+                let mut stage_tasks: Vec<&TaskStatus> = tasks
+                    .iter()
+                    .filter(|t| {
+                        t.partition_id.as_ref().map_or(false, |p| {
+                            &p.job_uuid == job_id && p.stage_id as usize == stage_id
+                        })
+                    })
+                    .collect();
+                stage_tasks.sort_by_key(|t| t.partition_id.as_ref().unwrap().partition_id);
+                let mut locations = vec![];
+                for stage_task in stage_tasks {
+                    let executor_meta = match &stage_task.status {
+                        Some(task_status::Status::Completed(CompletedTask {
+                            executor_id, ..
+                        })) => executors.get(executor_id),
+                        _ => None,
+                    };
+                    match executor_meta {
+                        Some(executor_meta) => locations.push(PartitionLocation {
+                            partition_id: stage_task.partition_id.clone().unwrap().try_into()?,
+                            executor_meta: executor_meta.clone(),
+                        }),
+                        None => {
+                            dependencies_completed = false;
+                            break;
+                        }
+                    }
+                }
+                if !dependencies_completed {
+                    break;
+                }
+                partition_locations.insert(stage_id, locations);
+            }
+            if !dependencies_completed {
+                continue;
+            }
+
+            let plan = remove_unresolved_shuffles(plan.as_ref(), &partition_locations)?;
+            let status = TaskStatus {
+                partition_id: status.partition_id.clone(),
+                status: Some(task_status::Status::Running(RunningTask {
+                    executor_id: executor_id.to_owned(),
+                })),
+                attempts: status.attempts + 1,
+                failed_executor_ids: status.failed_executor_ids.clone(),
+            };
+            self.save_task_status(namespace, &status).await?;
+            return Ok(Some((status, plan)));
+        }
+        Ok(None)
+    }
+
+    /// Update the status of a running job based on the status of its tasks. The job fails as
+    /// soon as one task has failed all its attempts and completes once every task has
+    /// completed. While the job is running, its status keeps track of the task attempts.
+    /// Returns the status of the job when it has finished with this update.
+    pub async fn synchronize_job_status(
+        &self,
+        namespace: &str,
+        job_id: &str,
+    ) -> Result<Option<JobStatus>> {
+        let _guard = self.job_status_lock.lock().await;
+        let mut info = match self.get_job_info(namespace, job_id).await? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let previous_info = info.clone();
+        let job_status = info.status.clone().unwrap_or_default();
+        if !matches!(job_status.status, Some(job_status::Status::Running(_))) {
+            return Ok(None);
+        }
+        let tasks = self.get_job_tasks(namespace, job_id).await?;
+        if tasks.is_empty() {
+            return Ok(None);
+        }
+        let executors: HashMap<String, ExecutorMeta> = self
+            .get_executors_metadata(namespace)
+            .await?
+            .into_iter()
+            .map(|meta| (meta.id.clone(), meta))
+            .collect();
+        let final_stage_id = tasks
+            .iter()
+            .filter_map(|t| t.partition_id.as_ref().map(|p| p.stage_id))
+            .max()
+            .unwrap_or_default();
+        let task_attempts = tasks.iter().map(|t| t.attempts).sum();
+        record_job_progress(&mut info, &tasks, final_stage_id);
+
+        let mut partition_location = vec![];
+        let mut completed = true;
+        for task in &tasks {
+            let partition_id = task.partition_id.clone().ok_or_else(|| {
+                BallistaError::Internal("Task status is missing the partition id".to_owned())
+            })?;
+            match &task.status {
+                Some(task_status::Status::Failed(failed)) => {
+                    info!(
+                        "Job {} failed because a task failed: {}",
+                        job_id, failed.error
+                    );
+                    let status = JobStatus {
+                        status: Some(job_status::Status::Failed(FailedJob {
+                            error: format!(
+                                "Task {}/{} failed: {}",
+                                partition_id.stage_id, partition_id.partition_id, failed.error
+                            ),
+                        })),
+                        task_attempts,
+                    };
+                    set_job_status(&mut info, status.clone());
+                    self.save_job_info(namespace, &info).await?;
+                    return Ok(Some(status));
+                }
+                Some(task_status::Status::Completed(CompletedTask { executor_id, .. })) => {
+                    if partition_id.stage_id == final_stage_id {
+                        match executors.get(executor_id) {
+                            Some(executor_meta) => {
+                                partition_location.push(protobuf::PartitionLocation {
+                                    partition_id: Some(partition_id),
+                                    executor_meta: Some(executor_meta.clone().into()),
+                                })
+                            }
+                            // the result partition has to be computed again
+                            None => completed = false,
+                        }
+                    }
+                }
+                _ => completed = false,
+            }
+        }
+
+        let status = if completed {
+            partition_location.sort_by_key(|l| l.partition_id.as_ref().unwrap().partition_id);
+            JobStatus {
+                status: Some(job_status::Status::Completed(CompletedJob {
+                    partition_location,
+                })),
+                task_attempts,
+            }
+        } else {
+            JobStatus {
+                status: job_status.status,
+                task_attempts,
+            }
+        };
+        set_job_status(&mut info, status.clone());
+        if info == previous_info {
+            // the job is still running and nothing changed
+            return Ok(None);
+        }
+        self.save_job_info(namespace, &info).await?;
+        Ok(if completed { Some(status) } else { None })
+    }
+}
+
+/// Returns true if the job has completed, failed or was cancelled
+fn job_finished(status: &JobStatus) -> bool {
+    matches!(
+        status.status,
+        Some(job_status::Status::Completed(_))
+            | Some(job_status::Status::Failed(_))
+            | Some(job_status::Status::Cancelled(_))
+    )
+}
+
+/// Set the status of a job and record its end time once it has finished
+fn set_job_status(info: &mut JobInfo, status: JobStatus) {
+    if job_finished(&status) && info.end_time == 0 {
+        info.end_time = now_millis();
+    }
+    info.status = Some(status);
+}
+
+fn job_state(info: &JobInfo) -> JobState {
+    match info
+        .status
+        .as_ref()
+        .and_then(|status| status.status.as_ref())
+    {
+        None | Some(job_status::Status::Queued(_)) => JobState::Queued,
+        Some(job_status::Status::Running(_)) => JobState::Running,
+        Some(job_status::Status::Failed(_)) => JobState::Failed,
+        Some(job_status::Status::Completed(_)) => JobState::Completed,
+        Some(job_status::Status::Cancelled(_)) => JobState::Cancelled,
+    }
+}
+
+/// Record the task progress of each stage of a job, and the rows and bytes of its result
+/// partitions
+fn record_job_progress(info: &mut JobInfo, tasks: &[TaskStatus], final_stage_id: u32) {
+    let mut stages: BTreeMap<u32, StageProgress> = BTreeMap::new();
+    let mut num_rows = 0;
+    let mut num_bytes = 0;
+    for task in tasks {
+        let stage_id = match &task.partition_id {
+            Some(partition_id) => partition_id.stage_id,
+            None => continue,
+        };
+        let progress = stages.entry(stage_id).or_insert_with(|| StageProgress {
+            stage_id,
+            ..Default::default()
+        });
+        progress.total_tasks += 1;
+        if let Some(task_status::Status::Completed(completed)) = &task.status {
+            progress.completed_tasks += 1;
+            match &completed.stats {
+                Some(stats) if stage_id == final_stage_id => {
+                    num_rows += stats.num_rows;
+                    num_bytes += stats.num_bytes;
+                }
+                _ => {}
+            }
+        }
+    }
+    info.stages = stages.values().cloned().collect();
+    info.num_rows = num_rows;
+    info.num_bytes = num_bytes;
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Returns the ids of the query stages that the plan reads from
+fn find_unresolved_shuffle_stages(plan: &dyn ExecutionPlan) -> Vec<usize> {
+    if let Some(unresolved_shuffle) = plan.as_any().downcast_ref::<UnresolvedShuffleExec>() {
+        unresolved_shuffle.query_stage_ids.clone()
+    } else {
+        plan.children()
+            .iter()
+            .flat_map(|child| find_unresolved_shuffle_stages(child.as_ref()))
+            .collect()
     }
 }
 
@@ -116,8 +911,54 @@ fn get_executor_key(namespace: &str, id: &str) -> String {
     format!("{}/{}", get_executors_prefix(namespace), id)
 }
 
+fn get_jobs_prefix(namespace: &str) -> String {
+    format!("/ballista/jobs/{}/", namespace)
+}
+
 fn get_job_key(namespace: &str, id: &str) -> String {
-    format!("/ballista/jobs/{}/{}", namespace, id)
+    format!("{}{}", get_jobs_prefix(namespace), id)
+}
+
+fn get_active_jobs_prefix(namespace: &str) -> String {
+    format!("/ballista/active_jobs/{}/", namespace)
+}
+
+fn get_active_job_key(namespace: &str, id: &str) -> String {
+    format!("{}{}", get_active_jobs_prefix(namespace), id)
+}
+
+fn get_session_key(namespace: &str, session_id: &str) -> String {
+    format!("/ballista/sessions/{}/{}", namespace, session_id)
+}
+
+fn get_stage_plan_prefix(namespace: &str, job_id: &str) -> String {
+    format!("/ballista/stages/{}/{}/", namespace, job_id)
+}
+
+fn get_stage_plan_key(namespace: &str, job_id: &str, stage_id: usize) -> String {
+    format!("{}{}", get_stage_plan_prefix(namespace, job_id), stage_id)
+}
+
+fn get_task_prefix(namespace: &str) -> String {
+    format!("/ballista/tasks/{}/", namespace)
+}
+
+fn get_task_prefix_for_job(namespace: &str, job_id: &str) -> String {
+    format!("{}{}/", get_task_prefix(namespace), job_id)
+}
+
+fn get_task_status_key(
+    namespace: &str,
+    job_id: &str,
+    stage_id: usize,
+    partition_id: usize,
+) -> String {
+    format!(
+        "{}{}/{}",
+        get_task_prefix_for_job(namespace, job_id),
+        stage_id,
+        partition_id
+    )
 }
 
 fn decode_protobuf<T: Message + Default>(bytes: &[u8]) -> Result<T> {
@@ -133,3 +974,480 @@ fn encode_protobuf<T: Message + Default>(msg: &T) -> Result<Vec<u8>> {
     })?;
     Ok(value)
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use arrow::datatypes::Schema;
+    use datafusion::physical_plan::empty::EmptyExec;
+    use uuid::Uuid;
+
+    use super::{SchedulerState, StandaloneClient};
+    use crate::error::BallistaError;
+    use crate::scheduler::execution_plans::UnresolvedShuffleExec;
+    use crate::serde::protobuf::{
+        job_status, task_status, CompletedTask, FailedTask, JobState, JobStatus, LogicalPlanNode,
+        PartitionStats, RunningJob, SessionState, StageProgress, TaskStatus,
+    };
+    use crate::serde::scheduler::{ExecutorMeta, PartitionId};
+
+    type State = SchedulerState<StandaloneClient>;
+
+    /// Register an executor with the given number of task slots
+    async fn save_executor(
+        state: &State,
+        namespace: &str,
+        id: &str,
+        port: u16,
+        task_slots: usize,
+    ) -> Result<ExecutorMeta, BallistaError> {
+        let executor = ExecutorMeta {
+            id: id.to_owned(),
+            host: "localhost".to_owned(),
+            port,
+            task_slots,
+        };
+        state
+            .save_executor_metadata(namespace, executor.clone())
+            .await?;
+        Ok(executor)
+    }
+
+    fn pending_task(job_uuid: Uuid, stage_id: usize, partition_id: usize) -> TaskStatus {
+        TaskStatus {
+            partition_id: Some(PartitionId::new(job_uuid, stage_id, partition_id).into()),
+            status: None,
+            attempts: 0,
+            failed_executor_ids: vec![],
+        }
+    }
+
+    async fn save_running_job(
+        state: &State,
+        namespace: &str,
+        job_id: &str,
+    ) -> Result<(), BallistaError> {
+        state
+            .save_job_metadata(
+                namespace,
+                job_id,
+                &JobStatus {
+                    status: Some(job_status::Status::Running(RunningJob {})),
+                    task_attempts: 0,
+                },
+            )
+            .await
+    }
+
+    /// Save a running job with a single stage of empty partitions that are waiting to be
+    /// scheduled
+    async fn save_empty_job(
+        state: &State,
+        namespace: &str,
+        job_uuid: Uuid,
+        num_partitions: usize,
+    ) -> Result<(), BallistaError> {
+        let job_id = job_uuid.to_string();
+        state
+            .save_stage_plan(
+                namespace,
+                &job_id,
+                1,
+                Arc::new(EmptyExec::new(false, Arc::new(Schema::empty()))),
+            )
+            .await?;
+        for partition_id in 0..num_partitions {
+            state
+                .save_task_status(namespace, &pending_task(job_uuid, 1, partition_id))
+                .await?;
+        }
+        save_running_job(state, namespace, &job_id).await
+    }
+
+    #[tokio::test]
+    async fn assign_and_complete_task() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 1);
+        let namespace = "default";
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+        let executor = save_executor(&state, namespace, "executor", 50051, 1).await?;
+        save_empty_job(&state, namespace, job_uuid, 1).await?;
+
+        let (task, _plan) = state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .expect("Expected a task to be assigned");
+        assert!(matches!(task.status, Some(task_status::Status::Running(_))));
+        // the only task is running so there is nothing left to assign
+        assert!(state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .is_none());
+
+        state
+            .save_task_status(
+                namespace,
+                &TaskStatus {
+                    partition_id: task.partition_id,
+                    status: Some(task_status::Status::Completed(CompletedTask {
+                        executor_id: executor.id.clone(),
+                        stats: None,
+                    })),
+                    attempts: task.attempts,
+                    failed_executor_ids: vec![],
+                },
+            )
+            .await?;
+        state.synchronize_job_status(namespace, &job_id).await?;
+        let status = state.get_job_metadata(namespace, &job_id).await?.unwrap();
+        match status.status {
+            Some(job_status::Status::Completed(completed)) => {
+                assert_eq!(completed.partition_location.len(), 1);
+            }
+            other => panic!("Expected job to be completed, got {:?}", other),
+        }
+        // the tasks and stage plans of a finished job are removed
+        assert!(state.get_job_tasks(namespace, &job_id).await?.is_empty());
+        assert!(state.get_active_jobs(namespace).await?.is_empty());
+        assert!(state.get_stage_plan(namespace, &job_id, 1).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn respect_task_slots() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 1);
+        let namespace = "default";
+        let job_uuid = Uuid::new_v4();
+        let executor = save_executor(&state, namespace, "executor", 50051, 1).await?;
+        save_empty_job(&state, namespace, job_uuid, 2).await?;
+
+        let (task, _plan) = state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .expect("Expected a task to be assigned");
+        // the executor only has one slot so the second task has to wait
+        assert!(state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .is_none());
+
+        state
+            .save_task_status(
+                namespace,
+                &TaskStatus {
+                    partition_id: task.partition_id,
+                    status: Some(task_status::Status::Completed(CompletedTask {
+                        executor_id: executor.id.clone(),
+                        stats: None,
+                    })),
+                    attempts: task.attempts,
+                    failed_executor_ids: vec![],
+                },
+            )
+            .await?;
+        assert!(state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_failed_task_on_other_executor() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 2);
+        let namespace = "default";
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+        let mut executors = vec![];
+        for i in 0..2 {
+            let id = format!("executor-{}", i);
+            executors.push(save_executor(&state, namespace, &id, 50051 + i, 1).await?);
+        }
+        save_empty_job(&state, namespace, job_uuid, 1).await?;
+
+        let failed = |task: &TaskStatus| TaskStatus {
+            partition_id: task.partition_id.clone(),
+            status: Some(task_status::Status::Failed(FailedTask {
+                error: "boom".to_owned(),
+            })),
+            attempts: 0,
+            failed_executor_ids: vec![],
+        };
+
+        let (task, _plan) = state
+            .assign_next_schedulable_task(namespace, &executors[0].id)
+            .await?
+            .expect("Expected a task to be assigned");
+        assert_eq!(task.attempts, 1);
+        state
+            .update_task_status(namespace, &executors[0].id, failed(&task))
+            .await?;
+        state.synchronize_job_status(namespace, &job_id).await?;
+        let status = state.get_job_metadata(namespace, &job_id).await?.unwrap();
+        assert!(matches!(
+            status.status,
+            Some(job_status::Status::Running(_))
+        ));
+        assert_eq!(status.task_attempts, 1);
+
+        // the task failed on the first executor so it is retried on the second one
+        assert!(state
+            .assign_next_schedulable_task(namespace, &executors[0].id)
+            .await?
+            .is_none());
+        let (task, _plan) = state
+            .assign_next_schedulable_task(namespace, &executors[1].id)
+            .await?
+            .expect("Expected the task to be retried");
+        assert_eq!(task.attempts, 2);
+
+        // the second failure uses up all the attempts
+        state
+            .update_task_status(namespace, &executors[1].id, failed(&task))
+            .await?;
+        state.synchronize_job_status(namespace, &job_id).await?;
+        let status = state.get_job_metadata(namespace, &job_id).await?.unwrap();
+        assert!(matches!(status.status, Some(job_status::Status::Failed(_))));
+        assert_eq!(status.task_attempts, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rerun_lost_shuffle_output() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 3);
+        let namespace = "default";
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+        let executor = save_executor(&state, namespace, "executor", 50051, 1).await?;
+        let schema = Arc::new(Schema::empty());
+        state
+            .save_stage_plan(
+                namespace,
+                &job_id,
+                1,
+                Arc::new(EmptyExec::new(false, schema.clone())),
+            )
+            .await?;
+        state
+            .save_stage_plan(
+                namespace,
+                &job_id,
+                2,
+                Arc::new(UnresolvedShuffleExec::new(vec![1], schema, 1, None)),
+            )
+            .await?;
+        // stage 1 completed on an executor that is no longer registered
+        state
+            .save_task_status(
+                namespace,
+                &TaskStatus {
+                    partition_id: Some(PartitionId::new(job_uuid, 1, 0).into()),
+                    status: Some(task_status::Status::Completed(CompletedTask {
+                        executor_id: "lost-executor".to_owned(),
+                        stats: None,
+                    })),
+                    attempts: 1,
+                    failed_executor_ids: vec![],
+                },
+            )
+            .await?;
+        state
+            .save_task_status(namespace, &pending_task(job_uuid, 2, 0))
+            .await?;
+        save_running_job(&state, namespace, &job_id).await?;
+
+        // tasks are only recovered once an executor that was alive at the last recovery is gone
+        let executors: HashMap<String, ExecutorMeta> =
+            vec![(executor.id.clone(), executor.clone())]
+                .into_iter()
+                .collect();
+        state.save_live_executors(&executors, true).await;
+        assert!(!state.executors_lost(&executors).await);
+        let live = vec![executor.id.clone(), "lost-executor".to_owned()];
+        *state.live_executors.lock().await = Some((live.into_iter().collect(), Instant::now()));
+        assert!(state.executors_lost(&executors).await);
+
+        let (task, _plan) = state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .expect("Expected a task to be assigned");
+        let partition_id = task.partition_id.unwrap();
+        assert_eq!(partition_id.stage_id, 1);
+        assert_eq!(task.attempts, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancel_running_job() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 1);
+        let namespace = "default";
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+        let executor = save_executor(&state, namespace, "executor", 50051, 1).await?;
+        save_empty_job(&state, namespace, job_uuid, 1).await?;
+
+        assert!(state
+            .get_job_metadata(namespace, "missing")
+            .await?
+            .is_none());
+        assert!(!state.cancel_job(namespace, "missing").await?);
+        assert!(state.cancel_job(namespace, &job_id).await?);
+        // the job has already been cancelled
+        assert!(!state.cancel_job(namespace, &job_id).await?);
+        let status = state.get_job_metadata(namespace, &job_id).await?.unwrap();
+        assert!(matches!(
+            status.status,
+            Some(job_status::Status::Cancelled(_))
+        ));
+
+        // tasks of a cancelled job are not assigned and the job is not resumed
+        assert!(state
+            .assign_next_schedulable_task(namespace, &executor.id)
+            .await?
+            .is_none());
+        let running = JobStatus {
+            status: Some(job_status::Status::Running(RunningJob {})),
+            task_attempts: 0,
+        };
+        assert!(
+            !state
+                .save_job_metadata_unless_cancelled(namespace, &job_id, &running)
+                .await?
+        );
+        let status = state.get_job_metadata(namespace, &job_id).await?.unwrap();
+        assert!(matches!(
+            status.status,
+            Some(job_status::Status::Cancelled(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn register_session_tables() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 1);
+        let namespace = "default";
+        assert!(state.get_session(namespace, "session").await?.is_none());
+        assert!(state
+            .register_session_table(namespace, "session", "t", LogicalPlanNode::default())
+            .await
+            .is_err());
+
+        // a session without tables or settings must still be found
+        state
+            .save_session(
+                namespace,
+                &SessionState {
+                    session_id: "session".to_owned(),
+                    tables: vec![],
+                    settings: vec![],
+                },
+            )
+            .await?;
+        assert!(state.get_session(namespace, "session").await?.is_some());
+
+        state
+            .register_session_table(namespace, "session", "t1", LogicalPlanNode::default())
+            .await?;
+        state
+            .register_session_table(namespace, "session", "t2", LogicalPlanNode::default())
+            .await?;
+        // registering a table again replaces it
+        state
+            .register_session_table(namespace, "session", "t1", LogicalPlanNode::default())
+            .await?;
+        let session = state.get_session(namespace, "session").await?.unwrap();
+        let mut names: Vec<&str> = session.tables.iter().map(|t| t.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(vec!["t1", "t2"], names);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_job_info() -> Result<(), BallistaError> {
+        let state = SchedulerState::new(StandaloneClient::try_new_temporary()?, 1);
+        let namespace = "default";
+        let job_uuid = Uuid::new_v4();
+        let job_id = job_uuid.to_string();
+        let executor = save_executor(&state, namespace, "executor", 50051, 2).await?;
+        state
+            .create_job(namespace, &job_id, "EmptyRelation".to_owned())
+            .await?;
+        for partition_id in 0..2 {
+            state
+                .save_task_status(namespace, &pending_task(job_uuid, 1, partition_id))
+                .await?;
+        }
+        save_running_job(&state, namespace, &job_id).await?;
+
+        let complete_task = |partition_id| TaskStatus {
+            partition_id: Some(PartitionId::new(job_uuid, 1, partition_id).into()),
+            status: Some(task_status::Status::Completed(CompletedTask {
+                executor_id: executor.id.clone(),
+                stats: Some(PartitionStats {
+                    num_rows: 10,
+                    num_batches: 1,
+                    num_bytes: 100,
+                    null_count: 0,
+                    skipped_rows: 0,
+                    skipped_bytes: 0,
+                    uncompressed_bytes: 100,
+                    compressed_bytes: 100,
+                }),
+            })),
+            attempts: 1,
+            failed_executor_ids: vec![],
+        };
+        state.save_task_status(namespace, &complete_task(0)).await?;
+        assert!(state
+            .synchronize_job_status(namespace, &job_id)
+            .await?
+            .is_none());
+        let info = state.get_job_info(namespace, &job_id).await?.unwrap();
+        assert_eq!("EmptyRelation", info.plan);
+        assert!(info.start_time > 0);
+        assert_eq!(0, info.end_time);
+        assert_eq!(
+            vec![StageProgress {
+                stage_id: 1,
+                completed_tasks: 1,
+                total_tasks: 2,
+            }],
+            info.stages
+        );
+        assert_eq!(10, info.num_rows);
+        assert_eq!(
+            1,
+            state
+                .list_jobs(namespace, &[JobState::Running])
+                .await?
+                .len()
+        );
+        assert!(state
+            .list_jobs(namespace, &[JobState::Completed])
+            .await?
+            .is_empty());
+
+        state.save_task_status(namespace, &complete_task(1)).await?;
+        // the job finishes with this update
+        let finished = state.synchronize_job_status(namespace, &job_id).await?;
+        assert!(matches!(
+            finished.and_then(|status| status.status),
+            Some(job_status::Status::Completed(_))
+        ));
+        let info = state.get_job_info(namespace, &job_id).await?.unwrap();
+        assert!(info.end_time >= info.start_time);
+        assert_eq!(20, info.num_rows);
+        assert_eq!(200, info.num_bytes);
+        let jobs = state.list_jobs(namespace, &[JobState::Completed]).await?;
+        assert_eq!(
+            vec![job_id.clone()],
+            jobs.into_iter().map(|j| j.job_id).collect::<Vec<_>>()
+        );
+        assert_eq!(1, state.list_jobs(namespace, &[]).await?.len());
+        assert!(state.list_jobs("other", &[]).await?.is_empty());
+        Ok(())
+    }
+}
@@ -12,70 +12,196 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::convert::TryInto;
 use std::time::Duration;
 
 use crate::error::{ballista_error, Result};
-use crate::scheduler::state::ConfigBackendClient;
+use crate::scheduler::state::{now_millis, ConfigBackendClient};
 
-use log::warn;
+use log::{debug, warn};
+
+/// Values are stored behind a header holding the time at which their lease expires, in
+/// milliseconds since the Unix epoch. Values without a lease have an expiry time of zero.
+const EXPIRY_HEADER_LEN: usize = 8;
+
+/// Key holding the version of the format of the stored values. It is stored without an expiry
+/// header and is not under the `/ballista/` prefix, so it is never read as state.
+const FORMAT_VERSION_KEY: &str = "format_version";
+
+/// Version of the format of the stored values. Version 1 added the expiry header, and
+/// databases without a version were written before it.
+const FORMAT_VERSION: u32 = 1;
+
+/// How often the sweeper removes expired keys unless configured otherwise
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
 
 /// A [`ConfigBackendClient`] implementation that uses file-based storage to save cluster configuration.
+///
+/// Keys that were saved with a lease are no longer read once the lease has expired. Expired keys
+/// are removed from storage by the sweeper started with [`StandaloneClient::spawn_sweeper`].
 #[derive(Clone)]
 pub struct StandaloneClient {
     db: sled::Db,
 }
 
 impl StandaloneClient {
-    /// Creates a StandaloneClient that saves data to the specified file.
+    /// Creates a StandaloneClient that saves data to the specified file. Fails if the database
+    /// was written in another format, such as by a release before the format was versioned, in
+    /// which case the directory must be removed.
     pub fn try_new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-        Ok(Self {
-            db: sled::open(path)?,
-        })
+        Self::try_from_db(sled::open(path)?)
     }
 
     /// Creates a StandaloneClient that saves data to a temp file.
     pub fn try_new_temporary() -> Result<Self> {
-        Ok(Self {
-            db: sled::Config::new().temporary(true).open()?,
+        Self::try_from_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    fn try_from_db(db: sled::Db) -> Result<Self> {
+        let version = db
+            .get(FORMAT_VERSION_KEY)
+            .map_err(|e| ballista_error(&format!("sled error {:?}", e)))?;
+        match version {
+            Some(version) if version.as_ref() == FORMAT_VERSION.to_be_bytes() => {}
+            Some(version) => {
+                return Err(ballista_error(&format!(
+                    "sled database has format version {:?} instead of {}",
+                    version.as_ref(),
+                    FORMAT_VERSION
+                )))
+            }
+            None if db.is_empty() => {
+                db.insert(FORMAT_VERSION_KEY, &FORMAT_VERSION.to_be_bytes()[..])
+                    .map_err(|e| ballista_error(&format!("sled error {:?}", e)))?;
+            }
+            None => {
+                return Err(ballista_error(
+                    "sled database was written by an older version of the scheduler and must be \
+                     removed",
+                ))
+            }
+        }
+        Ok(Self { db })
+    }
+
+    /// Remove the keys whose lease has expired and return how many were removed. This scans the
+    /// whole database, so it should not be called from an async task.
+    pub fn remove_expired_keys(&self) -> Result<usize> {
+        let now = now_millis();
+        let mut removed = 0;
+        for entry in self.db.iter() {
+            let (key, value) = entry.map_err(|e| ballista_error(&format!("sled error {:?}", e)))?;
+            let is_version = key.as_ref() == FORMAT_VERSION_KEY.as_bytes();
+            if is_version || decode_value(&value, now)?.is_some() {
+                continue;
+            }
+            // the key is only removed if its lease has not been renewed in the meantime
+            let result = self
+                .db
+                .compare_and_swap(&key, Some(&value), None as Option<&[u8]>)
+                .map_err(|e| ballista_error(&format!("sled error {:?}", e)))?;
+            if result.is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Periodically remove expired keys in the background. Scanning the database blocks, so each
+    /// sweep runs on a blocking thread.
+    pub fn spawn_sweeper(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let client = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let client = client.clone();
+                match tokio::task::spawn_blocking(move || client.remove_expired_keys()).await {
+                    Ok(Ok(removed)) => debug!("Removed {} expired keys", removed),
+                    Ok(Err(e)) => warn!("Could not remove expired keys: {}", e),
+                    Err(e) => warn!("Could not remove expired keys: {:?}", e),
+                }
+            }
         })
     }
 }
 
+fn encode_value(value: Vec<u8>, lease_time: Option<Duration>) -> Vec<u8> {
+    let expiry = lease_time
+        .map(|lease_time| now_millis() + lease_time.as_millis() as u64)
+        .unwrap_or(0);
+    let mut encoded = Vec::with_capacity(EXPIRY_HEADER_LEN + value.len());
+    encoded.extend_from_slice(&expiry.to_be_bytes());
+    encoded.extend(value);
+    encoded
+}
+
+/// Returns None if the lease of the value has expired
+fn decode_value(encoded: &[u8], now: u64) -> Result<Option<&[u8]>> {
+    if encoded.len() < EXPIRY_HEADER_LEN {
+        return Err(ballista_error("sled value is missing its expiry header"));
+    }
+    let (expiry, value) = encoded.split_at(EXPIRY_HEADER_LEN);
+    let expiry = u64::from_be_bytes(expiry.try_into().unwrap());
+    if expiry != 0 && expiry <= now {
+        Ok(None)
+    } else {
+        Ok(Some(value))
+    }
+}
+
 #[tonic::async_trait]
 impl ConfigBackendClient for StandaloneClient {
     async fn get(&mut self, key: &str) -> Result<Vec<u8>> {
-        Ok(self
+        let value = self
             .db
             .get(key)
-            .map_err(|e| ballista_error(&format!("sled error {:?}", e)))?
-            .map(|v| v.to_vec())
-            .unwrap_or_default())
+            .map_err(|e| ballista_error(&format!("sled error {:?}", e)))?;
+        match value {
+            Some(value) => Ok(decode_value(&value, now_millis())?
+                .map(|v| v.to_vec())
+                .unwrap_or_default()),
+            None => Ok(vec![]),
+        }
     }
 
     async fn get_from_prefix(&mut self, prefix: &str) -> Result<Vec<Vec<u8>>> {
-        Ok(self
-            .db
-            .scan_prefix(prefix)
-            .map(|v| v.map(|(_key, value)| value.to_vec()))
-            .collect::<std::result::Result<Vec<_>, _>>()
-            .map_err(|e| ballista_error(&format!("sled error {:?}", e)))?)
+        let now = now_millis();
+        let mut result = vec![];
+        for entry in self.db.scan_prefix(prefix) {
+            let (_key, value) =
+                entry.map_err(|e| ballista_error(&format!("sled error {:?}", e)))?;
+            if let Some(value) = decode_value(&value, now)? {
+                result.push(value.to_vec());
+            }
+        }
+        Ok(result)
     }
 
-    // TODO: support lease_time. See https://github.com/spacejam/sled/issues/1119 for how to approach this
     async fn put(
         &mut self,
         key: String,
         value: Vec<u8>,
-        _lease_time: Option<Duration>,
+        lease_time: Option<Duration>,
     ) -> Result<()> {
         self.db
-            .insert(key, value)
+            .insert(key, encode_value(value, lease_time))
             .map_err(|e| {
                 warn!("sled insert failed: {}", e);
                 ballista_error("sled insert failed")
             })
             .map(|_| ())
     }
+
+    async fn delete_from_prefix(&mut self, prefix: &str) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for key in self.db.scan_prefix(prefix).keys() {
+            batch.remove(key.map_err(|e| ballista_error(&format!("sled error {:?}", e)))?);
+        }
+        self.db.apply_batch(batch).map_err(|e| {
+            warn!("sled delete failed: {}", e);
+            ballista_error("sled delete failed")
+        })
+    }
 }
 
 #[cfg(test)]
@@ -84,6 +210,7 @@ mod tests {
 
     use super::StandaloneClient;
     use std::result::Result;
+    use std::time::Duration;
 
     fn create_instance() -> Result<StandaloneClient, Box<dyn std::error::Error>> {
         Ok(StandaloneClient::try_new_temporary()?)
@@ -122,4 +249,73 @@ mod tests {
         assert_eq!(client.get_from_prefix(key).await?, vec![value, value]);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn read_expired() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = create_instance()?;
+        let value = "value".as_bytes();
+        client
+            .put(
+                "key/1".to_owned(),
+                value.to_vec(),
+                Some(Duration::from_millis(1)),
+            )
+            .await?;
+        client
+            .put(
+                "key/2".to_owned(),
+                value.to_vec(),
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        client.put("key/3".to_owned(), value.to_vec(), None).await?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let empty: &[u8] = &[];
+        assert_eq!(client.get("key/1").await?, empty);
+        assert_eq!(client.get("key/2").await?, value);
+        assert_eq!(client.get_from_prefix("key").await?, vec![value, value]);
+
+        assert_eq!(client.remove_expired_keys()?, 1);
+        assert_eq!(client.remove_expired_keys()?, 0);
+        assert_eq!(client.get_from_prefix("key").await?, vec![value, value]);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unversioned_database() -> Result<(), Box<dyn std::error::Error>> {
+        let db = sled::Config::new().temporary(true).open()?;
+        db.insert("/ballista/key", "value")?;
+        assert!(StandaloneClient::try_from_db(db.clone()).is_err());
+
+        db.clear()?;
+        let client = StandaloneClient::try_from_db(db.clone())?;
+        assert_eq!(client.remove_expired_keys()?, 0);
+        assert!(StandaloneClient::try_from_db(db).is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn renew_lease() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = create_instance()?;
+        let value = "value".as_bytes();
+        client
+            .put(
+                "key".to_owned(),
+                value.to_vec(),
+                Some(Duration::from_millis(1)),
+            )
+            .await?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        client
+            .put(
+                "key".to_owned(),
+                value.to_vec(),
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        assert_eq!(client.remove_expired_keys()?, 0);
+        assert_eq!(client.get("key").await?, value);
+        Ok(())
+    }
 }
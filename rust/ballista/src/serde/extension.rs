@@ -0,0 +1,591 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extension point for serializing user-defined logical nodes, execution plans and table
+//! providers.
+//!
+//! Ballista can only serialize the plans it knows about. Plans containing custom operators or
+//! table providers can be run by implementing [`BallistaCodec`] for them and registering the
+//! codec with [`register_extension_codec`] in every process that handles the plans, i.e. the
+//! client, the scheduler and the executors. The scheduler plans user-defined logical nodes with
+//! the [`ExtensionPlanner`] returned by [`BallistaCodec::extension_planner`].
+
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use crate::error::{BallistaError, Result};
+
+use arrow::datatypes::SchemaRef;
+use datafusion::datasource::TableProvider;
+use datafusion::execution::context::{ExecutionConfig, ExecutionContextState, QueryPlanner};
+use datafusion::logical_plan::{LogicalPlan, UserDefinedLogicalNode};
+use datafusion::physical_plan::planner::{DefaultPhysicalPlanner, ExtensionPlanner};
+use datafusion::physical_plan::{ExecutionPlan, PhysicalPlanner};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref CODECS: RwLock<Vec<Arc<dyn BallistaCodec>>> = RwLock::new(vec![]);
+}
+
+/// Encodes and decodes user-defined plan nodes as opaque bytes.
+///
+/// Inputs of the nodes are serialized by Ballista and passed back when decoding, so a codec
+/// only needs to encode the state of the node itself.
+pub trait BallistaCodec: Send + Sync {
+    /// Name identifying this codec in serialized plans
+    fn type_name(&self) -> &str;
+
+    /// Encode a user-defined logical node, returning `None` if it is not handled by this codec
+    fn try_encode_logical(&self, _node: &dyn UserDefinedLogicalNode) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Decode a user-defined logical node previously encoded by this codec
+    fn try_decode_logical(
+        &self,
+        _bytes: &[u8],
+        _inputs: &[LogicalPlan],
+    ) -> Result<Arc<dyn UserDefinedLogicalNode + Send + Sync>> {
+        Err(BallistaError::NotImplemented(format!(
+            "Codec '{}' does not decode logical nodes",
+            self.type_name()
+        )))
+    }
+
+    /// Planner creating the execution plans of the logical nodes decoded by this codec, which
+    /// the scheduler uses when it plans a query
+    fn extension_planner(&self) -> Option<Arc<dyn ExtensionPlanner + Send + Sync>> {
+        None
+    }
+
+    /// Encode an execution plan, returning `None` if it is not handled by this codec
+    fn try_encode_physical(&self, _plan: &dyn ExecutionPlan) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Decode an execution plan previously encoded by this codec
+    fn try_decode_physical(
+        &self,
+        _bytes: &[u8],
+        _inputs: &[Arc<dyn ExecutionPlan>],
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Err(BallistaError::NotImplemented(format!(
+            "Codec '{}' does not decode execution plans",
+            self.type_name()
+        )))
+    }
+
+    /// Encode a table provider, returning `None` if it is not handled by this codec
+    fn try_encode_table_provider(&self, _provider: &dyn TableProvider) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Decode a table provider previously encoded by this codec
+    fn try_decode_table_provider(
+        &self,
+        _bytes: &[u8],
+        _schema: SchemaRef,
+    ) -> Result<Arc<dyn TableProvider + Send + Sync>> {
+        Err(BallistaError::NotImplemented(format!(
+            "Codec '{}' does not decode table providers",
+            self.type_name()
+        )))
+    }
+}
+
+/// Register a codec for user-defined plan nodes, replacing any codec with the same type name
+pub fn register_extension_codec(codec: Arc<dyn BallistaCodec>) -> Result<()> {
+    let mut codecs = CODECS.write().map_err(|_| poisoned())?;
+    codecs.retain(|c| c.type_name() != codec.type_name());
+    codecs.push(codec);
+    Ok(())
+}
+
+/// Create a DataFusion configuration whose physical planner plans user-defined logical nodes
+/// with the extension planners of the registered codecs
+pub fn extension_config() -> Result<ExecutionConfig> {
+    let planners = codecs()?
+        .iter()
+        .filter_map(|codec| codec.extension_planner())
+        .collect();
+    Ok(ExecutionConfig::new().with_query_planner(Arc::new(ExtensionQueryPlanner { planners })))
+}
+
+/// Query planner using the extension planners of the registered codecs
+struct ExtensionQueryPlanner {
+    planners: Vec<Arc<dyn ExtensionPlanner + Send + Sync>>,
+}
+
+impl QueryPlanner for ExtensionQueryPlanner {
+    fn create_physical_plan(
+        &self,
+        logical_plan: &LogicalPlan,
+        ctx_state: &ExecutionContextState,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        DefaultPhysicalPlanner::with_extension_planners(self.planners.clone())
+            .create_physical_plan(logical_plan, ctx_state)
+    }
+}
+
+fn codecs() -> Result<RwLockReadGuard<'static, Vec<Arc<dyn BallistaCodec>>>> {
+    CODECS.read().map_err(|_| poisoned())
+}
+
+fn poisoned() -> BallistaError {
+    BallistaError::Internal("The extension codec registry is poisoned".to_owned())
+}
+
+/// Encode a user-defined logical node with the first registered codec that supports it,
+/// returning the type name of the codec along with the encoded bytes
+pub(crate) fn encode_logical(node: &dyn UserDefinedLogicalNode) -> Result<(String, Vec<u8>)> {
+    for codec in codecs()?.iter() {
+        if let Some(bytes) = codec.try_encode_logical(node)? {
+            return Ok((codec.type_name().to_owned(), bytes));
+        }
+    }
+    Err(BallistaError::NotImplemented(format!(
+        "No extension codec registered for logical node {:?}",
+        node
+    )))
+}
+
+pub(crate) fn decode_logical(
+    type_name: &str,
+    bytes: &[u8],
+    inputs: &[LogicalPlan],
+) -> Result<Arc<dyn UserDefinedLogicalNode + Send + Sync>> {
+    find_codec(type_name)?.try_decode_logical(bytes, inputs)
+}
+
+/// Encode an execution plan with the first registered codec that supports it, returning the
+/// type name of the codec along with the encoded bytes
+pub(crate) fn encode_physical(plan: &dyn ExecutionPlan) -> Result<(String, Vec<u8>)> {
+    for codec in codecs()?.iter() {
+        if let Some(bytes) = codec.try_encode_physical(plan)? {
+            return Ok((codec.type_name().to_owned(), bytes));
+        }
+    }
+    Err(BallistaError::General(format!(
+        "physical plan to_proto unsupported plan {:?}",
+        plan
+    )))
+}
+
+pub(crate) fn decode_physical(
+    type_name: &str,
+    bytes: &[u8],
+    inputs: &[Arc<dyn ExecutionPlan>],
+) -> Result<Arc<dyn ExecutionPlan>> {
+    find_codec(type_name)?.try_decode_physical(bytes, inputs)
+}
+
+/// Encode a table provider with the first registered codec that supports it, returning the
+/// type name of the codec along with the encoded bytes
+pub(crate) fn encode_table_provider(provider: &dyn TableProvider) -> Result<(String, Vec<u8>)> {
+    for codec in codecs()?.iter() {
+        if let Some(bytes) = codec.try_encode_table_provider(provider)? {
+            return Ok((codec.type_name().to_owned(), bytes));
+        }
+    }
+    Err(BallistaError::General(
+        "logical plan to_proto unsupported table provider".to_owned(),
+    ))
+}
+
+pub(crate) fn decode_table_provider(
+    type_name: &str,
+    bytes: &[u8],
+    schema: SchemaRef,
+) -> Result<Arc<dyn TableProvider + Send + Sync>> {
+    find_codec(type_name)?.try_decode_table_provider(bytes, schema)
+}
+
+fn find_codec(type_name: &str) -> Result<Arc<dyn BallistaCodec>> {
+    codecs()?
+        .iter()
+        .find(|c| c.type_name() == type_name)
+        .cloned()
+        .ok_or_else(|| {
+            BallistaError::General(format!(
+                "No extension codec registered with type name '{}'",
+                type_name
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::convert::TryInto;
+    use std::fmt;
+    use std::sync::Arc;
+
+    use std::time::Duration;
+
+    use super::{register_extension_codec, BallistaCodec};
+    use crate::error::{BallistaError, Result};
+    use crate::scheduler::state::StandaloneClient;
+    use crate::scheduler::{SchedulerServer, DEFAULT_SPLIT_SIZE};
+    use crate::serde::protobuf::scheduler_grpc_server::SchedulerGrpc;
+    use crate::serde::protobuf::{
+        self, execute_query_params::Query, job_status, ExecuteQueryParams, GetJobStatusParams,
+    };
+
+    use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+    use async_trait::async_trait;
+    use datafusion::datasource::datasource::Statistics;
+    use datafusion::datasource::TableProvider;
+    use datafusion::execution::context::ExecutionContextState;
+    use datafusion::logical_plan::{
+        DFSchemaRef, Expr, LogicalPlan, LogicalPlanBuilder, UserDefinedLogicalNode,
+    };
+    use datafusion::physical_plan::empty::EmptyExec;
+    use datafusion::physical_plan::planner::ExtensionPlanner;
+    use datafusion::physical_plan::{ExecutionPlan, Partitioning, SendableRecordBatchStream};
+    use tonic::Request;
+
+    /// Logical node keeping a percentage of the rows of its input
+    #[derive(Debug)]
+    struct SampleNode {
+        input: LogicalPlan,
+        percent: u32,
+    }
+
+    impl UserDefinedLogicalNode for SampleNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn inputs(&self) -> Vec<&LogicalPlan> {
+            vec![&self.input]
+        }
+
+        fn schema(&self) -> &DFSchemaRef {
+            self.input.schema()
+        }
+
+        fn expressions(&self) -> Vec<Expr> {
+            vec![]
+        }
+
+        fn fmt_for_explain(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "Sample: percent={}", self.percent)
+        }
+
+        fn from_template(
+            &self,
+            _exprs: &[Expr],
+            inputs: &[LogicalPlan],
+        ) -> Arc<dyn UserDefinedLogicalNode + Send + Sync> {
+            Arc::new(SampleNode {
+                input: inputs[0].clone(),
+                percent: self.percent,
+            })
+        }
+    }
+
+    #[derive(Debug)]
+    struct SampleExec {
+        input: Arc<dyn ExecutionPlan>,
+        percent: u32,
+    }
+
+    #[async_trait]
+    impl ExecutionPlan for SampleExec {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> SchemaRef {
+            self.input.schema()
+        }
+
+        fn output_partitioning(&self) -> Partitioning {
+            self.input.output_partitioning()
+        }
+
+        fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+            vec![self.input.clone()]
+        }
+
+        fn with_new_children(
+            &self,
+            children: Vec<Arc<dyn ExecutionPlan>>,
+        ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+            Ok(Arc::new(SampleExec {
+                input: children[0].clone(),
+                percent: self.percent,
+            }))
+        }
+
+        async fn execute(
+            &self,
+            partition: usize,
+        ) -> datafusion::error::Result<SendableRecordBatchStream> {
+            self.input.execute(partition).await
+        }
+    }
+
+    struct SamplePlanner;
+
+    impl ExtensionPlanner for SamplePlanner {
+        fn plan_extension(
+            &self,
+            node: &dyn UserDefinedLogicalNode,
+            inputs: &[Arc<dyn ExecutionPlan>],
+            _ctx_state: &ExecutionContextState,
+        ) -> datafusion::error::Result<Option<Arc<dyn ExecutionPlan>>> {
+            Ok(node.as_any().downcast_ref::<SampleNode>().map(|node| {
+                let exec: Arc<dyn ExecutionPlan> = Arc::new(SampleExec {
+                    input: inputs[0].clone(),
+                    percent: node.percent,
+                });
+                exec
+            }))
+        }
+    }
+
+    /// Table generating a number of rows, which has no serialization of its own
+    struct SampleTable {
+        schema: SchemaRef,
+        num_rows: u32,
+    }
+
+    impl TableProvider for SampleTable {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> SchemaRef {
+            self.schema.clone()
+        }
+
+        fn scan(
+            &self,
+            _projection: &Option<Vec<usize>>,
+            _batch_size: usize,
+            _filters: &[Expr],
+        ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+            Ok(Arc::new(EmptyExec::new(false, self.schema.clone())))
+        }
+
+        fn statistics(&self) -> Statistics {
+            Statistics {
+                num_rows: Some(self.num_rows as usize),
+                total_byte_size: None,
+                column_statistics: None,
+            }
+        }
+    }
+
+    struct SampleCodec;
+
+    fn decode_u32(bytes: &[u8]) -> Result<u32> {
+        if bytes.len() != 4 {
+            return Err(BallistaError::General("Invalid sample encoding".to_owned()));
+        }
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    impl BallistaCodec for SampleCodec {
+        fn type_name(&self) -> &str {
+            "sample"
+        }
+
+        fn try_encode_logical(&self, node: &dyn UserDefinedLogicalNode) -> Result<Option<Vec<u8>>> {
+            Ok(node
+                .as_any()
+                .downcast_ref::<SampleNode>()
+                .map(|node| node.percent.to_be_bytes().to_vec()))
+        }
+
+        fn try_decode_logical(
+            &self,
+            bytes: &[u8],
+            inputs: &[LogicalPlan],
+        ) -> Result<Arc<dyn UserDefinedLogicalNode + Send + Sync>> {
+            Ok(Arc::new(SampleNode {
+                input: inputs[0].clone(),
+                percent: decode_u32(bytes)?,
+            }))
+        }
+
+        fn extension_planner(&self) -> Option<Arc<dyn ExtensionPlanner + Send + Sync>> {
+            Some(Arc::new(SamplePlanner))
+        }
+
+        fn try_encode_physical(&self, plan: &dyn ExecutionPlan) -> Result<Option<Vec<u8>>> {
+            Ok(plan
+                .as_any()
+                .downcast_ref::<SampleExec>()
+                .map(|exec| exec.percent.to_be_bytes().to_vec()))
+        }
+
+        fn try_decode_physical(
+            &self,
+            bytes: &[u8],
+            inputs: &[Arc<dyn ExecutionPlan>],
+        ) -> Result<Arc<dyn ExecutionPlan>> {
+            Ok(Arc::new(SampleExec {
+                input: inputs[0].clone(),
+                percent: decode_u32(bytes)?,
+            }))
+        }
+
+        fn try_encode_table_provider(
+            &self,
+            provider: &dyn TableProvider,
+        ) -> Result<Option<Vec<u8>>> {
+            Ok(provider
+                .as_any()
+                .downcast_ref::<SampleTable>()
+                .map(|table| table.num_rows.to_be_bytes().to_vec()))
+        }
+
+        fn try_decode_table_provider(
+            &self,
+            bytes: &[u8],
+            schema: SchemaRef,
+        ) -> Result<Arc<dyn TableProvider + Send + Sync>> {
+            Ok(Arc::new(SampleTable {
+                schema,
+                num_rows: decode_u32(bytes)?,
+            }))
+        }
+    }
+
+    #[test]
+    fn roundtrip_logical_extension() -> Result<()> {
+        register_extension_codec(Arc::new(SampleCodec))?;
+        let plan = LogicalPlan::Extension {
+            node: Arc::new(SampleNode {
+                input: LogicalPlanBuilder::empty(false).build()?,
+                percent: 10,
+            }),
+        };
+        let proto: protobuf::LogicalPlanNode = (&plan).try_into()?;
+        let round_trip: LogicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", round_trip));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_physical_extension() -> Result<()> {
+        register_extension_codec(Arc::new(SampleCodec))?;
+        let plan: Arc<dyn ExecutionPlan> = Arc::new(SampleExec {
+            input: Arc::new(EmptyExec::new(false, Arc::new(Schema::empty()))),
+            percent: 10,
+        });
+        let proto: protobuf::PhysicalPlanNode = plan.clone().try_into()?;
+        let round_trip: Arc<dyn ExecutionPlan> = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", round_trip));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_provider_scan() -> Result<()> {
+        register_extension_codec(Arc::new(SampleCodec))?;
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let table = SampleTable {
+            schema,
+            num_rows: 42,
+        };
+        let plan = LogicalPlanBuilder::scan("sample", Arc::new(table), None)?.build()?;
+
+        let proto: protobuf::LogicalPlanNode = (&plan).try_into()?;
+        match &proto.logical_plan_type {
+            Some(protobuf::logical_plan_node::LogicalPlanType::ProviderScan(scan)) => {
+                assert_eq!("sample", scan.type_name)
+            }
+            other => panic!("Expected a provider scan, got {:?}", other),
+        }
+        let round_trip: LogicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", round_trip));
+        match round_trip {
+            LogicalPlan::TableScan { source, .. } => {
+                let table = source.as_any().downcast_ref::<SampleTable>().unwrap();
+                assert_eq!(42, table.num_rows);
+            }
+            other => panic!("Expected a table scan, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plan_logical_extension_in_scheduler() -> Result<()> {
+        register_extension_codec(Arc::new(SampleCodec))?;
+        let plan = LogicalPlan::Extension {
+            node: Arc::new(SampleNode {
+                input: LogicalPlanBuilder::empty(false).build()?,
+                percent: 10,
+            }),
+        };
+        let scheduler = SchedulerServer::new(
+            StandaloneClient::try_new_temporary()?,
+            "default".to_owned(),
+            1,
+            DEFAULT_SPLIT_SIZE,
+        );
+        let job_id = scheduler
+            .execute_query(Request::new(ExecuteQueryParams {
+                query: Some(Query::LogicalPlan((&plan).try_into()?)),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .job_id;
+
+        // the job is running once its stages are planned, or fails if the scheduler cannot
+        // create the execution plan of the extension node
+        loop {
+            let status = scheduler
+                .get_job_status(Request::new(GetJobStatusParams {
+                    job_id: job_id.clone(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .status
+                .unwrap();
+            match status.status {
+                Some(job_status::Status::Queued(_)) => {
+                    tokio::time::sleep(Duration::from_millis(10)).await
+                }
+                other => {
+                    assert!(
+                        matches!(other, Some(job_status::Status::Running(_))),
+                        "unexpected job status {:?}",
+                        other
+                    );
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decode_unregistered_extension() {
+        let proto = protobuf::PhysicalPlanNode {
+            physical_plan_type: Some(protobuf::physical_plan_node::PhysicalPlanType::Extension(
+                protobuf::PhysicalExtensionNode {
+                    type_name: "unregistered".to_owned(),
+                    node: vec![],
+                    inputs: vec![],
+                },
+            )),
+        };
+        let result: Result<Arc<dyn ExecutionPlan>> = (&proto).try_into();
+        assert!(result.is_err());
+    }
+}
@@ -16,14 +16,21 @@
 
 use std::{
     convert::{From, TryInto},
+    sync::Arc,
     unimplemented,
 };
 
 use crate::error::BallistaError;
-use crate::serde::{proto_error, protobuf};
+use crate::scheduler::execution_plans::{
+    JsonReadOptions, JsonTable, ParquetScanTable, PartitionedTable, UploadedTable,
+    DEFAULT_PARQUET_CONCURRENCY,
+};
+use crate::serde::{decode_batches, extension, parse_delimiter, proto_error, protobuf};
+use crate::udf;
 use crate::{convert_box_required, convert_required};
 
 use arrow::datatypes::{DataType, Field, Schema};
+use datafusion::datasource::MemTable;
 use datafusion::logical_plan::{
     abs, acos, asin, atan, ceil, cos, exp, floor, log10, log2, round, signum, sin, sqrt, tan,
     trunc, Expr, JoinType, LogicalPlan, LogicalPlanBuilder, Operator,
@@ -94,7 +101,7 @@ impl TryInto<LogicalPlan> for &protobuf::LogicalPlanNode {
                 let schema: Schema = convert_required!(scan.schema)?;
                 let options = CsvReadOptions::new()
                     .schema(&schema)
-                    .delimiter(scan.delimiter.as_bytes()[0])
+                    .delimiter(parse_delimiter(&scan.delimiter)?)
                     .file_extension(&scan.file_extension)
                     .has_header(scan.has_header);
 
@@ -133,7 +140,95 @@ impl TryInto<LogicalPlan> for &protobuf::LogicalPlanNode {
                         Some(r?)
                     }
                 };
-                LogicalPlanBuilder::scan_parquet(&scan.path, projection, 24)? //TODO concurrency
+                let provider = ParquetScanTable::try_new(&scan.path, DEFAULT_PARQUET_CONCURRENCY)?;
+                LogicalPlanBuilder::scan(&scan.table_name, Arc::new(provider), projection)?
+                    .build()
+                    .map_err(|e| e.into())
+            }
+            LogicalPlanType::JsonScan(scan) => {
+                let schema: Schema = convert_required!(scan.schema)?;
+                let projection = parse_projection(&schema, &scan.projection)?;
+                let options = JsonReadOptions::new()
+                    .schema(&schema)
+                    .file_extension(&scan.file_extension);
+                let provider = JsonTable::try_new(&scan.path, options)?;
+                LogicalPlanBuilder::scan(&scan.table_name, Arc::new(provider), projection)?
+                    .build()
+                    .map_err(|e| e.into())
+            }
+            LogicalPlanType::UploadedScan(scan) => {
+                let schema: Schema = convert_required!(scan.schema)?;
+                let projection = parse_projection(&schema, &scan.projection)?;
+                let partitions = scan
+                    .partition
+                    .iter()
+                    .map(|location| location.clone().try_into())
+                    .collect::<Result<Vec<_>, BallistaError>>()?;
+                let provider = UploadedTable::new(Arc::new(schema), partitions);
+                LogicalPlanBuilder::scan(&scan.table_name, Arc::new(provider), projection)?
+                    .build()
+                    .map_err(|e| e.into())
+            }
+            LogicalPlanType::PartitionedScan(scan) => {
+                let schema: Schema = convert_required!(scan.schema)?;
+                let projection = parse_projection(&schema, &scan.projection)?;
+                let file_type: protobuf::FileType = scan.file_type.try_into()?;
+                // the directories are listed again, so that the files that were added since the
+                // plan was created are also scanned
+                let provider = match file_type {
+                    protobuf::FileType::Parquet => PartitionedTable::try_new_parquet(
+                        &scan.path,
+                        &scan.partition_columns,
+                        DEFAULT_PARQUET_CONCURRENCY,
+                    )?,
+                    protobuf::FileType::Csv => {
+                        let file_schema = Schema::new(
+                            schema
+                                .fields()
+                                .iter()
+                                .filter(|field| !scan.partition_columns.contains(field.name()))
+                                .cloned()
+                                .collect(),
+                        );
+                        let options = CsvReadOptions::new()
+                            .schema(&file_schema)
+                            .delimiter(parse_delimiter(&scan.delimiter)?)
+                            .file_extension(&scan.file_extension)
+                            .has_header(scan.has_header);
+                        PartitionedTable::try_new_csv(&scan.path, &scan.partition_columns, options)?
+                    }
+                    protobuf::FileType::NdJson => {
+                        return Err(proto_error(
+                            "Partitioned NdJson tables are not supported".to_owned(),
+                        ))
+                    }
+                };
+                LogicalPlanBuilder::scan(&scan.table_name, Arc::new(provider), projection)?
+                    .build()
+                    .map_err(|e| e.into())
+            }
+            LogicalPlanType::MemoryScan(scan) => {
+                let schema: Schema = convert_required!(scan.schema)?;
+                let projection = parse_projection(&schema, &scan.projection)?;
+                let partitions = scan
+                    .partitions
+                    .iter()
+                    .map(|partition| decode_batches(partition))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let provider = MemTable::try_new(Arc::new(schema), partitions)?;
+                LogicalPlanBuilder::scan(&scan.table_name, Arc::new(provider), projection)?
+                    .build()
+                    .map_err(|e| e.into())
+            }
+            LogicalPlanType::ProviderScan(scan) => {
+                let schema: Schema = convert_required!(scan.schema)?;
+                let projection = parse_projection(&schema, &scan.projection)?;
+                let provider = extension::decode_table_provider(
+                    &scan.type_name,
+                    &scan.provider,
+                    Arc::new(schema),
+                )?;
+                LogicalPlanBuilder::scan(&scan.table_name, provider, projection)?
                     .build()
                     .map_err(|e| e.into())
             }
@@ -209,6 +304,15 @@ impl TryInto<LogicalPlan> for &protobuf::LogicalPlanNode {
                     .build()
                     .map_err(|e| e.into())
             }
+            LogicalPlanType::Extension(ext) => {
+                let inputs = ext
+                    .inputs
+                    .iter()
+                    .map(|input| input.try_into())
+                    .collect::<Result<Vec<LogicalPlan>, _>>()?;
+                let node = extension::decode_logical(&ext.type_name, &ext.node, &inputs)?;
+                Ok(LogicalPlan::Extension { node })
+            }
             LogicalPlanType::Limit(limit) => {
                 let input: LogicalPlan = convert_box_required!(limit.input)?;
                 LogicalPlanBuilder::from(&input)
@@ -928,6 +1032,27 @@ impl TryInto<Expr> for &protobuf::LogicalExprNode {
                     )),
                 }
             }
+            ExprType::ScalarUdfExpr(expr) => {
+                let fun = udf::get_udf(&expr.fun_name).ok_or_else(|| {
+                    proto_error(format!("Scalar UDF '{}' is not registered", expr.fun_name))
+                })?;
+                Ok(Expr::ScalarUDF {
+                    fun,
+                    args: parse_exprs(&expr.args)?,
+                })
+            }
+            ExprType::AggregateUdfExpr(expr) => {
+                let fun = udf::get_udaf(&expr.fun_name).ok_or_else(|| {
+                    proto_error(format!(
+                        "Aggregate UDF '{}' is not registered",
+                        expr.fun_name
+                    ))
+                })?;
+                Ok(Expr::AggregateUDF {
+                    fun,
+                    args: parse_exprs(&expr.args)?,
+                })
+            }
         }
     }
 }
@@ -1053,3 +1178,23 @@ fn parse_optional_expr(
         None => Ok(None),
     }
 }
+
+fn parse_exprs(exprs: &[protobuf::LogicalExprNode]) -> Result<Vec<Expr>, BallistaError> {
+    exprs.iter().map(|expr| expr.try_into()).collect()
+}
+
+fn parse_projection(
+    schema: &Schema,
+    projection: &Option<protobuf::ProjectionColumns>,
+) -> Result<Option<Vec<usize>>, BallistaError> {
+    match projection {
+        Some(columns) => Ok(Some(
+            columns
+                .columns
+                .iter()
+                .map(|name| schema.index_of(name))
+                .collect::<Result<Vec<usize>, _>>()?,
+        )),
+        None => Ok(None),
+    }
+}
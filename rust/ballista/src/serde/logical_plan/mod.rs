@@ -917,4 +917,224 @@ mod roundtrip_tests {
 
         Ok(())
     }
+
+    #[test]
+    fn roundtrip_scalar_udf() -> Result<()> {
+        let udf = crate::test_utils::test_identity_udf();
+        crate::udf::register_udf(udf.clone());
+        let test_expr = Expr::ScalarUDF {
+            fun: std::sync::Arc::new(udf),
+            args: vec![col("col")],
+        };
+        roundtrip_test!(test_expr, protobuf::LogicalExprNode, Expr);
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_aggregate_udf() -> Result<()> {
+        let udaf = crate::test_utils::test_avg_udaf();
+        crate::udf::register_udaf(udaf.clone());
+        let test_expr = Expr::AggregateUDF {
+            fun: std::sync::Arc::new(udaf),
+            args: vec![col("col")],
+        };
+        roundtrip_test!(test_expr, protobuf::LogicalExprNode, Expr);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unregistered_udf() -> Result<()> {
+        let proto = protobuf::LogicalExprNode {
+            expr_type: Some(protobuf::logical_expr_node::ExprType::ScalarUdfExpr(
+                protobuf::ScalarUdfExprNode {
+                    fun_name: "unregistered".to_owned(),
+                    args: vec![],
+                },
+            )),
+        };
+        let result: Result<Expr> = (&proto).try_into();
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_memory_scan() -> Result<()> {
+        use crate::serde::collect_partitions;
+        use arrow::array::Int32Array;
+        use arrow::record_batch::RecordBatch;
+        use datafusion::datasource::{MemTable, TableProvider};
+        use std::sync::Arc;
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Int32, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2, 3])),
+                Arc::new(Int32Array::from(vec![4, 5, 6])),
+            ],
+        )?;
+        let table = MemTable::try_new(schema, vec![vec![batch.clone()], vec![]])?;
+        let plan = LogicalPlanBuilder::scan("mem", Arc::new(table), Some(vec![1]))?.build()?;
+
+        roundtrip_test!(plan);
+
+        let proto: protobuf::LogicalPlanNode = (&plan).try_into()?;
+        let round_trip: LogicalPlan = (&proto).try_into()?;
+        let source = match round_trip {
+            LogicalPlan::TableScan { source, .. } => source,
+            _ => panic!("Expected a table scan"),
+        };
+        let exec = source.scan(&None, 1024, &[])?;
+        let partitions = collect_partitions(exec.as_ref())?;
+        assert_eq!(2, partitions.len());
+        assert_eq!(format!("{:?}", vec![batch]), format!("{:?}", partitions[0]));
+        assert!(partitions[1].is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn scheduler_table_scan() -> Result<()> {
+        use crate::context::SchedulerTable;
+        use datafusion::datasource::datasource::Statistics;
+        use protobuf::logical_plan_node::LogicalPlanType;
+        use std::sync::Arc;
+
+        // the path does not exist locally because it is resolved by the scheduler
+        let table = SchedulerTable {
+            path: "unresolved/lineitem".to_owned(),
+            file_type: protobuf::FileType::Csv,
+            has_header: false,
+            delimiter: b'|',
+            file_extension: ".tbl".to_owned(),
+            partition_columns: vec![],
+            schema: Arc::new(Schema::new(vec![
+                Field::new("a", DataType::Int32, false),
+                Field::new("b", DataType::Utf8, false),
+            ])),
+            statistics: Statistics {
+                num_rows: None,
+                total_byte_size: None,
+                column_statistics: None,
+            },
+        };
+        let plan = LogicalPlanBuilder::scan("lineitem", Arc::new(table), Some(vec![1]))?.build()?;
+
+        let proto: protobuf::LogicalPlanNode = (&plan).try_into()?;
+        match proto.logical_plan_type {
+            Some(LogicalPlanType::CsvScan(scan)) => {
+                assert_eq!("unresolved/lineitem", scan.path);
+                assert_eq!("|", scan.delimiter);
+                assert_eq!(".tbl", scan.file_extension);
+                assert_eq!(vec!["b".to_owned()], scan.projection.unwrap().columns);
+            }
+            other => panic!("Expected a CSV scan, got {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn uploaded_table_scan() -> Result<()> {
+        use crate::scheduler::execution_plans::UploadedTable;
+        use crate::scheduler::planner::PartitionLocation;
+        use crate::serde::scheduler::{ExecutorMeta, PartitionId};
+        use std::sync::Arc;
+        use uuid::Uuid;
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Utf8, false),
+        ]));
+        let table_uuid = Uuid::new_v4();
+        let partitions = (0..2)
+            .map(|partition| PartitionLocation {
+                partition_id: PartitionId::new(table_uuid, 0, partition),
+                executor_meta: ExecutorMeta {
+                    id: format!("executor-{}", partition),
+                    host: "localhost".to_owned(),
+                    port: 50051 + partition as u16,
+                    task_slots: 4,
+                },
+            })
+            .collect();
+        let table = UploadedTable::new(schema, partitions);
+        let plan = LogicalPlanBuilder::scan("uploaded", Arc::new(table), Some(vec![1]))?.build()?;
+
+        let proto: protobuf::LogicalPlanNode = (&plan).try_into()?;
+        let round_trip: LogicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", round_trip));
+        match round_trip {
+            LogicalPlan::TableScan { source, .. } => {
+                let table = source.as_any().downcast_ref::<UploadedTable>().unwrap();
+                assert_eq!(2, table.partitions().len());
+                assert_eq!(
+                    PartitionId::new(table_uuid, 0, 1),
+                    table.partitions()[1].partition_id
+                );
+                assert_eq!(50052, table.partitions()[1].executor_meta.port);
+            }
+            other => panic!("Expected a table scan, got {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn partitioned_scan_with_empty_delimiter() {
+        use protobuf::logical_plan_node::LogicalPlanType;
+
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("region", DataType::Utf8, false),
+        ]);
+        let proto = protobuf::LogicalPlanNode {
+            logical_plan_type: Some(LogicalPlanType::PartitionedScan(
+                protobuf::PartitionedTableScanNode {
+                    table_name: "nation".to_owned(),
+                    path: "unresolved/nation".to_owned(),
+                    file_type: protobuf::FileType::Csv.into(),
+                    partition_columns: vec!["region".to_owned()],
+                    has_header: false,
+                    delimiter: String::new(),
+                    file_extension: ".tbl".to_owned(),
+                    projection: None,
+                    schema: Some((&schema).into()),
+                    filters: vec![],
+                },
+            )),
+        };
+        let result: std::result::Result<LogicalPlan, BallistaError> = (&proto).try_into();
+        match result {
+            Err(BallistaError::General(message)) => assert!(message.contains("delimiter")),
+            other => panic!("Expected an error about the delimiter, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn roundtrip_empty_memory_scan() -> Result<()> {
+        use datafusion::datasource::MemTable;
+        use std::sync::Arc;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let table = MemTable::try_new(schema, vec![])?;
+        let plan = LogicalPlanBuilder::scan("empty", Arc::new(table), None)?.build()?;
+
+        roundtrip_test!(plan);
+
+        let proto: protobuf::LogicalPlanNode = (&plan).try_into()?;
+        match proto.logical_plan_type {
+            Some(protobuf::logical_plan_node::LogicalPlanType::MemoryScan(scan)) => {
+                assert!(scan.partitions.is_empty())
+            }
+            other => panic!("Expected a memory scan, got {:?}", other),
+        }
+        Ok(())
+    }
 }
@@ -21,12 +21,15 @@ use std::{
     convert::{TryFrom, TryInto},
 };
 
-use crate::context::DFTableAdapter;
-use crate::serde::{protobuf, BallistaError};
+use crate::context::{DFTableAdapter, SchedulerTable};
+use crate::scheduler::execution_plans::{
+    JsonTable, ParquetScanTable, PartitionedTable, UploadedTable,
+};
+use crate::serde::{collect_partitions, encode_batches, extension, protobuf, BallistaError};
 
 use arrow::datatypes::{DataType, Schema};
-use datafusion::datasource::CsvFile;
-use datafusion::logical_plan::{Expr, JoinType, LogicalPlan};
+use datafusion::datasource::{CsvFile, MemTable, TableProvider};
+use datafusion::logical_plan::{col, Expr, JoinType, LogicalPlan, LogicalPlanBuilder};
 use datafusion::physical_plan::aggregates::AggregateFunction;
 use datafusion::{datasource::parquet::ParquetTable, logical_plan::exprlist_to_fields};
 use protobuf::{
@@ -659,18 +662,30 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
             } => {
                 let schema = source.schema();
 
-                // unwrap the DFTableAdapter to get to the real TableProvider
+                // unwrap the DFTableAdapter to get to the real TableProvider, or to the plan of
+                // the registered table when it is not a plain table scan
                 let source = if let Some(adapter) = source.as_any().downcast_ref::<DFTableAdapter>()
                 {
                     match &adapter.logical_plan {
-                        LogicalPlan::TableScan { source, .. } => Ok(source.as_any()),
-                        _ => Err(BallistaError::General(
-                            "Invalid LogicalPlan::TableScan".to_owned(),
-                        )),
+                        LogicalPlan::TableScan { source, .. } => source,
+                        plan => {
+                            let plan = match projection {
+                                Some(columns) => LogicalPlanBuilder::from(plan)
+                                    .project(
+                                        &columns
+                                            .iter()
+                                            .map(|i| col(schema.field(*i).name()))
+                                            .collect::<Vec<_>>(),
+                                    )?
+                                    .build()?,
+                                None => plan.clone(),
+                            };
+                            return (&plan).try_into();
+                        }
                     }
                 } else {
-                    Ok(source.as_any())
-                }?;
+                    source
+                };
 
                 let projection = match projection {
                     None => None,
@@ -691,41 +706,185 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                     .map(|filter| filter.try_into())
                     .collect::<Result<Vec<_>, _>>()?;
 
-                if let Some(parquet) = source.downcast_ref::<ParquetTable>() {
+                // tables resolved by the scheduler are sent as scans of their unresolved paths
+                let scheduler_table = source.as_any().downcast_ref::<SchedulerTable>();
+                let partitioned =
+                    if let Some(table) = source.as_any().downcast_ref::<PartitionedTable>() {
+                        Some((
+                            table.path(),
+                            table.file_type(),
+                            table.partition_columns(),
+                            table.has_header(),
+                            table.delimiter(),
+                            table.file_extension(),
+                        ))
+                    } else {
+                        scheduler_table
+                            .filter(|table| !table.partition_columns.is_empty())
+                            .map(|table| {
+                                (
+                                    table.path.as_str(),
+                                    table.file_type,
+                                    table.partition_columns.as_slice(),
+                                    table.has_header,
+                                    table.delimiter,
+                                    table.file_extension.as_str(),
+                                )
+                            })
+                    };
+                if let Some((path, file_type, partition_columns, has_header, delimiter, ext)) =
+                    partitioned
+                {
+                    let delimiter = [delimiter];
+                    let delimiter = std::str::from_utf8(&delimiter)
+                        .map_err(|_| BallistaError::General("Invalid CSV delimiter".to_owned()))?;
+                    return Ok(protobuf::LogicalPlanNode {
+                        logical_plan_type: Some(LogicalPlanType::PartitionedScan(
+                            protobuf::PartitionedTableScanNode {
+                                table_name: table_name.to_owned(),
+                                path: path.to_owned(),
+                                file_type: file_type.into(),
+                                partition_columns: partition_columns.to_vec(),
+                                has_header,
+                                delimiter: delimiter.to_string(),
+                                file_extension: ext.to_string(),
+                                projection,
+                                schema: Some(schema),
+                                filters,
+                            },
+                        )),
+                    });
+                }
+
+                let parquet_path = if let Some(parquet) =
+                    source.as_any().downcast_ref::<ParquetTable>()
+                {
+                    Some(parquet.path())
+                } else if let Some(parquet) = source.as_any().downcast_ref::<ParquetScanTable>() {
+                    Some(parquet.path())
+                } else {
+                    scheduler_table
+                        .filter(|table| table.file_type == protobuf::FileType::Parquet)
+                        .map(|table| table.path.as_str())
+                };
+                let csv_options = if let Some(csv) = source.as_any().downcast_ref::<CsvFile>() {
+                    Some((
+                        csv.path(),
+                        csv.has_header(),
+                        csv.delimiter(),
+                        csv.file_extension(),
+                    ))
+                } else {
+                    scheduler_table
+                        .filter(|table| table.file_type == protobuf::FileType::Csv)
+                        .map(|table| {
+                            (
+                                table.path.as_str(),
+                                table.has_header,
+                                table.delimiter,
+                                table.file_extension.as_str(),
+                            )
+                        })
+                };
+                let json_options = if let Some(json) = source.as_any().downcast_ref::<JsonTable>() {
+                    Some((json.path(), json.file_extension()))
+                } else {
+                    scheduler_table
+                        .filter(|table| table.file_type == protobuf::FileType::NdJson)
+                        .map(|table| (table.path.as_str(), table.file_extension.as_str()))
+                };
+                if let Some(path) = parquet_path {
                     Ok(protobuf::LogicalPlanNode {
                         logical_plan_type: Some(LogicalPlanType::ParquetScan(
                             protobuf::ParquetTableScanNode {
                                 table_name: table_name.to_owned(),
-                                path: parquet.path().to_owned(),
+                                path: path.to_owned(),
                                 projection,
                                 schema: Some(schema),
                                 filters,
                             },
                         )),
                     })
-                } else if let Some(csv) = source.downcast_ref::<CsvFile>() {
-                    let delimiter = [csv.delimiter()];
+                } else if let Some((path, has_header, delimiter, file_extension)) = csv_options {
+                    let delimiter = [delimiter];
                     let delimiter = std::str::from_utf8(&delimiter)
                         .map_err(|_| BallistaError::General("Invalid CSV delimiter".to_owned()))?;
                     Ok(protobuf::LogicalPlanNode {
                         logical_plan_type: Some(LogicalPlanType::CsvScan(
                             protobuf::CsvTableScanNode {
                                 table_name: table_name.to_owned(),
-                                path: csv.path().to_owned(),
+                                path: path.to_owned(),
                                 projection,
                                 schema: Some(schema),
-                                has_header: csv.has_header(),
+                                has_header,
                                 delimiter: delimiter.to_string(),
-                                file_extension: csv.file_extension().to_string(),
+                                file_extension: file_extension.to_string(),
+                                filters,
+                            },
+                        )),
+                    })
+                } else if let Some((path, file_extension)) = json_options {
+                    Ok(protobuf::LogicalPlanNode {
+                        logical_plan_type: Some(LogicalPlanType::JsonScan(
+                            protobuf::JsonTableScanNode {
+                                table_name: table_name.to_owned(),
+                                path: path.to_owned(),
+                                file_extension: file_extension.to_owned(),
+                                projection,
+                                schema: Some(schema),
+                                filters,
+                            },
+                        )),
+                    })
+                } else if let Some(table) = source.as_any().downcast_ref::<UploadedTable>() {
+                    let partition = table
+                        .partitions()
+                        .iter()
+                        .map(|location| location.clone().try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?;
+                    Ok(protobuf::LogicalPlanNode {
+                        logical_plan_type: Some(LogicalPlanType::UploadedScan(
+                            protobuf::UploadedTableScanNode {
+                                table_name: table_name.to_owned(),
+                                partition,
+                                projection,
+                                schema: Some(schema),
+                                filters,
+                            },
+                        )),
+                    })
+                } else if let Some(mem) = source.as_any().downcast_ref::<MemTable>() {
+                    // the batch size is not used when scanning a MemTable
+                    let exec = mem.scan(&None, 32768, &[])?;
+                    let partitions = collect_partitions(exec.as_ref())?
+                        .iter()
+                        .map(|batches| encode_batches(mem.schema().as_ref(), batches))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok(protobuf::LogicalPlanNode {
+                        logical_plan_type: Some(LogicalPlanType::MemoryScan(
+                            protobuf::MemoryTableScanNode {
+                                table_name: table_name.to_owned(),
+                                partitions,
+                                projection,
+                                schema: Some(schema),
                                 filters,
                             },
                         )),
                     })
                 } else {
-                    Err(BallistaError::General(format!(
-                        "logical plan to_proto unsupported table provider {:?}",
-                        source
-                    )))
+                    let (type_name, provider) = extension::encode_table_provider(source.as_ref())?;
+                    Ok(protobuf::LogicalPlanNode {
+                        logical_plan_type: Some(LogicalPlanType::ProviderScan(
+                            protobuf::ProviderTableScanNode {
+                                table_name: table_name.to_owned(),
+                                type_name,
+                                provider,
+                                projection,
+                                schema: Some(schema),
+                                filters,
+                            },
+                        )),
+                    })
                 }
             }
             LogicalPlan::Projection { expr, input, .. } => Ok(protobuf::LogicalPlanNode {
@@ -914,11 +1073,23 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                     ))),
                 })
             }
-            LogicalPlan::Extension { .. } => unimplemented!(),
-            // _ => Err(BallistaError::General(format!(
-            //     "logical plan to_proto {:?}",
-            //     self
-            // ))),
+            LogicalPlan::Extension { node } => {
+                let (type_name, bytes) = extension::encode_logical(node.as_ref())?;
+                let inputs = node
+                    .inputs()
+                    .into_iter()
+                    .map(|input| input.try_into())
+                    .collect::<Result<Vec<_>, BallistaError>>()?;
+                Ok(protobuf::LogicalPlanNode {
+                    logical_plan_type: Some(LogicalPlanType::Extension(
+                        protobuf::LogicalExtensionNode {
+                            type_name,
+                            node: bytes,
+                            inputs,
+                        },
+                    )),
+                })
+            }
         }
     }
 }
@@ -1010,8 +1181,24 @@ impl TryInto<protobuf::LogicalExprNode> for &Expr {
                     )),
                 })
             }
-            Expr::ScalarUDF { .. } => unimplemented!(),
-            Expr::AggregateUDF { .. } => unimplemented!(),
+            Expr::ScalarUDF { ref fun, ref args } => Ok(protobuf::LogicalExprNode {
+                expr_type: Some(ExprType::ScalarUdfExpr(protobuf::ScalarUdfExprNode {
+                    fun_name: fun.name.clone(),
+                    args: args
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                })),
+            }),
+            Expr::AggregateUDF { ref fun, ref args } => Ok(protobuf::LogicalExprNode {
+                expr_type: Some(ExprType::AggregateUdfExpr(protobuf::AggregateUdfExprNode {
+                    fun_name: fun.name.clone(),
+                    args: args
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                })),
+            }),
             Expr::Not(expr) => {
                 let expr = Box::new(protobuf::Not {
                     expr: Some(Box::new(expr.as_ref().try_into()?)),
@@ -19,6 +19,12 @@ use std::{convert::TryInto, io::Cursor};
 
 use crate::{error::BallistaError, serde::scheduler::Action as BallistaAction};
 
+use arrow::datatypes::Schema;
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use datafusion::physical_plan::{common, ExecutionPlan};
+use futures::FutureExt;
 use prost::Message;
 
 // include the generated protobuf source as a submodule
@@ -27,6 +33,7 @@ pub mod protobuf {
     include!(concat!(env!("OUT_DIR"), "/ballista.protobuf.rs"));
 }
 
+pub mod extension;
 pub mod logical_plan;
 pub mod physical_plan;
 pub mod scheduler;
@@ -39,10 +46,75 @@ pub(crate) fn decode_protobuf(bytes: &[u8]) -> Result<BallistaAction, BallistaEr
         .and_then(|node| node.try_into())
 }
 
+pub(crate) fn encode_protobuf(action: &BallistaAction) -> Result<Vec<u8>, BallistaError> {
+    let action: protobuf::Action = action.clone().try_into()?;
+    let mut buf: Vec<u8> = Vec::with_capacity(action.encoded_len());
+    action
+        .encode(&mut buf)
+        .map_err(|e| BallistaError::Internal(format!("{:?}", e)))?;
+    Ok(buf)
+}
+
 pub(crate) fn proto_error<S: Into<String>>(message: S) -> BallistaError {
     BallistaError::General(message.into())
 }
 
+/// Get the single-byte CSV delimiter of a serialized scan
+pub(crate) fn parse_delimiter(delimiter: &str) -> Result<u8, BallistaError> {
+    delimiter
+        .as_bytes()
+        .first()
+        .cloned()
+        .ok_or_else(|| proto_error("Protobuf deserialization error: empty CSV delimiter"))
+}
+
+/// Encode record batches as an Arrow IPC stream
+pub(crate) fn encode_batches(
+    schema: &Schema,
+    batches: &[RecordBatch],
+) -> Result<Vec<u8>, BallistaError> {
+    let mut bytes = vec![];
+    {
+        let mut writer = StreamWriter::try_new(&mut bytes, schema)?;
+        for batch in batches {
+            writer.write(batch)?;
+        }
+        writer.finish()?;
+    }
+    Ok(bytes)
+}
+
+/// Decode record batches from an Arrow IPC stream
+pub(crate) fn decode_batches(bytes: &[u8]) -> Result<Vec<RecordBatch>, BallistaError> {
+    let reader = StreamReader::try_new(Cursor::new(bytes))?;
+    reader.map(|batch| batch.map_err(|e| e.into())).collect()
+}
+
+/// Read all partitions of an in-memory execution plan, such as the scan of a `MemTable`. Their
+/// batches are not exposed, but their streams are ready as soon as they are polled, so they are
+/// polled once instead of being waited for. Plans whose output is not ready are rejected.
+pub(crate) fn collect_partitions(
+    plan: &dyn ExecutionPlan,
+) -> Result<Vec<Vec<RecordBatch>>, BallistaError> {
+    let not_in_memory = || {
+        BallistaError::General(format!(
+            "Cannot serialize the partitions of {:?}, which are not in memory",
+            plan
+        ))
+    };
+    (0..plan.output_partitioning().partition_count())
+        .map(|partition| -> Result<Vec<RecordBatch>, BallistaError> {
+            let stream = plan
+                .execute(partition)
+                .now_or_never()
+                .ok_or_else(not_in_memory)??;
+            Ok(common::collect(stream)
+                .now_or_never()
+                .ok_or_else(not_in_memory)??)
+        })
+        .collect()
+}
+
 #[macro_export]
 macro_rules! convert_required {
     ($PB:expr) => {{
@@ -19,20 +19,28 @@ use std::convert::TryInto;
 use std::sync::Arc;
 
 use crate::error::BallistaError;
-use crate::scheduler::execution_plans::ShuffleReaderExec;
+use crate::scheduler::execution_plans::{
+    CsvScanExec, FileSplit, JsonScanExec, ParquetScanExec, QueryStageExec, ShuffleReaderExec,
+    UnresolvedShuffleExec,
+};
 use crate::scheduler::planner::PartitionLocation;
 use crate::serde::protobuf::LogicalExprNode;
-use crate::serde::{proto_error, protobuf};
+use crate::serde::{decode_batches, extension, parse_delimiter, proto_error, protobuf};
 use crate::{convert_box_required, convert_required};
 
 use arrow::datatypes::{DataType, Schema, SchemaRef};
 use datafusion::execution::context::{ExecutionConfig, ExecutionContextState};
-use datafusion::logical_plan::{DFSchema, Expr};
+use datafusion::logical_plan::{DFSchema, Expr, PlanType, StringifiedPlan};
 use datafusion::physical_plan::aggregates::{create_aggregate_expr, AggregateFunction};
+use datafusion::physical_plan::explain::ExplainExec;
 use datafusion::physical_plan::expressions::col;
 use datafusion::physical_plan::hash_aggregate::{AggregateMode, HashAggregateExec};
+use datafusion::physical_plan::memory::MemoryExec;
 use datafusion::physical_plan::merge::MergeExec;
 use datafusion::physical_plan::planner::DefaultPhysicalPlanner;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::udaf;
+use datafusion::physical_plan::union::UnionExec;
 use datafusion::physical_plan::{
     coalesce_batches::CoalesceBatchesExec,
     csv::CsvExec,
@@ -46,11 +54,13 @@ use datafusion::physical_plan::{
     projection::ProjectionExec,
     sort::{SortExec, SortOptions},
 };
-use datafusion::physical_plan::{AggregateExpr, ExecutionPlan, PhysicalExpr};
+use datafusion::physical_plan::{AggregateExpr, ExecutionPlan, Partitioning, PhysicalExpr};
 use datafusion::prelude::CsvReadOptions;
 use log::debug;
 use protobuf::logical_expr_node::ExprType;
 use protobuf::physical_plan_node::PhysicalPlanType;
+use protobuf::repartition_exec_node::PartitionMethod;
+use uuid::Uuid;
 
 impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
     type Error = BallistaError;
@@ -92,29 +102,62 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                 let options = CsvReadOptions::new()
                     .has_header(scan.has_header)
                     .file_extension(&scan.file_extension)
-                    .delimiter(scan.delimiter.as_bytes()[0])
+                    .delimiter(parse_delimiter(&scan.delimiter)?)
                     .schema(&schema);
-                // TODO we don't care what the DataFusion batch size was because Ballista will
-                // have its own configs. Hard-code for now.
-                let batch_size = 32768;
+                let batch_size = scan.batch_size as usize;
+                let projection = scan.projection.iter().map(|i| *i as usize).collect();
+                let exec = CsvExec::try_new(&scan.path, options, Some(projection), batch_size)?;
+                if scan.splits.is_empty() {
+                    Ok(Arc::new(exec))
+                } else {
+                    let splits = scan.splits.iter().map(|split| split.into()).collect();
+                    Ok(Arc::new(CsvScanExec::new(exec, splits, batch_size)))
+                }
+            }
+            PhysicalPlanType::JsonScan(scan) => {
+                let schema = Arc::new(convert_required!(scan.schema)?);
                 let projection = scan.projection.iter().map(|i| *i as usize).collect();
-                Ok(Arc::new(CsvExec::try_new(
+                Ok(Arc::new(JsonScanExec::try_new(
                     &scan.path,
-                    options,
+                    scan.filename.clone(),
+                    schema,
                     Some(projection),
-                    batch_size,
+                    scan.batch_size as usize,
                 )?))
             }
+            PhysicalPlanType::Memory(memory) => {
+                let schema = Arc::new(convert_required!(memory.schema)?);
+                let partitions = memory
+                    .partitions
+                    .iter()
+                    .map(|partition| decode_batches(partition))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Arc::new(MemoryExec::try_new(&partitions, schema, None)?))
+            }
             PhysicalPlanType::ParquetScan(scan) => {
                 let projection = scan.projection.iter().map(|i| *i as usize).collect();
                 let filenames: Vec<&str> = scan.filename.iter().map(|s| s.as_str()).collect();
-                Ok(Arc::new(ParquetExec::try_from_files(
+                let predicate: Option<Expr> = scan
+                    .predicate
+                    .as_ref()
+                    .map(|expr| expr.try_into())
+                    .transpose()?;
+                // the predicate is applied by the ParquetScanExec rather than the ParquetExec
+                let exec = ParquetExec::try_from_files(
                     &filenames,
                     Some(projection),
                     None,
                     scan.batch_size as usize,
                     scan.num_partitions as usize,
-                )?))
+                )?;
+                if predicate.is_none() && scan.splits.is_empty() {
+                    Ok(Arc::new(exec))
+                } else {
+                    let splits = scan.splits.iter().map(|split| split.into()).collect();
+                    Ok(Arc::new(
+                        ParquetScanExec::new(exec, predicate).with_splits(splits),
+                    ))
+                }
             }
             PhysicalPlanType::CoalesceBatches(coalesce_batches) => {
                 let input: Arc<dyn ExecutionPlan> = convert_box_required!(coalesce_batches.input)?;
@@ -123,6 +166,56 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     coalesce_batches.target_batch_size as usize,
                 )))
             }
+            PhysicalPlanType::Repartition(repartition) => {
+                let input: Arc<dyn ExecutionPlan> = convert_box_required!(repartition.input)?;
+                let partitioning = match repartition.partition_method.as_ref() {
+                    Some(PartitionMethod::RoundRobin(partition_count)) => {
+                        Partitioning::RoundRobinBatch(*partition_count as usize)
+                    }
+                    Some(PartitionMethod::Hash(hash_part)) => {
+                        parse_hash_partitioning(hash_part, &input.schema())?
+                    }
+                    Some(PartitionMethod::Unknown(partition_count)) => {
+                        Partitioning::UnknownPartitioning(*partition_count as usize)
+                    }
+                    None => {
+                        return Err(proto_error(
+                            "RepartitionExecNode is missing the partition method",
+                        ))
+                    }
+                };
+                Ok(Arc::new(RepartitionExec::try_new(input, partitioning)?))
+            }
+            PhysicalPlanType::Union(union) => {
+                let inputs = union
+                    .inputs
+                    .iter()
+                    .map(|input| input.try_into())
+                    .collect::<Result<Vec<Arc<dyn ExecutionPlan>>, _>>()?;
+                Ok(Arc::new(UnionExec::new(inputs)))
+            }
+            PhysicalPlanType::Explain(explain) => {
+                let schema = Arc::new(convert_required!(explain.schema)?);
+                let stringified_plans = explain
+                    .stringified_plans
+                    .iter()
+                    .map(|plan| {
+                        Ok(StringifiedPlan::new(
+                            parse_plan_type(&plan.plan_type)?,
+                            plan.plan.clone(),
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, BallistaError>>()?;
+                Ok(Arc::new(ExplainExec::new(schema, stringified_plans)))
+            }
+            PhysicalPlanType::Extension(ext) => {
+                let inputs = ext
+                    .inputs
+                    .iter()
+                    .map(|input| input.try_into())
+                    .collect::<Result<Vec<Arc<dyn ExecutionPlan>>, _>>()?;
+                extension::decode_physical(&ext.type_name, &ext.node, &inputs)
+            }
             PhysicalPlanType::Merge(merge) => {
                 let input: Arc<dyn ExecutionPlan> = convert_box_required!(merge.input)?;
                 Ok(Arc::new(MergeExec::new(input)))
@@ -200,6 +293,22 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                                 name.to_string(),
                             )?);
                         }
+                        Expr::AggregateUDF { fun, args } => {
+                            let args = args
+                                .iter()
+                                .map(|arg| {
+                                    df_planner
+                                        .create_physical_expr(arg, &physical_schema, &ctx_state)
+                                        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+                                })
+                                .collect::<Result<Vec<_>, _>>()?;
+                            physical_aggr_expr.push(udaf::create_aggregate_expr(
+                                &fun,
+                                &args,
+                                &physical_schema,
+                                name.to_string(),
+                            )?);
+                        }
                         _ => {
                             return Err(BallistaError::General(
                                 "Invalid expression for HashAggregateExec".to_string(),
@@ -241,14 +350,61 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
             }
             PhysicalPlanType::ShuffleReader(shuffle_reader) => {
                 let schema = Arc::new(convert_required!(shuffle_reader.schema)?);
-                let partition_location: Vec<PartitionLocation> = shuffle_reader
-                    .partition_location
+                let partition: Vec<Vec<PartitionLocation>> = shuffle_reader
+                    .partition
                     .iter()
-                    .map(|p| p.clone().try_into())
+                    .map(|p| {
+                        p.location
+                            .iter()
+                            .map(|l| l.clone().try_into())
+                            .collect::<Result<Vec<_>, BallistaError>>()
+                    })
                     .collect::<Result<Vec<_>, BallistaError>>()?;
-                let shuffle_reader = ShuffleReaderExec::try_new(partition_location, schema)?;
+                let hash_exprs = parse_hash_exprs(
+                    shuffle_reader.hash_partitioned,
+                    &shuffle_reader.hash_expr,
+                    &schema,
+                )?;
+                let shuffle_reader = ShuffleReaderExec::try_new(partition, schema, hash_exprs)?;
                 Ok(Arc::new(shuffle_reader))
             }
+            PhysicalPlanType::Unresolved(unresolved_shuffle) => {
+                let schema = Arc::new(convert_required!(unresolved_shuffle.schema)?);
+                let hash_exprs = parse_hash_exprs(
+                    unresolved_shuffle.hash_partitioned,
+                    &unresolved_shuffle.hash_expr,
+                    &schema,
+                )?;
+                Ok(Arc::new(UnresolvedShuffleExec::new(
+                    unresolved_shuffle
+                        .query_stage_ids
+                        .iter()
+                        .map(|id| *id as usize)
+                        .collect(),
+                    schema,
+                    unresolved_shuffle.partition_count as usize,
+                    hash_exprs,
+                )))
+            }
+            PhysicalPlanType::QueryStage(query_stage) => {
+                let input: Arc<dyn ExecutionPlan> = convert_box_required!(query_stage.input)?;
+                let job_uuid = Uuid::parse_str(&query_stage.job_uuid).map_err(|e| {
+                    proto_error(format!(
+                        "Invalid job uuid {} in QueryStageExecNode: {:?}",
+                        query_stage.job_uuid, e
+                    ))
+                })?;
+                let shuffle_output_partitioning = match &query_stage.output_partitioning {
+                    Some(hash_part) => Some(parse_hash_partitioning(hash_part, &input.schema())?),
+                    None => None,
+                };
+                Ok(Arc::new(QueryStageExec::try_new(
+                    job_uuid,
+                    query_stage.stage_id as usize,
+                    input,
+                    shuffle_output_partitioning,
+                )?))
+            }
             PhysicalPlanType::Empty(empty) => {
                 let schema = Arc::new(convert_required!(empty.schema)?);
                 Ok(Arc::new(EmptyExec::new(empty.produce_one_row, schema)))
@@ -315,3 +471,58 @@ fn compile_expr(
         .create_physical_expr(&expr, schema, &state)
         .map_err(|e| BallistaError::General(format!("{:?}", e)))
 }
+
+fn parse_hash_partitioning(
+    hash_part: &protobuf::PhysicalHashRepartition,
+    input_schema: &Schema,
+) -> Result<Partitioning, BallistaError> {
+    let exprs = hash_part
+        .hash_expr
+        .iter()
+        .map(|expr| compile_expr(expr, input_schema))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Partitioning::Hash(
+        exprs,
+        hash_part.partition_count as usize,
+    ))
+}
+
+/// Parse the hash partitioning expressions of a shuffle
+fn parse_hash_exprs(
+    hash_partitioned: bool,
+    hash_expr: &[protobuf::LogicalExprNode],
+    schema: &Schema,
+) -> Result<Option<Vec<Arc<dyn PhysicalExpr>>>, BallistaError> {
+    if !hash_partitioned {
+        return Ok(None);
+    }
+    let exprs = hash_expr
+        .iter()
+        .map(|expr| compile_expr(expr, schema))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(exprs))
+}
+
+/// Parse a plan type as displayed by EXPLAIN
+fn parse_plan_type(plan_type: &str) -> Result<PlanType, BallistaError> {
+    let optimized_prefix = String::from(&PlanType::OptimizedLogicalPlan {
+        optimizer_name: String::new(),
+    });
+    if plan_type == String::from(&PlanType::LogicalPlan) {
+        Ok(PlanType::LogicalPlan)
+    } else if plan_type == String::from(&PlanType::PhysicalPlan) {
+        Ok(PlanType::PhysicalPlan)
+    } else if let Some(optimizer_name) = plan_type.strip_prefix(&optimized_prefix) {
+        Ok(PlanType::OptimizedLogicalPlan {
+            optimizer_name: optimizer_name.to_owned(),
+        })
+    } else {
+        Err(proto_error(format!("Unknown plan type '{}'", plan_type)))
+    }
+}
+
+impl Into<FileSplit> for &protobuf::FileSplit {
+    fn into(self) -> FileSplit {
+        FileSplit::new(&self.filename, self.start, self.end)
+    }
+}
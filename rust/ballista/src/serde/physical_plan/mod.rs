@@ -167,4 +167,194 @@ mod roundtrip_tests {
             Arc::new(EmptyExec::new(false, schema.clone())),
         )?))
     }
+
+    #[test]
+    fn roundtrip_hash_partitioned_query_stage() -> Result<()> {
+        use crate::scheduler::execution_plans::QueryStageExec;
+        use arrow::datatypes::{DataType, Field, Schema};
+        use uuid::Uuid;
+        let field_a = Field::new("a", DataType::Int64, false);
+        let field_b = Field::new("b", DataType::Int64, false);
+        let schema = Arc::new(Schema::new(vec![field_a, field_b]));
+        roundtrip_test(Arc::new(QueryStageExec::try_new(
+            Uuid::new_v4(),
+            1,
+            Arc::new(EmptyExec::new(false, schema)),
+            Some(Partitioning::Hash(vec![col("a"), col("b")], 4)),
+        )?))
+    }
+
+    #[test]
+    fn roundtrip_repartition() -> Result<()> {
+        use arrow::datatypes::Field;
+        use datafusion::physical_plan::repartition::RepartitionExec;
+        let field_a = Field::new("a", DataType::Int64, false);
+        let schema = Arc::new(Schema::new(vec![field_a]));
+        let partitionings = vec![
+            Partitioning::RoundRobinBatch(4),
+            Partitioning::Hash(vec![col("a")], 4),
+            Partitioning::UnknownPartitioning(4),
+        ];
+        for partitioning in partitionings {
+            roundtrip_test(Arc::new(RepartitionExec::try_new(
+                Arc::new(EmptyExec::new(false, schema.clone())),
+                partitioning,
+            )?))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_union() -> Result<()> {
+        use datafusion::physical_plan::union::UnionExec;
+        let schema = Arc::new(Schema::empty());
+        roundtrip_test(Arc::new(UnionExec::new(vec![
+            Arc::new(EmptyExec::new(false, schema.clone())),
+            Arc::new(EmptyExec::new(true, schema)),
+        ])))
+    }
+
+    #[test]
+    fn roundtrip_explain() -> Result<()> {
+        use arrow::datatypes::Field;
+        use datafusion::logical_plan::{PlanType, StringifiedPlan};
+        use datafusion::physical_plan::explain::ExplainExec;
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("plan_type", DataType::Utf8, false),
+            Field::new("plan", DataType::Utf8, false),
+        ]));
+        roundtrip_test(Arc::new(ExplainExec::new(
+            schema,
+            vec![
+                StringifiedPlan::new(PlanType::LogicalPlan, "Projection: #a"),
+                StringifiedPlan::new(
+                    PlanType::OptimizedLogicalPlan {
+                        optimizer_name: "projection_push_down".to_owned(),
+                    },
+                    "Projection: #a",
+                ),
+                StringifiedPlan::new(PlanType::PhysicalPlan, "ProjectionExec"),
+            ],
+        )))
+    }
+
+    #[test]
+    fn roundtrip_scalar_udf() -> Result<()> {
+        use arrow::datatypes::Field;
+        use datafusion::physical_plan::projection::ProjectionExec;
+        use datafusion::physical_plan::udf::create_physical_expr;
+        let udf = crate::test_utils::test_identity_udf();
+        crate::udf::register_udf(udf.clone());
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Float64, false)]));
+        let expr = create_physical_expr(&udf, &[col("a")], &schema)?;
+        roundtrip_test(Arc::new(ProjectionExec::try_new(
+            vec![(expr, "test_identity(a)".to_string())],
+            Arc::new(EmptyExec::new(false, schema)),
+        )?))
+    }
+
+    #[test]
+    fn roundtrip_aggregate_udf() -> Result<()> {
+        use arrow::datatypes::Field;
+        use datafusion::physical_plan::udaf::create_aggregate_expr;
+        let udaf = crate::test_utils::test_avg_udaf();
+        crate::udf::register_udaf(udaf.clone());
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int64, false),
+            Field::new("b", DataType::Float64, false),
+        ]));
+        let aggregates = vec![create_aggregate_expr(
+            &udaf,
+            &[col("b")],
+            &schema,
+            "test_avg(b)".to_string(),
+        )?];
+        roundtrip_test(Arc::new(HashAggregateExec::try_new(
+            AggregateMode::Final,
+            vec![(col("a"), "a".to_string())],
+            aggregates,
+            Arc::new(EmptyExec::new(false, schema.clone())),
+            schema,
+        )?))
+    }
+
+    #[test]
+    fn roundtrip_memory() -> Result<()> {
+        use crate::serde::collect_partitions;
+        use arrow::array::Int32Array;
+        use arrow::datatypes::Field;
+        use arrow::record_batch::RecordBatch;
+        use datafusion::physical_plan::memory::MemoryExec;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let exec_plan: Arc<dyn ExecutionPlan> = Arc::new(MemoryExec::try_new(
+            &[vec![batch.clone(), batch], vec![]],
+            schema,
+            None,
+        )?);
+        let proto: protobuf::PhysicalPlanNode = exec_plan.clone().try_into()?;
+        let result_exec_plan: Arc<dyn ExecutionPlan> = (&proto).try_into()?;
+        assert_eq!(exec_plan.schema(), result_exec_plan.schema());
+        assert_eq!(
+            format!("{:?}", collect_partitions(exec_plan.as_ref())?),
+            format!("{:?}", collect_partitions(result_exec_plan.as_ref())?)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn roundtrip_parquet_scan_with_predicate() -> Result<()> {
+        use crate::error::BallistaError;
+        use crate::scheduler::execution_plans::{ParquetScanExec, ParquetScanTable};
+        use crate::utils::skipped_row_groups;
+        use arrow::array::Int32Array;
+        use arrow::datatypes::Field;
+        use arrow::record_batch::RecordBatch;
+        use datafusion::datasource::TableProvider;
+        use datafusion::logical_plan::{col, lit};
+        use datafusion::physical_plan::common::collect;
+        use parquet::arrow::ArrowWriter;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.parquet");
+        let mut writer = ArrowWriter::try_new(std::fs::File::create(&path)?, schema.clone(), None)
+            .map_err(|e| BallistaError::General(e.to_string()))?;
+        // each batch is written as a row group
+        for values in vec![vec![1, 2, 3], vec![10, 11, 12]] {
+            let batch =
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(values))])?;
+            writer
+                .write(&batch)
+                .map_err(|e| BallistaError::General(e.to_string()))?;
+        }
+        writer
+            .close()
+            .map_err(|e| BallistaError::General(e.to_string()))?;
+
+        let table = ParquetScanTable::try_new(path.to_str().unwrap(), 1)?;
+        let exec_plan = table.scan(&None, 1024, &[col("a").gt(lit(5))])?;
+        let proto: protobuf::PhysicalPlanNode = exec_plan.clone().try_into()?;
+        let result_exec_plan: Arc<dyn ExecutionPlan> = (&proto).try_into()?;
+        let scan = result_exec_plan
+            .as_any()
+            .downcast_ref::<ParquetScanExec>()
+            .expect("expected ParquetScanExec");
+        assert_eq!(
+            format!("{:?}", exec_plan.as_any().downcast_ref::<ParquetScanExec>()),
+            format!("{:?}", Some(scan))
+        );
+
+        let batches = collect(result_exec_plan.execute(0).await?).await?;
+        let num_rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+        assert_eq!(3, num_rows);
+        let (skipped_rows, skipped_bytes) = skipped_row_groups(result_exec_plan.as_ref(), 0);
+        assert_eq!(3, skipped_rows);
+        assert!(skipped_bytes > 0);
+        Ok(())
+    }
 }
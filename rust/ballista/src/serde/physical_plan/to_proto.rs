@@ -23,6 +23,7 @@ use std::{
 };
 
 use arrow::datatypes::DataType;
+use datafusion::logical_plan::Expr;
 use datafusion::physical_plan::expressions::{
     CaseExpr, InListExpr, IsNotNullExpr, IsNullExpr, NegativeExpr, NotExpr,
 };
@@ -31,9 +32,11 @@ use datafusion::physical_plan::hash_aggregate::AggregateMode;
 use datafusion::physical_plan::hash_join::HashJoinExec;
 use datafusion::physical_plan::hash_utils::JoinType;
 use datafusion::physical_plan::limit::{GlobalLimitExec, LocalLimitExec};
+use datafusion::physical_plan::memory::MemoryExec;
 use datafusion::physical_plan::parquet::ParquetExec;
 use datafusion::physical_plan::projection::ProjectionExec;
 use datafusion::physical_plan::sort::SortExec;
+use datafusion::physical_plan::udaf::AggregateFunctionExpr;
 use datafusion::physical_plan::{coalesce_batches::CoalesceBatchesExec, expressions::CastExpr};
 use datafusion::{
     physical_plan::{
@@ -47,15 +50,23 @@ use datafusion::physical_plan::{
     empty::EmptyExec,
     expressions::{Avg, BinaryExpr, Column, Sum},
 };
-use datafusion::physical_plan::{AggregateExpr, ExecutionPlan, PhysicalExpr};
+use datafusion::physical_plan::{AggregateExpr, ExecutionPlan, Partitioning, PhysicalExpr};
 
 use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
 use protobuf::physical_plan_node::PhysicalPlanType;
 
-use crate::scheduler::execution_plans::ShuffleReaderExec;
-use crate::serde::{protobuf, BallistaError};
+use crate::scheduler::execution_plans::{
+    CsvScanExec, FileSplit, JsonScanExec, ParquetScanExec, QueryStageExec, ShuffleReaderExec,
+    UnresolvedShuffleExec,
+};
+use crate::serde::{collect_partitions, encode_batches, extension, protobuf, BallistaError};
+use crate::udf;
+use datafusion::physical_plan::explain::ExplainExec;
 use datafusion::physical_plan::functions::{BuiltinScalarFunction, ScalarFunctionExpr};
 use datafusion::physical_plan::merge::MergeExec;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::union::UnionExec;
+use protobuf::repartition_exec_node::PartitionMethod;
 
 impl TryInto<protobuf::PhysicalPlanNode> for Arc<dyn ExecutionPlan> {
     type Error = BallistaError;
@@ -180,6 +191,20 @@ impl TryInto<protobuf::PhysicalPlanNode> for Arc<dyn ExecutionPlan> {
                     },
                 ))),
             })
+        } else if let Some(exec) = plan.downcast_ref::<MemoryExec>() {
+            // MemoryExec does not expose its partitions, so they are read from its output, which
+            // is already in memory
+            let schema = exec.schema();
+            let partitions = collect_partitions(exec)?
+                .iter()
+                .map(|batches| encode_batches(schema.as_ref(), batches))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Memory(protobuf::MemoryExecNode {
+                    schema: Some(schema.as_ref().into()),
+                    partitions,
+                })),
+            })
         } else if let Some(empty) = plan.downcast_ref::<EmptyExec>() {
             let schema = empty.schema().as_ref().into();
             Ok(protobuf::PhysicalPlanNode {
@@ -200,69 +225,135 @@ impl TryInto<protobuf::PhysicalPlanNode> for Arc<dyn ExecutionPlan> {
                 ))),
             })
         } else if let Some(exec) = plan.downcast_ref::<CsvExec>() {
-            let delimiter = [*exec.delimiter().ok_or_else(|| {
-                BallistaError::General("Delimeter is not set for CsvExec".to_owned())
-            })?];
-            let delimiter = std::str::from_utf8(&delimiter)
-                .map_err(|_| BallistaError::General("Invalid CSV delimiter".to_owned()))?;
-
+            // CsvExec does not expose its batch size, so whole-file scans use a fixed one
+            serialize_csv_exec(exec, &[], 32768)
+        } else if let Some(exec) = plan.downcast_ref::<CsvScanExec>() {
+            serialize_csv_exec(&exec.csv, &exec.splits, exec.batch_size())
+        } else if let Some(exec) = plan.downcast_ref::<JsonScanExec>() {
+            let projection = match exec.projection() {
+                Some(columns) => columns.iter().map(|i| *i as u32).collect(),
+                None => (0..exec.file_schema().fields().len() as u32).collect(),
+            };
             Ok(protobuf::PhysicalPlanNode {
-                physical_plan_type: Some(PhysicalPlanType::CsvScan(protobuf::CsvScanExecNode {
+                physical_plan_type: Some(PhysicalPlanType::JsonScan(protobuf::JsonScanExecNode {
                     path: exec.path().to_owned(),
                     filename: exec.filenames().to_vec(),
-                    projection: exec
-                        .projection()
-                        .ok_or_else(|| {
-                            BallistaError::General(
-                                "projection in CsvExec dosn not exist.".to_owned(),
-                            )
-                        })?
-                        .iter()
-                        .map(|n| *n as u32)
-                        .collect(),
-                    file_extension: exec.file_extension().to_owned(),
                     schema: Some(exec.file_schema().as_ref().into()),
-                    has_header: exec.has_header(),
-                    delimiter: delimiter.to_string(),
-                    batch_size: 32768,
+                    projection,
+                    batch_size: exec.batch_size() as u32,
                 })),
             })
         } else if let Some(exec) = plan.downcast_ref::<ParquetExec>() {
-            let filenames = exec
-                .partitions()
-                .iter()
-                .flat_map(|part| part.filenames().to_owned())
-                .collect();
-            Ok(protobuf::PhysicalPlanNode {
-                physical_plan_type: Some(PhysicalPlanType::ParquetScan(
-                    protobuf::ParquetScanExecNode {
-                        filename: filenames,
-                        projection: exec
-                            .projection()
-                            .as_ref()
-                            .iter()
-                            .map(|n| *n as u32)
-                            .collect(),
-                        num_partitions: exec.partitions().len() as u32,
-                        batch_size: exec.batch_size() as u32,
-                    },
-                )),
-            })
+            serialize_parquet_exec(exec, None, &[])
+        } else if let Some(exec) = plan.downcast_ref::<ParquetScanExec>() {
+            serialize_parquet_exec(&exec.parquet, exec.predicate.as_ref(), &exec.splits)
         } else if let Some(exec) = plan.downcast_ref::<ShuffleReaderExec>() {
-            let partition_location = exec
-                .partition_location
+            let partition = exec
+                .partition
                 .iter()
-                .map(|l| l.clone().try_into())
-                .collect::<Result<_, _>>()?;
+                .map(|locations| {
+                    Ok(protobuf::ShuffleReaderPartition {
+                        location: locations
+                            .iter()
+                            .map(|l| l.clone().try_into())
+                            .collect::<Result<_, _>>()?,
+                    })
+                })
+                .collect::<Result<_, BallistaError>>()?;
 
             Ok(protobuf::PhysicalPlanNode {
                 physical_plan_type: Some(PhysicalPlanType::ShuffleReader(
                     protobuf::ShuffleReaderExecNode {
-                        partition_location,
                         schema: Some(exec.schema().as_ref().into()),
+                        partition,
+                        hash_partitioned: exec.hash_partitioned(),
+                        hash_expr: serialize_hash_exprs(&exec.hash_exprs)?,
                     },
                 )),
             })
+        } else if let Some(exec) = plan.downcast_ref::<UnresolvedShuffleExec>() {
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Unresolved(
+                    protobuf::UnresolvedShuffleExecNode {
+                        query_stage_ids: exec.query_stage_ids.iter().map(|id| *id as u32).collect(),
+                        schema: Some(exec.schema().as_ref().into()),
+                        partition_count: exec.partition_count as u32,
+                        hash_partitioned: exec.hash_partitioned(),
+                        hash_expr: serialize_hash_exprs(&exec.hash_exprs)?,
+                    },
+                )),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<QueryStageExec>() {
+            let input: protobuf::PhysicalPlanNode = exec.child.to_owned().try_into()?;
+            let output_partitioning = match &exec.shuffle_output_partitioning {
+                Some(Partitioning::Hash(exprs, partition_count)) => {
+                    Some(serialize_hash_partitioning(exprs, *partition_count)?)
+                }
+                Some(other) => {
+                    return Err(BallistaError::General(format!(
+                        "physical_plan::to_proto() unsupported shuffle output partitioning {:?}",
+                        other
+                    )))
+                }
+                None => None,
+            };
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::QueryStage(Box::new(
+                    protobuf::QueryStageExecNode {
+                        job_uuid: exec.job_uuid.to_string(),
+                        stage_id: exec.stage_id as u32,
+                        input: Some(Box::new(input)),
+                        output_partitioning,
+                    },
+                ))),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<RepartitionExec>() {
+            let input: protobuf::PhysicalPlanNode = exec.children()[0].to_owned().try_into()?;
+            let partition_method = match exec.output_partitioning() {
+                Partitioning::RoundRobinBatch(partition_count) => {
+                    PartitionMethod::RoundRobin(partition_count as u64)
+                }
+                Partitioning::Hash(exprs, partition_count) => {
+                    PartitionMethod::Hash(serialize_hash_partitioning(&exprs, partition_count)?)
+                }
+                Partitioning::UnknownPartitioning(partition_count) => {
+                    PartitionMethod::Unknown(partition_count as u64)
+                }
+            };
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Repartition(Box::new(
+                    protobuf::RepartitionExecNode {
+                        input: Some(Box::new(input)),
+                        partition_method: Some(partition_method),
+                    },
+                ))),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<UnionExec>() {
+            let inputs = exec
+                .children()
+                .into_iter()
+                .map(|input| input.try_into())
+                .collect::<Result<Vec<_>, Self::Error>>()?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Union(protobuf::UnionExecNode {
+                    inputs,
+                })),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<ExplainExec>() {
+            let stringified_plans = exec
+                .stringified_plans()
+                .iter()
+                .map(|plan| protobuf::StringifiedPlan {
+                    plan_type: plan.plan_type.to_string(),
+                    plan: plan.plan.as_ref().to_owned(),
+                })
+                .collect();
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Explain(protobuf::ExplainExecNode {
+                    schema: Some(exec.schema().as_ref().into()),
+                    stringified_plans,
+                })),
+            })
         } else if let Some(exec) = plan.downcast_ref::<MergeExec>() {
             let input: protobuf::PhysicalPlanNode = exec.input().to_owned().try_into()?;
             Ok(protobuf::PhysicalPlanNode {
@@ -297,10 +388,21 @@ impl TryInto<protobuf::PhysicalPlanNode> for Arc<dyn ExecutionPlan> {
                 ))),
             })
         } else {
-            Err(BallistaError::General(format!(
-                "physical plan to_proto unsupported plan {:?}",
-                self
-            )))
+            let (type_name, bytes) = extension::encode_physical(self.as_ref())?;
+            let inputs = self
+                .children()
+                .into_iter()
+                .map(|input| input.try_into())
+                .collect::<Result<Vec<_>, Self::Error>>()?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Extension(
+                    protobuf::PhysicalExtensionNode {
+                        type_name,
+                        node: bytes,
+                        inputs,
+                    },
+                )),
+            })
         }
     }
 }
@@ -309,6 +411,9 @@ impl TryInto<protobuf::LogicalExprNode> for Arc<dyn AggregateExpr> {
     type Error = BallistaError;
 
     fn try_into(self) -> Result<protobuf::LogicalExprNode, Self::Error> {
+        if let Some(expr) = self.as_any().downcast_ref::<AggregateFunctionExpr>() {
+            return try_parse_aggregate_udf(expr);
+        }
         let aggr_function = if self.as_any().downcast_ref::<Avg>().is_some() {
             Ok(protobuf::AggregateFunction::Avg.into())
         } else if self.as_any().downcast_ref::<Sum>().is_some() {
@@ -446,18 +551,29 @@ impl TryFrom<Arc<dyn PhysicalExpr>> for protobuf::LogicalExprNode {
                 ))),
             })
         } else if let Some(expr) = expr.downcast_ref::<ScalarFunctionExpr>() {
-            let fun: BuiltinScalarFunction = BuiltinScalarFunction::from_str(expr.name())?;
-            let fun: protobuf::ScalarFunction = (&fun).try_into()?;
-            let expr: Vec<protobuf::LogicalExprNode> = expr
+            let args: Vec<protobuf::LogicalExprNode> = expr
                 .args()
                 .iter()
                 .map(|e| e.to_owned().try_into())
                 .collect::<Result<Vec<_>, _>>()?;
+            // user-defined functions are planned as ScalarFunctionExpr too, under their own name
+            if udf::get_udf(expr.name()).is_some() {
+                return Ok(protobuf::LogicalExprNode {
+                    expr_type: Some(protobuf::logical_expr_node::ExprType::ScalarUdfExpr(
+                        protobuf::ScalarUdfExprNode {
+                            fun_name: expr.name().to_owned(),
+                            args,
+                        },
+                    )),
+                });
+            }
+            let fun: BuiltinScalarFunction = BuiltinScalarFunction::from_str(expr.name())?;
+            let fun: protobuf::ScalarFunction = (&fun).try_into()?;
             Ok(protobuf::LogicalExprNode {
                 expr_type: Some(protobuf::logical_expr_node::ExprType::ScalarFunction(
                     protobuf::ScalarFunctionNode {
                         fun: fun.into(),
-                        expr,
+                        expr: args,
                     },
                 )),
             })
@@ -470,6 +586,108 @@ impl TryFrom<Arc<dyn PhysicalExpr>> for protobuf::LogicalExprNode {
     }
 }
 
+/// Serialize a CSV scan, along with the byte ranges read by its partitions if it is split
+fn serialize_csv_exec(
+    exec: &CsvExec,
+    splits: &[FileSplit],
+    batch_size: usize,
+) -> Result<protobuf::PhysicalPlanNode, BallistaError> {
+    let delimiter = [*exec
+        .delimiter()
+        .ok_or_else(|| BallistaError::General("Delimeter is not set for CsvExec".to_owned()))?];
+    let delimiter = std::str::from_utf8(&delimiter)
+        .map_err(|_| BallistaError::General("Invalid CSV delimiter".to_owned()))?;
+
+    Ok(protobuf::PhysicalPlanNode {
+        physical_plan_type: Some(PhysicalPlanType::CsvScan(protobuf::CsvScanExecNode {
+            path: exec.path().to_owned(),
+            filename: exec.filenames().to_vec(),
+            projection: exec
+                .projection()
+                .ok_or_else(|| {
+                    BallistaError::General("projection in CsvExec dosn not exist.".to_owned())
+                })?
+                .iter()
+                .map(|n| *n as u32)
+                .collect(),
+            file_extension: exec.file_extension().to_owned(),
+            schema: Some(exec.file_schema().as_ref().into()),
+            has_header: exec.has_header(),
+            delimiter: delimiter.to_string(),
+            batch_size: batch_size as u32,
+            splits: splits.iter().map(|split| split.into()).collect(),
+        })),
+    })
+}
+
+/// Serialize a Parquet scan along with the predicate used to skip row groups and the row group
+/// ranges read by its partitions, if any
+fn serialize_parquet_exec(
+    exec: &ParquetExec,
+    predicate: Option<&Expr>,
+    splits: &[FileSplit],
+) -> Result<protobuf::PhysicalPlanNode, BallistaError> {
+    let filenames = exec
+        .partitions()
+        .iter()
+        .flat_map(|part| part.filenames().to_owned())
+        .collect();
+    Ok(protobuf::PhysicalPlanNode {
+        physical_plan_type: Some(PhysicalPlanType::ParquetScan(
+            protobuf::ParquetScanExecNode {
+                filename: filenames,
+                projection: exec
+                    .projection()
+                    .as_ref()
+                    .iter()
+                    .map(|n| *n as u32)
+                    .collect(),
+                num_partitions: exec.partitions().len() as u32,
+                batch_size: exec.batch_size() as u32,
+                predicate: predicate.map(|expr| expr.try_into()).transpose()?,
+                splits: splits.iter().map(|split| split.into()).collect(),
+            },
+        )),
+    })
+}
+
+impl Into<protobuf::FileSplit> for &FileSplit {
+    fn into(self) -> protobuf::FileSplit {
+        protobuf::FileSplit {
+            filename: self.filename.clone(),
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// Serialize an aggregate UDF, which does not expose the function it calls. The function is
+/// found from the name of the output field, which the planner derives from the function name.
+fn try_parse_aggregate_udf(
+    expr: &AggregateFunctionExpr,
+) -> Result<protobuf::LogicalExprNode, BallistaError> {
+    let fun_name = expr.fun().name.as_str();
+    if udf::get_udaf(fun_name).is_none() {
+        return Err(BallistaError::General(format!(
+            "physical_plan::to_proto() unregistered aggregate UDF {:?}",
+            expr
+        )));
+    }
+    let args = expr
+        .expressions()
+        .iter()
+        .map(|e| e.clone().try_into())
+        .collect::<Result<Vec<_>, BallistaError>>()?;
+    Ok(protobuf::LogicalExprNode {
+        expr_type: Some(protobuf::logical_expr_node::ExprType::AggregateUdfExpr(
+            protobuf::AggregateUdfExprNode {
+                fun_name: fun_name.to_owned(),
+                args,
+            },
+        )),
+    })
+}
+
 fn try_parse_when_then_expr(
     when_expr: &Arc<dyn PhysicalExpr>,
     then_expr: &Arc<dyn PhysicalExpr>,
@@ -479,3 +697,27 @@ fn try_parse_when_then_expr(
         then_expr: Some(then_expr.clone().try_into()?),
     })
 }
+
+fn serialize_hash_partitioning(
+    exprs: &[Arc<dyn PhysicalExpr>],
+    partition_count: usize,
+) -> Result<protobuf::PhysicalHashRepartition, BallistaError> {
+    Ok(protobuf::PhysicalHashRepartition {
+        hash_expr: exprs
+            .iter()
+            .map(|expr| expr.clone().try_into())
+            .collect::<Result<Vec<_>, BallistaError>>()?,
+        partition_count: partition_count as u64,
+    })
+}
+
+/// Serialize the hash partitioning expressions of a shuffle
+fn serialize_hash_exprs(
+    exprs: &Option<Vec<Arc<dyn PhysicalExpr>>>,
+) -> Result<Vec<protobuf::LogicalExprNode>, BallistaError> {
+    exprs
+        .iter()
+        .flatten()
+        .map(|expr| expr.clone().try_into())
+        .collect()
+}
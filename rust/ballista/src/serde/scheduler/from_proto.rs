@@ -19,6 +19,8 @@ use crate::scheduler::planner::PartitionLocation;
 use crate::serde::protobuf;
 use crate::serde::protobuf::action::ActionType;
 use crate::serde::scheduler::{Action, ExecutePartition, PartitionId};
+use crate::utils::PartitionStats;
+use datafusion::datasource::datasource::{ColumnStatistics, Statistics};
 
 use datafusion::logical_plan::LogicalPlan;
 use uuid::Uuid;
@@ -48,6 +50,40 @@ impl TryInto<Action> for protobuf::Action {
             Some(ActionType::FetchPartition(partition)) => {
                 Ok(Action::FetchPartition(partition.try_into()?))
             }
+            Some(ActionType::FetchShufflePartition(fetch)) => {
+                let partition_id = fetch.partition_id.ok_or_else(|| {
+                    BallistaError::General(
+                        "PartitionId in FetchShufflePartition is missing".to_owned(),
+                    )
+                })?;
+                Ok(Action::FetchShufflePartition(
+                    partition_id.try_into()?,
+                    fetch.output_partition as usize,
+                ))
+            }
+            Some(ActionType::CancelJob(cancel)) => {
+                Ok(Action::CancelJob(parse_job_uuid(&cancel.job_uuid)?))
+            }
+            Some(ActionType::RemoveJobData(remove)) => Ok(Action::RemoveJobData(
+                parse_job_uuid(&remove.job_uuid)?,
+                remove.keep_stage_id.iter().map(|s| *s as usize).collect(),
+            )),
+            Some(ActionType::FetchPartitions(fetch)) => {
+                let partition_ids = fetch
+                    .partition_id
+                    .into_iter()
+                    .map(|id| id.try_into())
+                    .collect::<Result<Vec<_>, BallistaError>>()?;
+                let output_partition = if fetch.hash_partitioned {
+                    Some(fetch.output_partition as usize)
+                } else {
+                    None
+                };
+                Ok(Action::FetchPartitions(partition_ids, output_partition))
+            }
+            Some(ActionType::RemoveUploadedTable(remove)) => Ok(Action::RemoveUploadedTable(
+                parse_job_uuid(&remove.table_uuid)?,
+            )),
             _ => Err(BallistaError::General(
                 "scheduler::from_proto(Action) invalid or missing action".to_owned(),
             )),
@@ -67,6 +103,19 @@ impl TryInto<PartitionId> for protobuf::PartitionId {
     }
 }
 
+impl Into<PartitionStats> for protobuf::PartitionStats {
+    fn into(self) -> PartitionStats {
+        PartitionStats::new(
+            self.num_rows,
+            self.num_batches,
+            self.num_bytes,
+            self.null_count,
+        )
+        .with_skipped(self.skipped_rows, self.skipped_bytes)
+        .with_shuffle_bytes(self.uncompressed_bytes, self.compressed_bytes)
+    }
+}
+
 impl TryInto<PartitionLocation> for protobuf::PartitionLocation {
     type Error = BallistaError;
 
@@ -102,3 +151,45 @@ fn parse_job_uuid(job_uuid: &str) -> Result<Uuid, BallistaError> {
         )))
     }
 }
+
+impl TryInto<Statistics> for &protobuf::FileStatistics {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<Statistics, Self::Error> {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                Ok(ColumnStatistics {
+                    null_count: column.null_count_value.as_ref().map(|n| match n {
+                        protobuf::column_statistics::NullCountValue::NullCount(n) => *n as usize,
+                    }),
+                    max_value: column
+                        .max_value
+                        .as_ref()
+                        .map(|v| v.try_into())
+                        .transpose()?,
+                    min_value: column
+                        .min_value
+                        .as_ref()
+                        .map(|v| v.try_into())
+                        .transpose()?,
+                    distinct_count: None,
+                })
+            })
+            .collect::<Result<Vec<_>, BallistaError>>()?;
+        Ok(Statistics {
+            num_rows: self.num_rows_value.as_ref().map(|n| match n {
+                protobuf::file_statistics::NumRowsValue::NumRows(n) => *n as usize,
+            }),
+            total_byte_size: self.num_bytes_value.as_ref().map(|n| match n {
+                protobuf::file_statistics::NumBytesValue::NumBytes(n) => *n as usize,
+            }),
+            column_statistics: if columns.is_empty() {
+                None
+            } else {
+                Some(columns)
+            },
+        })
+    }
+}
@@ -33,8 +33,24 @@ pub enum Action {
     ExecutePartition(ExecutePartition),
     /// Collect a shuffle partition
     FetchPartition(PartitionId),
+    /// Collect one output partition of a hash-partitioned shuffle, given the task that wrote it
+    /// and the output partition
+    FetchShufflePartition(PartitionId, usize),
+    /// Abort the tasks of a cancelled job and remove its work files
+    CancelJob(Uuid),
+    /// Remove the work files of a finished job, except for the output of the given stages
+    RemoveJobData(Uuid, Vec<usize>),
+    /// Collect several partitions as one stream, or the given output partition of several
+    /// hash-partitioned partitions
+    FetchPartitions(Vec<PartitionId>, Option<usize>),
+    /// Remove the partitions of a table that was uploaded by a client
+    RemoveUploadedTable(Uuid),
 }
 
+/// Stage id of the partitions of tables uploaded by clients, whose job uuid is the uuid of the
+/// table. Query stages are numbered from 1, so the partitions never clash with stage output.
+pub const UPLOAD_STAGE_ID: usize = 0;
+
 /// Unique identifier for the output partition of an operator.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PartitionId {
@@ -59,6 +75,8 @@ pub struct ExecutorMeta {
     pub id: String,
     pub host: String,
     pub port: u16,
+    /// The number of tasks that the executor can run concurrently
+    pub task_slots: usize,
 }
 
 impl Into<protobuf::ExecutorMetadata> for ExecutorMeta {
@@ -67,6 +85,7 @@ impl Into<protobuf::ExecutorMetadata> for ExecutorMeta {
             id: self.id,
             host: self.host,
             port: self.port as u32,
+            task_slots: self.task_slots as u32,
         }
     }
 }
@@ -77,6 +96,7 @@ impl From<protobuf::ExecutorMetadata> for ExecutorMeta {
             id: meta.id,
             host: meta.host,
             port: meta.port as u16,
+            task_slots: meta.task_slots as usize,
         }
     }
 }
@@ -19,6 +19,8 @@ use crate::scheduler::planner::PartitionLocation;
 use crate::serde::protobuf;
 use crate::serde::protobuf::action::ActionType;
 use crate::serde::scheduler::{Action, ExecutePartition, PartitionId};
+use crate::utils::PartitionStats;
+use datafusion::datasource::datasource::Statistics;
 
 impl TryInto<protobuf::Action> for Action {
     type Error = BallistaError;
@@ -33,6 +35,44 @@ impl TryInto<protobuf::Action> for Action {
                 action_type: Some(ActionType::FetchPartition(partition_id.into())),
                 settings: vec![],
             }),
+            Action::FetchShufflePartition(partition_id, output_partition) => Ok(protobuf::Action {
+                action_type: Some(ActionType::FetchShufflePartition(
+                    protobuf::FetchShufflePartition {
+                        partition_id: Some(partition_id.into()),
+                        output_partition: output_partition as u32,
+                    },
+                )),
+                settings: vec![],
+            }),
+            Action::CancelJob(job_uuid) => Ok(protobuf::Action {
+                action_type: Some(ActionType::CancelJob(protobuf::CancelJob {
+                    job_uuid: job_uuid.to_string(),
+                })),
+                settings: vec![],
+            }),
+            Action::RemoveJobData(job_uuid, keep_stages) => Ok(protobuf::Action {
+                action_type: Some(ActionType::RemoveJobData(protobuf::RemoveJobData {
+                    job_uuid: job_uuid.to_string(),
+                    keep_stage_id: keep_stages.iter().map(|s| *s as u32).collect(),
+                })),
+                settings: vec![],
+            }),
+            Action::FetchPartitions(partition_ids, output_partition) => Ok(protobuf::Action {
+                action_type: Some(ActionType::FetchPartitions(protobuf::FetchPartitions {
+                    partition_id: partition_ids.into_iter().map(|id| id.into()).collect(),
+                    hash_partitioned: output_partition.is_some(),
+                    output_partition: output_partition.unwrap_or_default() as u32,
+                })),
+                settings: vec![],
+            }),
+            Action::RemoveUploadedTable(table_uuid) => Ok(protobuf::Action {
+                action_type: Some(ActionType::RemoveUploadedTable(
+                    protobuf::RemoveUploadedTable {
+                        table_uuid: table_uuid.to_string(),
+                    },
+                )),
+                settings: vec![],
+            }),
         }
     }
 }
@@ -61,6 +101,21 @@ impl Into<protobuf::PartitionId> for PartitionId {
     }
 }
 
+impl Into<protobuf::PartitionStats> for PartitionStats {
+    fn into(self) -> protobuf::PartitionStats {
+        protobuf::PartitionStats {
+            num_rows: self.num_rows(),
+            num_batches: self.num_batches(),
+            num_bytes: self.num_bytes(),
+            null_count: self.null_count(),
+            skipped_rows: self.skipped_rows(),
+            skipped_bytes: self.skipped_bytes(),
+            uncompressed_bytes: self.uncompressed_bytes(),
+            compressed_bytes: self.compressed_bytes(),
+        }
+    }
+}
+
 impl TryInto<protobuf::PartitionLocation> for PartitionLocation {
     type Error = BallistaError;
 
@@ -71,3 +126,42 @@ impl TryInto<protobuf::PartitionLocation> for PartitionLocation {
         })
     }
 }
+
+impl TryInto<protobuf::FileStatistics> for &Statistics {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::FileStatistics, Self::Error> {
+        let columns = match &self.column_statistics {
+            Some(columns) => columns
+                .iter()
+                .map(|column| {
+                    Ok(protobuf::ColumnStatistics {
+                        min_value: column
+                            .min_value
+                            .as_ref()
+                            .map(|v| v.try_into())
+                            .transpose()?,
+                        max_value: column
+                            .max_value
+                            .as_ref()
+                            .map(|v| v.try_into())
+                            .transpose()?,
+                        null_count_value: column.null_count.map(|n| {
+                            protobuf::column_statistics::NullCountValue::NullCount(n as u64)
+                        }),
+                    })
+                })
+                .collect::<Result<Vec<_>, BallistaError>>()?,
+            None => vec![],
+        };
+        Ok(protobuf::FileStatistics {
+            num_rows_value: self
+                .num_rows
+                .map(|n| protobuf::file_statistics::NumRowsValue::NumRows(n as u64)),
+            num_bytes_value: self
+                .total_byte_size
+                .map(|n| protobuf::file_statistics::NumBytesValue::NumBytes(n as u64)),
+            columns,
+        })
+    }
+}
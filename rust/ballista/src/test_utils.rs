@@ -12,11 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use crate::error::Result;
 
 use arrow::datatypes::{DataType, Field, Schema};
 use datafusion::execution::context::ExecutionContext;
 use datafusion::physical_plan::csv::CsvReadOptions;
+use datafusion::physical_plan::expressions::AvgAccumulator;
+use datafusion::physical_plan::udaf::AggregateUDF;
+use datafusion::physical_plan::udf::ScalarUDF;
+use datafusion::physical_plan::ColumnarValue;
+use datafusion::prelude::{create_udaf, create_udf};
 
 pub const TPCH_TABLES: &[&str] = &[
     "part", "supplier", "partsupp", "customer", "orders", "lineitem", "nation", "region",
@@ -37,6 +44,27 @@ pub fn datafusion_test_context(path: &str) -> Result<ExecutionContext> {
     Ok(ctx)
 }
 
+/// Scalar UDF `test_identity(Float64)` returning its argument
+pub fn test_identity_udf() -> ScalarUDF {
+    create_udf(
+        "test_identity",
+        vec![DataType::Float64],
+        Arc::new(DataType::Float64),
+        Arc::new(|args: &[ColumnarValue]| Ok(args[0].clone())),
+    )
+}
+
+/// Aggregate UDF `test_avg(Float64)` computing the average of its argument
+pub fn test_avg_udaf() -> AggregateUDF {
+    create_udaf(
+        "test_avg",
+        DataType::Float64,
+        Arc::new(DataType::Float64),
+        Arc::new(|| Ok(Box::new(AvgAccumulator::try_new(&DataType::Float64)?))),
+        Arc::new(vec![DataType::UInt64, DataType::Float64]),
+    )
+}
+
 pub fn get_tpch_schema(table: &str) -> Schema {
     // note that the schema intentionally uses signed integers so that any generated Parquet
     // files can also be used to benchmark tools that only support signed integers, such as
@@ -0,0 +1,87 @@
+// Copyright 2021 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of user-defined functions.
+//!
+//! UDFs are referenced by name in serialized plans and resolved from this registry when the
+//! plans are decoded, so the same functions must be registered in the client, the scheduler and
+//! the executors, typically at startup before any plan is received. The scheduler and executor
+//! binaries register the functions of [`FUNCTION_SETS`] with [`register_all`] at startup, so
+//! functions that queries share with their clients are added to that list.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use datafusion::physical_plan::udaf::AggregateUDF;
+use datafusion::physical_plan::udf::ScalarUDF;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref SCALAR_FUNCTIONS: RwLock<HashMap<String, Arc<ScalarUDF>>> =
+        RwLock::new(HashMap::new());
+    static ref AGGREGATE_FUNCTIONS: RwLock<HashMap<String, Arc<AggregateUDF>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Functions that the scheduler and executor binaries register at startup. Each entry registers
+/// one set of functions with [`register_udf`] and [`register_udaf`].
+pub const FUNCTION_SETS: &[fn()] = &[];
+
+/// Register the functions of all sets in [`FUNCTION_SETS`]
+pub fn register_all() {
+    for register in FUNCTION_SETS {
+        register();
+    }
+}
+
+/// Register a scalar UDF, replacing any function registered with the same name
+pub fn register_udf(f: ScalarUDF) {
+    SCALAR_FUNCTIONS
+        .write()
+        .unwrap()
+        .insert(f.name.clone(), Arc::new(f));
+}
+
+/// Register an aggregate UDF, replacing any function registered with the same name
+pub fn register_udaf(f: AggregateUDF) {
+    AGGREGATE_FUNCTIONS
+        .write()
+        .unwrap()
+        .insert(f.name.clone(), Arc::new(f));
+}
+
+/// Get a registered scalar UDF by name
+pub fn get_udf(name: &str) -> Option<Arc<ScalarUDF>> {
+    SCALAR_FUNCTIONS.read().unwrap().get(name).cloned()
+}
+
+/// Get a registered aggregate UDF by name
+pub fn get_udaf(name: &str) -> Option<Arc<AggregateUDF>> {
+    AGGREGATE_FUNCTIONS.read().unwrap().get(name).cloned()
+}
+
+/// All registered scalar UDFs
+pub fn udfs() -> Vec<Arc<ScalarUDF>> {
+    SCALAR_FUNCTIONS.read().unwrap().values().cloned().collect()
+}
+
+/// All registered aggregate UDFs
+pub fn udafs() -> Vec<Arc<AggregateUDF>> {
+    AGGREGATE_FUNCTIONS
+        .read()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect()
+}
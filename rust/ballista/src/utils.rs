@@ -12,19 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{fs::File, pin::Pin};
 
+use crate::compression::{ShuffleCompression, ShuffleWriter};
 use crate::error::{BallistaError, Result};
 use crate::memory_stream::MemoryStream;
 
-use crate::scheduler::execution_plans::{QueryStageExec, UnresolvedShuffleExec};
+use crate::scheduler::execution_plans::{
+    CsvScanExec, JsonScanExec, ParquetScanExec, QueryStageExec, UnresolvedShuffleExec,
+};
+use ahash::RandomState;
 use arrow::array::{
-    ArrayBuilder, ArrayRef, StructArray, StructBuilder, UInt64Array, UInt64Builder,
+    ArrayBuilder, ArrayRef, StructArray, StructBuilder, UInt32Array, UInt64Array, UInt64Builder,
 };
-use arrow::datatypes::{DataType, Field};
+use arrow::compute::take;
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::Result as ArrowResult;
 use arrow::ipc::reader::FileReader;
-use arrow::ipc::writer::FileWriter;
 use arrow::record_batch::RecordBatch;
 use datafusion::logical_plan::Operator;
 use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
@@ -32,10 +38,12 @@ use datafusion::physical_plan::csv::CsvExec;
 use datafusion::physical_plan::expressions::{BinaryExpr, Column, Literal};
 use datafusion::physical_plan::filter::FilterExec;
 use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
-use datafusion::physical_plan::hash_join::HashJoinExec;
+use datafusion::physical_plan::hash_join::{create_hashes, HashJoinExec};
 use datafusion::physical_plan::merge::MergeExec;
 use datafusion::physical_plan::parquet::ParquetExec;
-use datafusion::physical_plan::{AggregateExpr, ExecutionPlan, PhysicalExpr, RecordBatchStream};
+use datafusion::physical_plan::{
+    AggregateExpr, ExecutionPlan, Partitioning, PhysicalExpr, RecordBatchStream,
+};
 use futures::StreamExt;
 use std::collections::HashMap;
 use std::ops::Deref;
@@ -47,6 +55,10 @@ pub struct PartitionStats {
     num_batches: u64,
     num_bytes: u64,
     null_count: u64,
+    skipped_rows: u64,
+    skipped_bytes: u64,
+    uncompressed_bytes: u64,
+    compressed_bytes: u64,
 }
 
 impl Default for PartitionStats {
@@ -56,11 +68,84 @@ impl Default for PartitionStats {
             num_batches: 0,
             num_bytes: 0,
             null_count: 0,
+            skipped_rows: 0,
+            skipped_bytes: 0,
+            uncompressed_bytes: 0,
+            compressed_bytes: 0,
         }
     }
 }
 
 impl PartitionStats {
+    pub fn new(num_rows: u64, num_batches: u64, num_bytes: u64, null_count: u64) -> Self {
+        Self {
+            num_rows,
+            num_batches,
+            num_bytes,
+            null_count,
+            skipped_rows: 0,
+            skipped_bytes: 0,
+            uncompressed_bytes: 0,
+            compressed_bytes: 0,
+        }
+    }
+
+    /// Set the rows and bytes of the Parquet row groups that were skipped by predicate pushdown
+    pub fn with_skipped(mut self, skipped_rows: u64, skipped_bytes: u64) -> Self {
+        self.skipped_rows = skipped_rows;
+        self.skipped_bytes = skipped_bytes;
+        self
+    }
+
+    /// Set the size of the Arrow IPC data of the shuffle files and the size of the files on disk,
+    /// which is smaller when the files are compressed
+    pub fn with_shuffle_bytes(mut self, uncompressed_bytes: u64, compressed_bytes: u64) -> Self {
+        self.uncompressed_bytes = uncompressed_bytes;
+        self.compressed_bytes = compressed_bytes;
+        self
+    }
+
+    pub fn num_rows(&self) -> u64 {
+        self.num_rows
+    }
+
+    pub fn num_batches(&self) -> u64 {
+        self.num_batches
+    }
+
+    pub fn num_bytes(&self) -> u64 {
+        self.num_bytes
+    }
+
+    pub fn null_count(&self) -> u64 {
+        self.null_count
+    }
+
+    pub fn skipped_rows(&self) -> u64 {
+        self.skipped_rows
+    }
+
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    pub fn uncompressed_bytes(&self) -> u64 {
+        self.uncompressed_bytes
+    }
+
+    pub fn compressed_bytes(&self) -> u64 {
+        self.compressed_bytes
+    }
+
+    /// Ratio of the size of the Arrow IPC data of the shuffle files to their size on disk
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            1.0
+        } else {
+            self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+
     pub fn arrow_struct_repr(self) -> Field {
         Field::new(
             "partition_stats",
@@ -74,6 +159,10 @@ impl PartitionStats {
             Field::new("num_batches", DataType::UInt64, false),
             Field::new("num_bytes", DataType::UInt64, false),
             Field::new("null_count", DataType::UInt64, false),
+            Field::new("skipped_rows", DataType::UInt64, false),
+            Field::new("skipped_bytes", DataType::UInt64, false),
+            Field::new("uncompressed_bytes", DataType::UInt64, false),
+            Field::new("compressed_bytes", DataType::UInt64, false),
         ]
     }
 
@@ -96,6 +185,30 @@ impl PartitionStats {
         null_count_builder.append_value(self.null_count).unwrap();
         field_builders.push(Box::new(null_count_builder) as Box<dyn ArrayBuilder>);
 
+        let mut skipped_rows_builder = UInt64Builder::new(1);
+        skipped_rows_builder
+            .append_value(self.skipped_rows)
+            .unwrap();
+        field_builders.push(Box::new(skipped_rows_builder) as Box<dyn ArrayBuilder>);
+
+        let mut skipped_bytes_builder = UInt64Builder::new(1);
+        skipped_bytes_builder
+            .append_value(self.skipped_bytes)
+            .unwrap();
+        field_builders.push(Box::new(skipped_bytes_builder) as Box<dyn ArrayBuilder>);
+
+        let mut uncompressed_bytes_builder = UInt64Builder::new(1);
+        uncompressed_bytes_builder
+            .append_value(self.uncompressed_bytes)
+            .unwrap();
+        field_builders.push(Box::new(uncompressed_bytes_builder) as Box<dyn ArrayBuilder>);
+
+        let mut compressed_bytes_builder = UInt64Builder::new(1);
+        compressed_bytes_builder
+            .append_value(self.compressed_bytes)
+            .unwrap();
+        field_builders.push(Box::new(compressed_bytes_builder) as Box<dyn ArrayBuilder>);
+
         let mut struct_builder = StructBuilder::new(self.arrow_struct_fields(), field_builders);
         struct_builder.append(true).unwrap();
         Arc::new(struct_builder.finish())
@@ -135,15 +248,48 @@ impl PartitionStats {
                 .expect("from_arrow_struct_array expected null_count to be a UInt64Array")
                 .value(0)
                 .to_owned(),
+            skipped_rows: struct_array
+                .column_by_name("skipped_rows")
+                .expect("from_arrow_struct_array expected a field skipped_rows")
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .expect("from_arrow_struct_array expected skipped_rows to be a UInt64Array")
+                .value(0)
+                .to_owned(),
+            skipped_bytes: struct_array
+                .column_by_name("skipped_bytes")
+                .expect("from_arrow_struct_array expected a field skipped_bytes")
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .expect("from_arrow_struct_array expected skipped_bytes to be a UInt64Array")
+                .value(0)
+                .to_owned(),
+            uncompressed_bytes: struct_array
+                .column_by_name("uncompressed_bytes")
+                .expect("from_arrow_struct_array expected a field uncompressed_bytes")
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .expect("from_arrow_struct_array expected uncompressed_bytes to be a UInt64Array")
+                .value(0)
+                .to_owned(),
+            compressed_bytes: struct_array
+                .column_by_name("compressed_bytes")
+                .expect("from_arrow_struct_array expected a field compressed_bytes")
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .expect("from_arrow_struct_array expected compressed_bytes to be a UInt64Array")
+                .value(0)
+                .to_owned(),
         };
     }
 }
 
-/// Stream data to disk in Arrow IPC format
+/// Stream data to disk in Arrow IPC format, compressed with the given codec
 
 pub async fn write_stream_to_disk(
     stream: &mut Pin<Box<dyn RecordBatchStream + Send + Sync>>,
     path: &str,
+    compression: ShuffleCompression,
 ) -> Result<PartitionStats> {
     let file = File::create(&path).map_err(|e| {
         BallistaError::General(format!(
@@ -156,7 +302,7 @@ pub async fn write_stream_to_disk(
     let mut num_batches = 0;
     let mut num_bytes = 0;
     let mut null_count = 0;
-    let mut writer = FileWriter::try_new(file, stream.schema().as_ref())?;
+    let mut writer = ShuffleWriter::try_new(file, stream.schema().as_ref(), compression)?;
 
     while let Some(result) = stream.next().await {
         let batch = result?;
@@ -173,13 +319,96 @@ pub async fn write_stream_to_disk(
         null_count += batch_null_count;
         writer.write(&batch)?;
     }
-    writer.finish()?;
-    Ok(PartitionStats {
-        num_rows: num_rows as u64,
+    let (uncompressed_bytes, compressed_bytes) = writer.finish()?;
+    Ok(PartitionStats::new(
+        num_rows as u64,
         num_batches,
-        num_bytes: num_bytes as u64,
-        null_count: null_count as u64,
-    })
+        num_bytes as u64,
+        null_count as u64,
+    )
+    .with_shuffle_bytes(uncompressed_bytes, compressed_bytes))
+}
+
+/// Name of the file that holds one output partition of a hash-partitioned partition
+pub fn shuffle_partition_file_name(output_partition: usize) -> String {
+    format!("data-{}.arrow", output_partition)
+}
+
+/// Stream data to disk in Arrow IPC format, splitting the rows into one file per output
+/// partition based on the hash of the partitioning expressions. A file is created in the
+/// directory for every output partition, even if no rows are hashed to it.
+pub async fn write_hash_partitioned_stream_to_disk(
+    stream: &mut Pin<Box<dyn RecordBatchStream + Send + Sync>>,
+    exprs: &[Arc<dyn PhysicalExpr>],
+    num_partitions: usize,
+    dir: &str,
+    compression: ShuffleCompression,
+) -> Result<PartitionStats> {
+    let schema = stream.schema();
+    let mut writers = (0..num_partitions)
+        .map(|output_partition| {
+            let mut path = PathBuf::from(dir);
+            path.push(shuffle_partition_file_name(output_partition));
+            let file = File::create(&path).map_err(|e| {
+                BallistaError::General(format!(
+                    "Failed to create partition file at {:?}: {:?}",
+                    path, e
+                ))
+            })?;
+            ShuffleWriter::try_new(file, schema.as_ref(), compression)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // the hash of a row has to be the same on every executor
+    let random_state = RandomState::with_seeds(0, 0, 0, 0);
+
+    let mut stats = PartitionStats::default();
+    while let Some(result) = stream.next().await {
+        let batch = result?;
+
+        stats.num_batches += 1;
+        stats.num_rows += batch.num_rows() as u64;
+        stats.num_bytes += batch
+            .columns()
+            .iter()
+            .map(|array| array.get_array_memory_size() as u64)
+            .sum::<u64>();
+        stats.null_count += batch
+            .columns()
+            .iter()
+            .map(|array| array.null_count() as u64)
+            .sum::<u64>();
+
+        let arrays = exprs
+            .iter()
+            .map(|expr| Ok(expr.evaluate(&batch)?.into_array(batch.num_rows())))
+            .collect::<Result<Vec<_>>>()?;
+        let mut hashes = vec![0; batch.num_rows()];
+        create_hashes(&arrays, &random_state, &mut hashes)?;
+
+        let mut indices: Vec<Vec<u32>> = vec![vec![]; num_partitions];
+        for (row, hash) in hashes.iter().enumerate() {
+            indices[(*hash % num_partitions as u64) as usize].push(row as u32);
+        }
+        for (writer, indices) in writers.iter_mut().zip(indices) {
+            if indices.is_empty() {
+                continue;
+            }
+            let indices = UInt32Array::from(indices);
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|array| take(array.as_ref(), &indices, None))
+                .collect::<ArrowResult<Vec<_>>>()?;
+            writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+    }
+    for writer in writers {
+        let (uncompressed_bytes, compressed_bytes) = writer.finish()?;
+        stats.uncompressed_bytes += uncompressed_bytes;
+        stats.compressed_bytes += compressed_bytes;
+    }
+    Ok(stats)
 }
 
 pub async fn collect_stream(
@@ -192,6 +421,70 @@ pub async fn collect_stream(
     Ok(batches)
 }
 
+/// Check that record batches with the given schema can be uploaded to an executor. The upload
+/// sends the batches without their dictionaries, so dictionary-encoded columns are rejected.
+pub fn check_upload_schema(schema: &Schema) -> Result<()> {
+    fn contains_dictionary(data_type: &DataType) -> bool {
+        match data_type {
+            DataType::Dictionary(_, _) => true,
+            DataType::List(field)
+            | DataType::LargeList(field)
+            | DataType::FixedSizeList(field, _) => contains_dictionary(field.data_type()),
+            DataType::Struct(fields) | DataType::Union(fields) => fields
+                .iter()
+                .any(|field| contains_dictionary(field.data_type())),
+            _ => false,
+        }
+    }
+
+    match schema
+        .fields()
+        .iter()
+        .find(|field| contains_dictionary(field.data_type()))
+    {
+        Some(field) => Err(BallistaError::General(format!(
+            "Column {} is dictionary-encoded, which uploads do not support; cast it to its value \
+             type before uploading the record batches",
+            field.name()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Rows and bytes of the Parquet row groups that were skipped when executing a partition of a
+/// plan. When a scan does not have the same partitioning as the plan, all of its partitions are
+/// read by the partition of the plan.
+pub fn skipped_row_groups(plan: &dyn ExecutionPlan, partition: usize) -> (u64, u64) {
+    fn visit(
+        plan: &dyn ExecutionPlan,
+        partition: usize,
+        partition_count: usize,
+        skipped: &mut (u64, u64),
+    ) {
+        if let Some(exec) = plan.as_any().downcast_ref::<ParquetScanExec>() {
+            let scan_partition_count = exec.output_partitioning().partition_count();
+            let partitions = if scan_partition_count == partition_count {
+                partition..partition + 1
+            } else {
+                0..scan_partition_count
+            };
+            for i in partitions {
+                let (rows, bytes) = exec.skipped(i);
+                skipped.0 += rows;
+                skipped.1 += bytes;
+            }
+        }
+        for child in plan.children() {
+            visit(child.as_ref(), partition, partition_count, skipped);
+        }
+    }
+
+    let mut skipped = (0, 0);
+    let partition_count = plan.output_partitioning().partition_count();
+    visit(plan, partition, partition_count, &mut skipped);
+    skipped
+}
+
 pub fn format_plan(plan: &dyn ExecutionPlan, indent: usize) -> Result<String> {
     let operator_str = if let Some(exec) = plan.as_any().downcast_ref::<HashAggregateExec>() {
         format!(
@@ -221,6 +514,29 @@ pub fn format_plan(plan: &dyn ExecutionPlan, indent: usize) -> Result<String> {
             exec.partitions().len(),
             num_files
         )
+    } else if let Some(exec) = plan.as_any().downcast_ref::<ParquetScanExec>() {
+        let mut num_files = 0;
+        for part in exec.parquet.partitions() {
+            num_files += part.filenames().len();
+        }
+        format!(
+            "ParquetScanExec: partitions={}, files={}, predicate={:?}",
+            exec.output_partitioning().partition_count(),
+            num_files,
+            exec.predicate
+        )
+    } else if let Some(exec) = plan.as_any().downcast_ref::<CsvScanExec>() {
+        format!(
+            "CsvScanExec: {}; partitions={}",
+            exec.csv.path(),
+            exec.splits.len()
+        )
+    } else if let Some(exec) = plan.as_any().downcast_ref::<JsonScanExec>() {
+        format!(
+            "JsonScanExec: {}; partitions={}",
+            exec.path(),
+            exec.filenames().len()
+        )
     } else if let Some(exec) = plan.as_any().downcast_ref::<CsvExec>() {
         format!(
             "CsvExec: {}; partitions={}",
@@ -230,12 +546,31 @@ pub fn format_plan(plan: &dyn ExecutionPlan, indent: usize) -> Result<String> {
     } else if let Some(exec) = plan.as_any().downcast_ref::<FilterExec>() {
         format!("FilterExec: {}", format_expr(exec.predicate().as_ref()))
     } else if let Some(exec) = plan.as_any().downcast_ref::<QueryStageExec>() {
-        format!(
-            "QueryStageExec: job={}, stage={}",
-            exec.job_uuid, exec.stage_id
-        )
+        match &exec.shuffle_output_partitioning {
+            Some(Partitioning::Hash(exprs, partition_count)) => format!(
+                "QueryStageExec: job={}, stage={}, hash={:?}, partitions={}",
+                exec.job_uuid,
+                exec.stage_id,
+                exprs
+                    .iter()
+                    .map(|e| format_expr(e.as_ref()))
+                    .collect::<Vec<String>>(),
+                partition_count
+            ),
+            _ => format!(
+                "QueryStageExec: job={}, stage={}",
+                exec.job_uuid, exec.stage_id
+            ),
+        }
     } else if let Some(exec) = plan.as_any().downcast_ref::<UnresolvedShuffleExec>() {
-        format!("UnresolvedShuffleExec: stages={:?}", exec.query_stage_ids)
+        if exec.hash_partitioned() {
+            format!(
+                "UnresolvedShuffleExec: stages={:?}, hash_partitioned",
+                exec.query_stage_ids
+            )
+        } else {
+            format!("UnresolvedShuffleExec: stages={:?}", exec.query_stage_ids)
+        }
     } else if let Some(exec) = plan.as_any().downcast_ref::<CoalesceBatchesExec>() {
         format!(
             "CoalesceBatchesExec: batchSize={}",
@@ -285,3 +620,39 @@ pub fn format_expr(expr: &dyn PhysicalExpr) -> String {
         format!("{}", expr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::StringArray;
+    use datafusion::physical_plan::memory::MemoryExec;
+
+    #[tokio::test]
+    async fn write_compressed_stream_stats() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("name", DataType::Utf8, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(vec![
+                "repeated string value";
+                1000
+            ]))],
+        )?;
+        let exec = MemoryExec::try_new(&[vec![batch.clone(), batch]], schema, None)?;
+        let dir = tempfile::TempDir::new()?;
+        for compression in &[ShuffleCompression::None, ShuffleCompression::Zstd] {
+            let path = dir.path().join(format!("{}.arrow", compression));
+            let mut stream = exec.execute(0).await?;
+            let stats =
+                write_stream_to_disk(&mut stream, path.to_str().unwrap(), *compression).await?;
+            assert_eq!(2000, stats.num_rows());
+            assert_eq!(2, stats.num_batches());
+            assert_eq!(std::fs::metadata(&path)?.len(), stats.compressed_bytes());
+            if *compression == ShuffleCompression::None {
+                assert_eq!(1.0, stats.compression_ratio());
+            } else {
+                assert!(stats.compression_ratio() > 1.0);
+            }
+        }
+        Ok(())
+    }
+}